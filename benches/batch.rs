@@ -0,0 +1,89 @@
+/*
+ * The throughput of gshare and the local predictor over 10M events,
+ * through predict_and_update_batch and through predict_and_update one
+ * event at a time.  The stream is many sites, some loops and the rest
+ * biased, over tables of 16M counters, larger than the cache, where
+ * the batches' prefetching pays; build with `--features simd` for it.
+ * Each way gets a fresh predictor and the best of several rounds is
+ * reported.  The simulator itself still steps one event at a time, so
+ * this is what the batches would save it.
+ */
+
+use bp::predictor::{GshareBp, LocalBp, Predictor};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const EVENTS: usize = 10_000_000;
+const ROUNDS: usize = 5;
+// Events per predict_and_update_batch call
+const BATCH: usize = 4096;
+
+fn stream() -> Vec<(usize, bool)> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let sites: Vec<(usize, usize)> = (0..1 << 18)
+        .map(|_| (rng.gen_range(0..1 << 24) << 1, rng.gen_range(0..10)))
+        .collect();
+    let mut trips = vec![0usize; sites.len()];
+    (0..EVENTS)
+        .map(|_| {
+            let site = rng.gen_range(0..sites.len());
+            let (addr, kind) = sites[site];
+            let was_taken = if kind < 3 {
+                trips[site] += 1;
+                !trips[site].is_multiple_of(kind + 3)
+            } else {
+                rng.gen_bool(0.1 * kind as f64)
+            };
+            (addr, was_taken)
+        })
+        .collect()
+}
+
+fn best(mut round: impl FnMut() -> usize) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(round());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn compare(name: &str, build: impl Fn() -> Box<dyn Predictor>, events: &[(usize, bool)]) {
+    let single = best(|| {
+        let mut p = build();
+        events
+            .iter()
+            .filter(|&&(addr, was_taken)| p.predict_and_update(addr, was_taken))
+            .count()
+    });
+    let batched = best(|| {
+        let mut p = build();
+        events
+            .chunks(BATCH)
+            .map(|batch| {
+                p.predict_and_update_batch(batch)
+                    .iter()
+                    .filter(|&&t| t)
+                    .count()
+            })
+            .sum()
+    });
+    let rate = |d: Duration| EVENTS as f64 / d.as_secs_f64() / 1e6;
+    println!(
+        "{:<10} single {:7.1} Mpredictions/s  batched {:7.1} Mpredictions/s  {:.2}x",
+        name,
+        rate(single),
+        rate(batched),
+        single.as_secs_f64() / batched.as_secs_f64()
+    );
+}
+
+fn main() {
+    let events = stream();
+    compare("gshare:24", || Box::new(GshareBp::new(24)), &events);
+    compare("local:24", || Box::new(LocalBp::new(24)), &events);
+}
@@ -0,0 +1,146 @@
+use crate::json::{self, Value};
+use crate::weighted_bool::{Boolish, TwoBitCounter};
+use std::collections::HashMap;
+
+/*
+ * A bias profile is how often each branch was taken on a reference
+ * trace, for seeding the address indexed counters of the predictors so
+ * a run starts out closer to steady state, see --init-from-profile.
+ * It's saved as
+ *
+ *   {"branches": [{"pc": "0x4005d0", "taken": 930, "count": 1000}, ..]}
+ *
+ * with the PC in hex as high addresses don't fit a JSON number.
+ */
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BiasProfile {
+    // (pc, taken, count), by pc
+    branches: Vec<(usize, usize, usize)>,
+}
+
+impl BiasProfile {
+    /// From per branch [not taken, taken] counts
+    pub fn from_directions(directions: &HashMap<usize, [usize; 2]>) -> BiasProfile {
+        let mut branches: Vec<_> = directions
+            .iter()
+            .map(|(&pc, &[not_taken, taken])| (pc, taken, not_taken + taken))
+            .collect();
+        branches.sort_unstable();
+        BiasProfile { branches }
+    }
+
+    pub fn len(&self) -> usize {
+        self.branches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.branches.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let branches = self
+            .branches
+            .iter()
+            .map(|&(pc, taken, count)| {
+                Value::Object(vec![
+                    ("pc".to_string(), format!("{:#x}", pc).as_str().into()),
+                    ("taken".to_string(), taken.into()),
+                    ("count".to_string(), count.into()),
+                ])
+            })
+            .collect();
+        Value::Object(vec![("branches".to_string(), Value::Array(branches))])
+    }
+
+    pub fn from_json(value: &Value) -> Result<BiasProfile, String> {
+        let mut directions = HashMap::new();
+        for b in value
+            .get("branches")
+            .and_then(Value::as_array)
+            .ok_or("missing branches")?
+        {
+            let pc = b
+                .get("pc")
+                .and_then(Value::as_str)
+                .and_then(|pc| pc.strip_prefix("0x"))
+                .and_then(|pc| usize::from_str_radix(pc, 16).ok())
+                .ok_or("missing or bad pc")?;
+            let field = |key: &str| {
+                b.get(key)
+                    .and_then(Value::as_usize)
+                    .ok_or_else(|| format!("missing or bad {}", key))
+            };
+            let (taken, count) = (field("taken")?, field("count")?);
+            if taken > count {
+                return Err(format!("{:#x} is taken more often than seen", pc));
+            }
+            let counts: &mut [usize; 2] = directions.entry(pc).or_default();
+            counts[0] += count - taken;
+            counts[1] += taken;
+        }
+        Ok(BiasProfile::from_directions(&directions))
+    }
+
+    pub fn read(file_name: &str) -> Result<BiasProfile, String> {
+        let text = std::fs::read_to_string(file_name).map_err(|e| e.to_string())?;
+        BiasProfile::from_json(&json::parse(&text)?)
+    }
+
+    pub fn write(&self, file_name: &str) -> std::io::Result<()> {
+        std::fs::write(file_name, format!("{}\n", self.to_json()))
+    }
+
+    /// Sets each counter of `table` that branches map to by `index` to
+    /// the state nearest their combined bias, leaving the others be
+    pub fn seed(&self, table: &mut [TwoBitCounter], index: impl Fn(usize) -> usize) {
+        let mut entries: HashMap<usize, (usize, usize)> = HashMap::new();
+        for &(pc, taken, count) in &self.branches {
+            let entry = entries.entry(index(pc)).or_default();
+            entry.0 += taken;
+            entry.1 += count;
+        }
+        for (i, (taken, count)) in entries {
+            if count == 0 {
+                continue;
+            }
+            let bias = taken as f64 / count as f64;
+            table[i] = if (0.25..0.75).contains(&bias) {
+                TwoBitCounter::new(bias >= 0.5)
+            } else {
+                TwoBitCounter::strong(bias >= 0.5)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let directions = HashMap::from([(0x4005d0, [70, 930]), (0xffff_8000_0000_1000, [5, 0])]);
+        let profile = BiasProfile::from_directions(&directions);
+        let text = profile.to_json().to_string();
+        assert!(text.contains("\"0xffff800000001000\""));
+        let value = json::parse(&text).unwrap();
+        assert_eq!(BiasProfile::from_json(&value), Ok(profile));
+    }
+
+    #[test]
+    fn seeds_nearest_state() {
+        let directions = HashMap::from([
+            (0, [1, 9]),
+            (2, [6, 4]),
+            (4, [9, 1]),
+            // Aliases 0 in a 4 entry table, pulling it to weakly taken
+            (8, [8, 2]),
+        ]);
+        let profile = BiasProfile::from_directions(&directions);
+        let mut table = vec![TwoBitCounter::new(true); 4];
+        profile.seed(&mut table, |pc| (pc >> 1) & 3);
+        let states: Vec<u8> = table.iter().map(|c| c.state()).collect();
+        assert_eq!(states, [2, 1, 0, 2]);
+    }
+}
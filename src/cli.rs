@@ -0,0 +1,4335 @@
+use crate::bias::BiasProfile;
+use crate::event::*;
+use crate::features::FeatureWriter;
+use crate::history::HistoryPolicy;
+use crate::predictor::*;
+use crate::profile::Profile;
+use crate::synthetic::Pattern;
+use crate::weighted_bool::{Boolish, TwoBitCounter};
+use crate::wrappers::{DelayedBp, DumpPredictions, NotTakenFilter, Stability, TrackPc, WriteError};
+use crate::{json, registry, verify};
+use clap::{App, Arg, ArgMatches};
+use format_num::format_num;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::process::Command;
+use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+};
+
+/*
+ * The least miss rate any predictor can have knowing only the PC and
+ * the recent outcomes.  The conditional entropy H of the outcome given
+ * that context bounds the error rate p from below by Fano's inequality,
+ * H <= H_b(p) for binary outcomes, so the bound is H_b's inverse on
+ * [0, 1/2].  As the contexts are counted over the whole trace, it's
+ * the bound for what the trace shows, not for the program.
+ */
+fn entropy_bound(contexts: &HashMap<(usize, usize), [usize; 2]>) -> f64 {
+    let binary_entropy = |p: f64| {
+        if p <= 0.0 || p >= 1.0 {
+            0.0
+        } else {
+            -p * p.log2() - (1.0 - p) * (1.0 - p).log2()
+        }
+    };
+
+    let total: usize = contexts.values().map(|[nt, t]| nt + t).sum();
+    let entropy: f64 = contexts
+        .values()
+        .map(|&[nt, t]| {
+            let n = (nt + t) as f64;
+            n / total.max(1) as f64 * binary_entropy(t as f64 / n)
+        })
+        .sum();
+
+    // H_b increases on [0, 1/2] so bisect
+    let (mut lo, mut hi) = (0.0, 0.5);
+    for _ in 0..64 {
+        let mid = (lo + hi) / 2.0;
+        if binary_entropy(mid) < entropy {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/*
+ * Ctrl-C during a run stops it where it is and reports what it got,
+ * rather than losing a long simulation.  The handler only sets a flag,
+ * polled by simulate, and restores the default so a second Ctrl-C
+ * kills as usual.
+ */
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Events between polls of INTERRUPTED
+const INTERRUPT_POLL: usize = 1 << 16;
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+fn catch_interrupts() {
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as *const () as libc::sighandler_t,
+        );
+    }
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Exits with 130, as killed by SIGINT, once interrupted
+fn exit_if_interrupted() {
+    if interrupted() {
+        std::process::exit(130);
+    }
+}
+
+/// Misses per thousand instructions
+fn mpki(misses: usize, instret: usize) -> f64 {
+    1000.0 * misses as f64 / instret as f64
+}
+
+/// Run wide counts; all but `events` are weighted
+#[derive(Default)]
+struct Totals {
+    events: usize,
+    count: usize,
+    instret: usize,
+    taken: usize,
+    delta: usize,
+    // Events whose outcome differs from the branch's previous one
+    transitions: usize,
+    // Events skipped by --collapse-repeats, not counted in the others
+    collapsed: usize,
+    // Events outside --pc-range, learnt from but not counted in the others
+    outside: usize,
+    // Whether --stop-when-stable cut the run short
+    stopped_early: bool,
+    // Whether Ctrl-C did
+    interrupted: bool,
+    // Events in the RESIZE_WINDOW before and after --resize-at
+    resize_count: [usize; 2],
+    // Per branch [not taken, taken] counts, only kept for --stats
+    directions: HashMap<usize, [usize; 2]>,
+    // Per (branch, recent outcomes) [not taken, taken] counts, only
+    // kept for --entropy-bound
+    contexts: HashMap<(usize, usize), [usize; 2]>,
+    // A counter of each branch's own and their misses, the 2bc-limit
+    // of --two-bit-limit
+    two_bit_limit: Option<(HashMap<usize, TwoBitCounter>, usize)>,
+}
+
+impl Totals {
+    /// Adds the totals of another shard of the trace
+    fn merge(&mut self, other: &Totals) {
+        self.events += other.events;
+        self.count += other.count;
+        self.instret += other.instret;
+        self.taken += other.taken;
+        self.delta += other.delta;
+        self.transitions += other.transitions;
+        self.collapsed += other.collapsed;
+        self.outside += other.outside;
+        self.stopped_early |= other.stopped_early;
+        self.interrupted |= other.interrupted;
+        // The shards' counters are cold for each other's branches
+        if let (Some((_, misses)), Some((_, other))) =
+            (&mut self.two_bit_limit, &other.two_bit_limit)
+        {
+            *misses += other;
+        }
+        for side in 0..2 {
+            self.resize_count[side] += other.resize_count[side];
+        }
+        for (&addr, counts) in &other.directions {
+            let merged = self.directions.entry(addr).or_default();
+            merged[0] += counts[0];
+            merged[1] += counts[1];
+        }
+        for (&context, counts) in &other.contexts {
+            let merged = self.contexts.entry(context).or_default();
+            merged[0] += counts[0];
+            merged[1] += counts[1];
+        }
+    }
+}
+
+// Events either side of --resize-at that the miss rate is compared over
+const RESIZE_WINDOW: usize = 10_000;
+
+// Events between the checks of --stop-when-stable
+const STABLE_WINDOW: usize = 100_000;
+
+// Events --synthetic generates
+const SYNTHETIC_EVENTS: usize = 1_000_000;
+
+/// Per predictor counts; misses are weighted
+#[derive(Clone, Default)]
+struct Counts {
+    misses: usize,
+    // Misses predicted taken, the rest were predicted not taken
+    false_taken: usize,
+    transition_misses: usize,
+    // Only graded with --gating
+    unconfident: usize,
+    confident_misses: usize,
+    // Misses in the RESIZE_WINDOW before and after --resize-at
+    resize_misses: [usize; 2],
+    // -log2 of the odds given the outcome, only summed with --log-loss
+    log_loss: f64,
+    // The MPKI of each full window of --window-percentiles events
+    window_mpkis: Vec<f64>,
+    // Only measured with --timing
+    time: Duration,
+    ports: Option<PortStats>,
+    // (flips, repeats) of Stability, only counted with --flip-rate
+    stability: Option<(usize, usize)>,
+    // Of the per-branch history twin, only run with --fetch-width
+    per_branch_misses: Option<usize>,
+    // Per branch misses while it has only been taken, only kept for
+    // --stats; see always_taken_misses
+    taken_only_misses: HashMap<usize, usize>,
+}
+
+impl Counts {
+    /// Adds the counts of the same predictor run on another shard; what
+    /// only one of them measured is dropped
+    fn merge(&mut self, other: &Counts) {
+        self.misses += other.misses;
+        self.false_taken += other.false_taken;
+        self.transition_misses += other.transition_misses;
+        self.unconfident += other.unconfident;
+        self.confident_misses += other.confident_misses;
+        for side in 0..2 {
+            self.resize_misses[side] += other.resize_misses[side];
+        }
+        self.log_loss += other.log_loss;
+        self.window_mpkis.extend(&other.window_mpkis);
+        self.time += other.time;
+        self.ports = match (self.ports, other.ports) {
+            (Some(a), Some(b)) => Some(PortStats {
+                events: a.events + b.events,
+                reads: a.reads + b.reads,
+                writes: a.writes + b.writes,
+                stalls: a.stalls + b.stalls,
+                ..a
+            }),
+            _ => None,
+        };
+        self.stability = match (self.stability, other.stability) {
+            (Some(a), Some(b)) => Some((a.0 + b.0, a.1 + b.1)),
+            _ => None,
+        };
+        self.per_branch_misses = match (self.per_branch_misses, other.per_branch_misses) {
+            (Some(a), Some(b)) => Some(a + b),
+            _ => None,
+        };
+        for (&addr, &misses) in &other.taken_only_misses {
+            *self.taken_only_misses.entry(addr).or_default() += misses;
+        }
+    }
+}
+
+/// The smallest of `values` with at least `fraction` of them at or
+/// below it, 0 for none
+fn quantile(values: &[f64], fraction: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied().unwrap_or(0.0)
+}
+
+/// The predictions by [predicted][actual] direction, 1 being taken
+fn confusion(counts: &Counts, totals: &Totals) -> [[usize; 2]; 2] {
+    let false_not_taken = counts.misses - counts.false_taken;
+    [
+        [
+            totals.count - totals.taken - counts.false_taken,
+            false_not_taken,
+        ],
+        [counts.false_taken, totals.taken - false_not_taken],
+    ]
+}
+
+/// A row of the results: (name, config, size in bits, counts)
+type Row = (String, Vec<usize>, usize, Counts);
+
+/// Where results came from, for telling plots apart
+#[derive(Clone, Debug, PartialEq)]
+struct Provenance {
+    trace: String,
+    // UTC, YYYY-MM-DD
+    date: String,
+}
+
+impl Provenance {
+    fn now(trace: &str) -> Provenance {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Provenance {
+            trace: trace.to_string(),
+            date: utc_date(secs),
+        }
+    }
+}
+
+/// The UTC date of a Unix time, by Howard Hinnant's days_from_civil
+/// algorithm run backwards
+fn utc_date(secs: u64) -> String {
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The plot title, as a gnuplot single quoted string
+fn plot_title(label: Option<&str>, provenance: &Provenance, events: usize) -> String {
+    let title = format!(
+        "{}{}, {} events, {}",
+        label.map_or(String::new(), |l| format!("{}: ", l)),
+        provenance.trace,
+        format_num!(",.0", events as f64),
+        provenance.date
+    );
+    format!("'{}'", title.replace('\'', "''"))
+}
+
+/// The results grouped by algorithm, in order of first appearance,
+/// each family by ascending size
+fn families(results: &[Row]) -> Vec<(&str, Vec<&Row>)> {
+    let mut families: Vec<(&str, Vec<&Row>)> = vec![];
+    for row in results {
+        match families.iter_mut().find(|(alg, _)| *alg == row.0) {
+            Some((_, rows)) => rows.push(row),
+            None => families.push((&row.0, vec![row])),
+        }
+    }
+    for (_, rows) in families.iter_mut() {
+        rows.sort_by_key(|r| r.2);
+    }
+    families
+}
+
+/// Within each family, the configs that miss more than a smaller one,
+/// as (smaller, larger) with the smaller the one missing least.  More
+/// storage rarely hurts, so these tend to be bugs or bad hashes.
+fn size_anomalies(results: &[Row]) -> Vec<(&Row, &Row)> {
+    let mut anomalies = vec![];
+    for (_, rows) in families(results) {
+        for (i, &row) in rows.iter().enumerate() {
+            let smaller = rows[..i]
+                .iter()
+                .filter(|r| r.2 < row.2)
+                .min_by_key(|r| r.3.misses);
+            if let Some(&smaller) = smaller.filter(|s| row.3.misses > s.3.misses) {
+                anomalies.push((smaller, row));
+            }
+        }
+    }
+    anomalies
+}
+
+/// The results no other is both as small and as accurate as, by size
+/// then misses.  Ties are all kept, as neither beats the other.
+fn pareto_frontier(results: &[Row]) -> Vec<&Row> {
+    let mut frontier: Vec<&Row> = results
+        .iter()
+        .filter(|r| {
+            !results.iter().any(|q| {
+                q.2 <= r.2 && q.3.misses <= r.3.misses && (q.2 < r.2 || q.3.misses < r.3.misses)
+            })
+        })
+        .collect();
+    frontier.sort_by_key(|r| (r.2, r.3.misses));
+    frontier
+}
+
+/// Writes the Pareto frontier of size versus MPKI as CSV, for design
+/// space exploration tools that only want the best points
+fn write_pareto(results: &[Row], instret: usize, file_name: &str) -> std::io::Result<()> {
+    // The names and configs have commas of their own
+    let quoted = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+    let mut out = BufWriter::new(File::create(file_name)?);
+    writeln!(out, "algorithm,config,size_bits,mpki")?;
+    for (alg, config, size, counts) in pareto_frontier(results) {
+        let config: Vec<String> = config.iter().map(|c| c.to_string()).collect();
+        writeln!(
+            out,
+            "{},{},{},{}",
+            quoted(alg),
+            quoted(&config.join(",")),
+            size,
+            mpki(counts.misses, instret)
+        )?;
+    }
+    out.flush()
+}
+
+const PALETTE: &[&str] = &[
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf",
+];
+
+/// The color and gnuplot point type of an algorithm's series, the same
+/// in every run: the color is that of its name without the options, so
+/// eg. YAGS3[alloc=50%] is drawn like YAGS3 but for its points
+fn series_style(alg: &str) -> (&'static str, usize) {
+    // FNV-1a, as the std hashers aren't promised to stay the same
+    let fnv = |s: &str| {
+        s.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100_0000_01b3)
+        })
+    };
+    let base = alg.split('[').next().unwrap_or(alg);
+    let color = PALETTE[(fnv(base) % PALETTE.len() as u64) as usize];
+    (color, 1 + (fnv(alg) % 12) as usize)
+}
+
+/// The data file of an algorithm's series, eg. bp.yags3.dat
+fn series_file(alg: &str) -> String {
+    let slug: String = alg
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("bp.{}.dat", slug)
+}
+
+/*
+ * Besides bp.dat of every point, each algorithm's points go to their
+ * own file in size order, so plot.gp can draw a line per algorithm
+ * over a sweep with
+ *
+ *   plot for [f in series] f using 1:2 with linespoints title f
+ *
+ * Their third column is the config, for labelling the points.  The
+ * colors and points words, in the same order, style each series as
+ * series_style does, for
+ *
+ *   plot for [i=1:words(series)] word(series, i) using 1:2 \
+ *       with linespoints lc rgb word(colors, i) pt int(word(points, i))
+ */
+fn write_series(
+    results: &[Row],
+    instret: usize,
+    label: Option<&str>,
+) -> Result<Vec<String>, std::io::Error> {
+    let mut files = vec![];
+    for (alg, rows) in families(results) {
+        let file_name = series_file(alg);
+        let mut data = File::create(&file_name)?;
+        if let Some(label) = label {
+            writeln!(&mut data, "# label: {}", label)?;
+        }
+        writeln!(&mut data, "# {}", alg)?;
+        for (_, config, size, counts) in rows {
+            let config: Vec<String> = config.iter().map(|c| c.to_string()).collect();
+            writeln!(
+                &mut data,
+                "{}\t{}\t\"{}\"",
+                *size as f64 / 8192.0,
+                mpki(counts.misses, instret),
+                config.join(",")
+            )?;
+        }
+        files.push(file_name);
+    }
+    Ok(files)
+}
+
+fn report(
+    mut results: Vec<Row>,
+    lanes: usize,
+    elapsed: Duration,
+    totals: &Totals,
+    provenance: &Provenance,
+    opts: &Options,
+) -> Result<(), std::io::Error> {
+    let Totals {
+        events,
+        count,
+        instret,
+        ..
+    } = *totals;
+
+    println!(
+        "Processed {} branch events ({} predictions) in {:.2} s = {:.3} Mpredictions/s",
+        format_num!(",.0", events as f64),
+        format_num!(",.0", (events * lanes) as f64),
+        elapsed.as_secs_f64(),
+        events as f64 * lanes as f64 / (1000000.0 * elapsed.as_secs_f64())
+    );
+    if opts.instret_policy != InstretPolicy::default() || opts.delay_slots > 0 {
+        let delay_slots = match opts.delay_slots {
+            0 => String::new(),
+            n => format!(", plus {} delay slots", n),
+        };
+        println!(
+            "MPKI over instructions counted as {}{}",
+            opts.instret_policy, delay_slots
+        );
+    }
+    if totals.collapsed > 0 {
+        println!(
+            "Collapsed {} immediately repeated branch events",
+            format_num!(",.0", totals.collapsed as f64)
+        );
+    }
+    if totals.interrupted {
+        println!("Interrupted, so these are partial results");
+    }
+    if totals.stopped_early {
+        let (tolerance, windows) = opts.stop_when_stable.unwrap();
+        println!(
+            "Stopped early, all miss rates having held within {}% for {} windows of {} events",
+            tolerance,
+            windows,
+            format_num!(",.0", STABLE_WINDOW as f64)
+        );
+    }
+    if let Some((start, end)) = opts.pc_range {
+        println!(
+            "Counted {} of {} branch events, those in {:#x}..{:#x}",
+            format_num!(",.0", totals.count as f64),
+            format_num!(",.0", (totals.count + totals.outside) as f64),
+            start,
+            end
+        );
+    }
+
+    // NoneTaken misses exactly the taken branches, so needn't be run
+    let baseline = match &opts.compare_baseline {
+        Some(None) => Some(("NoneTaken".to_string(), totals.taken)),
+        Some(Some(spec)) => match results.iter().find(|r| matches_spec(&r.0, &r.1, spec)) {
+            Some((alg, _, _, counts)) => Some((alg.clone(), counts.misses)),
+            None => {
+                eprintln!("--compare-baseline: no predictor matches {}", spec);
+                None
+            }
+        },
+        None => None,
+    };
+
+    if opts.replot.is_none() {
+        write_results(
+            "bp.results.json",
+            &results,
+            lanes,
+            elapsed,
+            totals,
+            provenance,
+            opts,
+        )?;
+    }
+
+    if opts.compare {
+        match &results[..] {
+            [a, b] => print_comparison(a, b, totals, opts),
+            _ => eprintln!(
+                "--compare needs exactly two predictors, not {}",
+                results.len()
+            ),
+        }
+        return Ok(());
+    }
+
+    results.sort_by_key(|r| std::cmp::Reverse(r.3.misses));
+
+    {
+        let mut data = File::create("bp.dat")?;
+        if let Some(label) = &opts.label {
+            println!("# label: {}", label);
+            writeln!(&mut data, "# label: {}", label)?;
+        }
+
+        for (alg, config, size, counts) in &results {
+            let (size, misses) = (*size, counts.misses);
+            let miss_rate = misses as f64 / count as f64;
+            let mpki = mpki(misses, instret);
+            let hit_rate = 100.0 - 100.0 * miss_rate;
+
+            let vs_baseline = match &baseline {
+                Some((name, base)) => format!(
+                    "{:6.1}% vs {} ",
+                    100.0 * (*base as f64 - misses as f64) / *base as f64,
+                    name
+                ),
+                None => String::new(),
+            };
+
+            let timing = if opts.timing {
+                format!(
+                    "{:6.1} ns/pred ",
+                    counts.time.as_nanos() as f64 / events as f64
+                )
+            } else {
+                String::new()
+            };
+
+            let precision = opts.precision;
+            let windows = if opts.window_percentiles.is_some() {
+                let at = |fraction| quantile(&counts.window_mpkis, fraction);
+                format!(
+                    "p50/90/99 {:.p$}/{:.p$}/{:.p$} ",
+                    at(0.5),
+                    at(0.9),
+                    at(0.99),
+                    p = precision
+                )
+            } else {
+                String::new()
+            };
+
+            println!(
+                "{:w$.p$} mpki ({:hw$.p$}%) {} {}{}{}{} {:?}",
+                mpki,
+                hit_rate,
+                opts.size_unit.format(size),
+                timing,
+                windows,
+                vs_baseline,
+                alg,
+                config,
+                w = precision + 4,
+                hw = precision + 3,
+                p = precision
+            );
+
+            match &opts.label {
+                Some(label) => writeln!(
+                    &mut data,
+                    "{}\t{}\t\"{}\"",
+                    size as f64 / 8192.0,
+                    mpki,
+                    label
+                )?,
+                None => writeln!(&mut data, "{}\t{}", size as f64 / 8192.0, mpki)?,
+            }
+        }
+
+        // What counters can do without aliasing, see --two-bit-limit
+        if let Some((_, misses)) = totals.two_bit_limit {
+            let precision = opts.precision;
+            println!(
+                "{:w$.p$} mpki ({:hw$.p$}%) 2bc-limit",
+                mpki(misses, instret),
+                100.0 - 100.0 * misses as f64 / count as f64,
+                w = precision + 4,
+                hw = precision + 3,
+                p = precision
+            );
+        }
+    }
+
+    for (smaller, larger) in size_anomalies(&results) {
+        eprintln!(
+            "Warning: {} {:?} misses more than the smaller {:?} ({:.3} vs {:.3} mpki)",
+            larger.0,
+            larger.1,
+            smaller.1,
+            mpki(larger.3.misses, instret),
+            mpki(smaller.3.misses, instret)
+        );
+    }
+
+    if !opts.size_brackets.is_empty() {
+        println!();
+        println!("Best within each size budget:");
+        for (budget, best) in best_per_bracket(&results, &opts.size_brackets) {
+            match best {
+                Some((alg, config, _, counts)) => println!(
+                    "<= {}: {:w$.p$} mpki {} {:?}",
+                    opts.size_unit.format(budget),
+                    mpki(counts.misses, instret),
+                    alg,
+                    config,
+                    w = opts.precision + 4,
+                    p = opts.precision
+                ),
+                None => println!("<= {}: none fits", opts.size_unit.format(budget)),
+            }
+        }
+    }
+
+    if opts.transitions_only {
+        println!();
+        println!(
+            "On direction changes only ({} of {} events):",
+            format_num!(",.0", totals.transitions as f64),
+            format_num!(",.0", count as f64)
+        );
+        for (alg, config, _, counts) in &results {
+            println!(
+                "{:5.1}% hit rate {} {:?}",
+                100.0 - 100.0 * counts.transition_misses as f64 / totals.transitions.max(1) as f64,
+                alg,
+                config
+            );
+        }
+    }
+
+    if let Some((at, bits)) = opts.resize_at {
+        println!();
+        println!(
+            "Miss rate {} events either side of resizing to {} bits at event {}:",
+            RESIZE_WINDOW, bits, at
+        );
+        for (alg, config, _, counts) in &results {
+            let [before, after] = counts.resize_misses;
+            println!(
+                "{:5.1}% -> {:5.1}% {} {:?}",
+                100.0 * before as f64 / totals.resize_count[0].max(1) as f64,
+                100.0 * after as f64 / totals.resize_count[1].max(1) as f64,
+                alg,
+                config
+            );
+        }
+    }
+
+    if opts.gating {
+        println!();
+        println!("Gating fetch on low confidence predictions:");
+        for (alg, config, _, counts) in &results {
+            let confident = count - counts.unconfident;
+            println!(
+                "{:5.1}% gated, {:5.1}% of the rest correct ({} right, {} wrong, {} gated) {} {:?}",
+                100.0 * counts.unconfident as f64 / count as f64,
+                100.0 - 100.0 * counts.confident_misses as f64 / confident.max(1) as f64,
+                confident - counts.confident_misses,
+                counts.confident_misses,
+                counts.unconfident,
+                alg,
+                config
+            );
+        }
+    }
+
+    if opts.confusion {
+        println!();
+        println!("Predicted (first) versus actual direction, and precision and recall of taken:");
+        println!(
+            "{:>12} {:>12} {:>12} {:>12} {:>9} {:>6}",
+            "TT", "TN", "NT", "NN", "precision", "recall"
+        );
+        for (alg, config, _, counts) in &results {
+            let [[nn, nt], [tn, tt]] = confusion(counts, totals);
+            println!(
+                "{:>12} {:>12} {:>12} {:>12} {:8.1}% {:5.1}% {} {:?}",
+                tt,
+                tn,
+                nt,
+                nn,
+                100.0 * tt as f64 / (tt + tn).max(1) as f64,
+                100.0 * tt as f64 / (tt + nt).max(1) as f64,
+                alg,
+                config
+            );
+        }
+    }
+
+    if opts.log_loss {
+        println!();
+        println!("Log-loss of the odds given the outcome, in bits per branch:");
+        for (alg, config, _, counts) in &results {
+            println!(
+                "{:9.4} {} {:?}",
+                counts.log_loss / totals.count.max(1) as f64,
+                alg,
+                config
+            );
+        }
+    }
+
+    if results.iter().any(|r| r.3.stability.is_some()) {
+        println!();
+        println!("Flip rate, how often a branch's prediction changed since its last occurrence:");
+        for (alg, config, _, counts) in &results {
+            if let Some((flips, repeats)) = counts.stability {
+                println!(
+                    "{:5.1}% {} {:?}",
+                    100.0 * flips as f64 / repeats.max(1) as f64,
+                    alg,
+                    config
+                );
+            }
+        }
+    }
+
+    if results.iter().any(|r| r.3.per_branch_misses.is_some()) {
+        println!();
+        println!(
+            "Fetching {} branches at a time versus per-branch history updates:",
+            opts.fetch_width
+        );
+        for (alg, config, _, counts) in &results {
+            if let Some(per_branch) = counts.per_branch_misses {
+                let (before, after) = (mpki(per_branch, instret), mpki(counts.misses, instret));
+                println!(
+                    "{:8.3} -> {:8.3} mpki ({:+.1}%) {} {:?}",
+                    before,
+                    after,
+                    100.0 * (after - before) / before.max(f64::MIN_POSITIVE),
+                    alg,
+                    config
+                );
+            }
+        }
+    }
+
+    if opts.stats {
+        /*
+         * Each misprediction fetches down the wrong path until it
+         * resolves; approximate that by the mean distance between
+         * branches, which is all the trace tells us.
+         */
+        let mean_delta = totals.delta as f64 / count as f64;
+        println!();
+        println!(
+            "Estimated wrong-path instructions (misses x mean delta {:.2}):",
+            mean_delta
+        );
+        for (alg, config, _, counts) in &results {
+            let wrong_path = counts.misses as f64 * mean_delta;
+            println!(
+                "{:>16} ({:5.1}% of instret) {} {:?}",
+                format_num!(",.0", wrong_path),
+                100.0 * wrong_path / instret as f64,
+                alg,
+                config
+            );
+        }
+
+        if results.iter().any(|r| r.3.ports.is_some()) {
+            println!();
+            println!("SRAM accesses per event:");
+            for (alg, config, _, counts) in &results {
+                if let Some(ports) = counts.ports {
+                    let events = ports.events.max(1) as f64;
+                    println!(
+                        "{:5.2} reads {:5.2} writes, {:5.1}% stalls with {} write ports {} {:?}",
+                        ports.reads as f64 / events,
+                        ports.writes as f64 / events,
+                        100.0 * ports.stalls as f64 / events,
+                        ports.write_ports,
+                        alg,
+                        config
+                    );
+                }
+            }
+        }
+
+        println!();
+        println!("Branch directions:");
+        let mut classes = [(0, 0); 3];
+        for &[not_taken, taken] in totals.directions.values() {
+            let class = match (not_taken, taken) {
+                (_, 0) => 0,
+                (0, _) => 1,
+                _ => 2,
+            };
+            classes[class].0 += 1;
+            classes[class].1 += not_taken + taken;
+        }
+        for (name, (branches, events)) in ["always not taken", "always taken", "mixed"]
+            .iter()
+            .zip(classes)
+        {
+            println!(
+                "{:>16} {:>8} branches ({:5.1}% of events)",
+                name,
+                branches,
+                100.0 * events as f64 / count as f64
+            );
+        }
+
+        let easy = classes[1].1;
+        println!();
+        println!(
+            "Always taken branches, likely unconditional ({:.1}% of events):",
+            100.0 * easy as f64 / count as f64
+        );
+        for (alg, config, _, counts) in &results {
+            let easy_hits = easy - always_taken_misses(&totals.directions, counts);
+            let hits = count - counts.misses;
+            println!(
+                "{:5.1}% of the correct predictions, {:5.1}% correct of the rest {} {:?}",
+                100.0 * easy_hits as f64 / hits.max(1) as f64,
+                100.0 * (hits - easy_hits) as f64 / (count - easy).max(1) as f64,
+                alg,
+                config
+            );
+        }
+    }
+
+    if let (Some(bits), false) = (opts.entropy_bound, totals.contexts.is_empty()) {
+        let min_misses = entropy_bound(&totals.contexts) * count as f64;
+        println!();
+        println!(
+            "Efficiency against the entropy bound for the PC and {} outcomes of history ({:.1} mpki):",
+            bits,
+            1000.0 * min_misses / instret as f64
+        );
+        for (alg, config, _, counts) in &results {
+            println!(
+                "{:5.1}% {} {:?}",
+                100.0 * min_misses / counts.misses.max(1) as f64,
+                alg,
+                config
+            );
+        }
+    }
+
+    let series = write_series(&results, instret, opts.label.as_deref())?;
+    write_pareto(&results, instret, "bp.pareto.csv")?;
+
+    // plot.gp can `set title title` and plot each of the series
+    let title = plot_title(opts.label.as_deref(), provenance, events);
+    let (colors, points): (Vec<&str>, Vec<String>) = families(&results)
+        .into_iter()
+        .map(|(alg, _)| {
+            let (color, point) = series_style(alg);
+            (color, point.to_string())
+        })
+        .unzip();
+    let output = Command::new("gnuplot")
+        .args(["-e", &format!("title={}", title)])
+        .args(["-e", &format!("series='{}'", series.join(" "))])
+        .args(["-e", &format!("colors='{}'", colors.join(" "))])
+        .args(["-e", &format!("points='{}'", points.join(" "))])
+        .arg("plot.gp")
+        .output()
+        .expect("failed to launch gnuplot")
+        .stdout;
+
+    /* GNUplot it */
+    print!("{}", str::from_utf8(&output).expect("Bad UTF-8"));
+
+    Ok(())
+}
+
+/// Whether `spec` names the predictor, either by algorithm name alone
+/// or as `name:c1,c2,..` to also match the config.
+fn matches_spec(alg: &str, alg_config: &[usize], spec: &str) -> bool {
+    let (name, config) = match spec.split_once(':') {
+        Some((name, config)) => (name, Some(config)),
+        None => (spec, None),
+    };
+
+    alg.eq_ignore_ascii_case(name)
+        && config.is_none_or(|c| {
+            let alg_config: Vec<String> = alg_config.iter().map(|v| v.to_string()).collect();
+            c.replace(' ', "") == alg_config.join(",")
+        })
+}
+
+/// Finds the first predictor matching `spec`, see matches_spec
+fn find_predictor(predictors: &[Box<dyn Predictor>], spec: &str) -> Option<usize> {
+    predictors.iter().position(|p| {
+        let (alg, config, _) = p.report();
+        matches_spec(&alg, &config, spec)
+    })
+}
+
+/// The metrics --compare shows, (metric, A, B)
+fn comparison(a: &Row, b: &Row, totals: &Totals, opts: &Options) -> Vec<(&'static str, f64, f64)> {
+    let metric = |f: &dyn Fn(&Row) -> f64| (f(a), f(b));
+    let mut metrics = vec![
+        ("size KiB", metric(&|r| r.2 as f64 / 8192.0)),
+        ("misses", metric(&|r| r.3.misses as f64)),
+        ("mpki", metric(&|r| mpki(r.3.misses, totals.instret))),
+        (
+            "miss rate %",
+            metric(&|r| 100.0 * r.3.misses as f64 / totals.count as f64),
+        ),
+    ];
+    if opts.transitions_only {
+        metrics.push((
+            "transition misses",
+            metric(&|r| r.3.transition_misses as f64),
+        ));
+    }
+    if opts.gating {
+        metrics.push((
+            "gated %",
+            metric(&|r| 100.0 * r.3.unconfident as f64 / totals.count as f64),
+        ));
+    }
+    if opts.timing {
+        metrics.push((
+            "ns/pred",
+            metric(&|r| r.3.time.as_nanos() as f64 / totals.events as f64),
+        ));
+    }
+    metrics.into_iter().map(|(m, (a, b))| (m, a, b)).collect()
+}
+
+/// Prints the two predictors' metrics side by side with the change
+fn print_comparison(a: &Row, b: &Row, totals: &Totals, opts: &Options) {
+    println!("A: {} {:?}", a.0, a.1);
+    println!("B: {} {:?}", b.0, b.1);
+    println!("{:>20} {:>14} {:>14} {:>14}", "", "A", "B", "B - A");
+    let p = opts.precision;
+    for (metric, a, b) in comparison(a, b, totals, opts) {
+        let relative = if a != 0.0 {
+            format!(" ({:+.1}%)", 100.0 * (b - a) / a)
+        } else {
+            String::new()
+        };
+        println!(
+            "{:>20} {:14.p$} {:14.p$} {:+14.p$}{}",
+            metric,
+            a,
+            b,
+            b - a,
+            relative,
+            p = p
+        );
+    }
+}
+
+/// Saves what report needs to regenerate the table and plot, see --replot
+fn write_results(
+    file_name: &str,
+    results: &[Row],
+    lanes: usize,
+    elapsed: Duration,
+    totals: &Totals,
+    provenance: &Provenance,
+    opts: &Options,
+) -> Result<(), std::io::Error> {
+    use json::Value;
+
+    let rows = results
+        .iter()
+        .map(|(alg, config, size, counts)| {
+            Value::Object(vec![
+                ("name".to_string(), alg.as_str().into()),
+                (
+                    "config".to_string(),
+                    Value::Array(config.iter().map(|&c| c.into()).collect()),
+                ),
+                ("id".to_string(), predictor_id(alg, config).as_str().into()),
+                ("size".to_string(), (*size).into()),
+                ("misses".to_string(), counts.misses.into()),
+            ])
+        })
+        .collect();
+    let results = Value::Object(vec![
+        (
+            "label".to_string(),
+            opts.label.as_deref().map_or(Value::Null, Value::from),
+        ),
+        ("trace".to_string(), provenance.trace.as_str().into()),
+        ("date".to_string(), provenance.date.as_str().into()),
+        ("lanes".to_string(), lanes.into()),
+        ("elapsed".to_string(), Value::Number(elapsed.as_secs_f64())),
+        ("events".to_string(), totals.events.into()),
+        ("count".to_string(), totals.count.into()),
+        ("instret".to_string(), totals.instret.into()),
+        ("taken".to_string(), totals.taken.into()),
+        ("delta".to_string(), totals.delta.into()),
+        ("predictors".to_string(), Value::Array(rows)),
+    ]);
+
+    writeln!(File::create(file_name)?, "{}", results)
+}
+
+type SavedResults = (
+    Vec<Row>,
+    usize,
+    Duration,
+    Totals,
+    Provenance,
+    Option<String>,
+);
+
+/// Loads what write_results saved
+fn read_results(file_name: &str) -> Result<SavedResults, String> {
+    let text = std::fs::read_to_string(file_name).map_err(|e| e.to_string())?;
+    let results = json::parse(&text)?;
+    let field = |v: &json::Value, key: &str| {
+        v.get(key)
+            .and_then(json::Value::as_usize)
+            .ok_or_else(|| format!("missing or bad {}", key))
+    };
+
+    let totals = Totals {
+        events: field(&results, "events")?,
+        count: field(&results, "count")?,
+        instret: field(&results, "instret")?,
+        taken: field(&results, "taken")?,
+        delta: field(&results, "delta")?,
+        ..Totals::default()
+    };
+    let elapsed = results
+        .get("elapsed")
+        .and_then(json::Value::as_f64)
+        .ok_or("missing elapsed")?;
+    let label = results
+        .get("label")
+        .and_then(json::Value::as_str)
+        .map(String::from);
+    // Not saved before, so optional
+    let text_field = |key: &str| {
+        results
+            .get(key)
+            .and_then(json::Value::as_str)
+            .unwrap_or("unknown")
+            .to_string()
+    };
+    let provenance = Provenance {
+        trace: text_field("trace"),
+        date: text_field("date"),
+    };
+
+    let mut rows = vec![];
+    for p in results
+        .get("predictors")
+        .and_then(json::Value::as_array)
+        .ok_or("missing predictors")?
+    {
+        let name = p
+            .get("name")
+            .and_then(json::Value::as_str)
+            .ok_or("missing name")?;
+        let config = p
+            .get("config")
+            .and_then(json::Value::as_array)
+            .ok_or("missing config")?
+            .iter()
+            .map(|c| c.as_usize().ok_or("bad config"))
+            .collect::<Result<_, _>>()?;
+        let counts = Counts {
+            misses: field(p, "misses")?,
+            ..Counts::default()
+        };
+        rows.push((name.to_string(), config, field(p, "size")?, counts));
+    }
+
+    Ok((
+        rows,
+        field(&results, "lanes")?,
+        Duration::from_secs_f64(elapsed),
+        totals,
+        provenance,
+        label,
+    ))
+}
+
+/*
+ * Without branch types, a branch taken every time it's seen is most
+ * likely unconditional and flatters every predictor.  Which branches
+ * those are is only known at the end of the trace, so the misses are
+ * kept per branch for as long as it has only been taken and summed
+ * over those that stayed that way.
+ */
+fn always_taken_misses(directions: &HashMap<usize, [usize; 2]>, counts: &Counts) -> usize {
+    counts
+        .taken_only_misses
+        .iter()
+        .filter(|(addr, _)| directions[addr][0] == 0)
+        .map(|(_, misses)| misses)
+        .sum()
+}
+
+/// Adds the results of the same predictors run on another shard of a
+/// trace.  Only the counts add up: the predictors learnt each shard
+/// from cold, so the misses are those of the shards, not of one run.
+fn merge_results(
+    rows: &mut [Row],
+    totals: &mut Totals,
+    other_rows: &[Row],
+    other_totals: &Totals,
+) -> Result<(), String> {
+    if rows.len() != other_rows.len() {
+        return Err(format!(
+            "{} predictors against {}",
+            other_rows.len(),
+            rows.len()
+        ));
+    }
+    for (alg, config, _, counts) in rows.iter_mut() {
+        let id = predictor_id(alg, config);
+        let (_, _, _, other) = other_rows
+            .iter()
+            .find(|r| predictor_id(&r.0, &r.1) == id)
+            .ok_or_else(|| format!("no {}", id))?;
+        counts.merge(other);
+    }
+    totals.merge(other_totals);
+    Ok(())
+}
+
+/// The fewest misses among the predictors of at most each size in bits
+fn best_per_bracket<'a>(results: &'a [Row], budgets: &[usize]) -> Vec<(usize, Option<&'a Row>)> {
+    budgets
+        .iter()
+        .map(|&budget| {
+            let best = results
+                .iter()
+                .filter(|r| r.2 <= budget)
+                .min_by_key(|r| r.3.misses);
+            (budget, best)
+        })
+        .collect()
+}
+
+/// Writes the distribution of a predictor's main table counters over
+/// the four 2-bit states as `state\tentries\tfraction`.
+fn write_counter_histogram(
+    p: &dyn Predictor,
+    file_name: &str,
+    label: Option<&str>,
+) -> Result<(), std::io::Error> {
+    let mut histogram = [0usize; 4];
+    for state in p.table_states() {
+        histogram[state as usize] += 1;
+    }
+    let total: usize = histogram.iter().sum();
+
+    let mut data = File::create(file_name)?;
+    let (alg, config, _) = p.report();
+    if let Some(label) = label {
+        writeln!(&mut data, "# label: {}", label)?;
+    }
+    writeln!(&mut data, "# {} {:?}", alg, config)?;
+    for (state, entries) in histogram.iter().enumerate() {
+        writeln!(
+            &mut data,
+            "{}\t{}\t{}",
+            state,
+            entries,
+            *entries as f64 / total.max(1) as f64
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The smallest delta with at least `fraction` of the events at or
+/// below it, `histogram` being indexed by delta
+fn percentile(histogram: &[usize], fraction: f64) -> usize {
+    let total: usize = histogram.iter().sum();
+    let mut seen = 0;
+    for (delta, &n) in histogram.iter().enumerate() {
+        seen += n;
+        if seen as f64 >= fraction * total as f64 && seen > 0 {
+            return delta;
+        }
+    }
+    0
+}
+
+/// Writes the --arbitration-trace log, one row per prediction with the
+/// table it came from, that counter's state, and the choice's
+fn write_arbitration(log: &[Arbitration], file_name: &str) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(file_name)?);
+    writeln!(
+        out,
+        "event,pc,provider,state,choice,tag_hit,predicted,taken"
+    )?;
+    for (i, a) in log.iter().enumerate() {
+        let tag_hit = a.tag_hit.map_or(String::new(), |n| n.to_string());
+        writeln!(
+            out,
+            "{},{:#x},{},{},{},{},{},{}",
+            i,
+            a.addr,
+            a.provider,
+            a.state,
+            a.choice,
+            tag_hit,
+            (a.state >= 2) as u8,
+            a.was_taken as u8
+        )?;
+    }
+    out.flush()
+}
+
+/// Writes the bias profile of the traces, returning how many branches
+/// it has
+fn write_bias(inputs: &[&str], weighted: bool, file_name: &str) -> std::io::Result<usize> {
+    let mut directions: HashMap<usize, [usize; 2]> = HashMap::new();
+    for input in inputs {
+        let (mut reader, _) = open_trace(input)?;
+        for event in EventReader::new(&mut reader, weighted) {
+            let event = event?;
+            directions.entry(event.addr).or_default()[event.was_taken as usize] += event.weight;
+        }
+    }
+    let profile = BiasProfile::from_directions(&directions);
+    profile.write(file_name)?;
+    Ok(profile.len())
+}
+
+/// Lists the first `n` events as decoded, the word re-encoded from
+/// them, for checking a trace generator against the format
+fn decode_check(
+    out: &mut impl Write,
+    reader: impl Read,
+    n: usize,
+    weighted: bool,
+) -> std::io::Result<usize> {
+    writeln!(out, "# event\tword\taddr\ttaken\tdelta\tweight")?;
+    let mut events = 0;
+    for event in EventReader::new(reader, weighted).take(n) {
+        let event = event?;
+        let word =
+            encode_event(event.addr, event.was_taken, event.delta).expect("decoded events encode");
+        let saturated = if event.delta == DELTA_MASK {
+            " (saturated)"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "{}\t{:#018x}\t{:#x}\t{}\t{:#x}{}\t{}",
+            events, word, event.addr, event.was_taken as u8, event.delta, saturated, event.weight
+        )?;
+        events += 1;
+    }
+    Ok(events)
+}
+
+/// Writes the distribution of the events' deltas over the traces to
+/// `file_name` and summarizes it.  The largest delta, DELTA_MASK, is
+/// saturated, standing for that distance or more.
+fn delta_histogram(
+    inputs: &[&str],
+    weighted: bool,
+    file_name: &str,
+    label: Option<&str>,
+) -> Result<(), std::io::Error> {
+    let mut histogram = vec![0usize; DELTA_MASK + 1];
+    for input in inputs {
+        let (mut reader, _) = open_trace(input)?;
+        for event in EventReader::new(&mut reader, weighted) {
+            let event = event?;
+            histogram[event.delta] += event.weight;
+        }
+    }
+    let count: usize = histogram.iter().sum();
+    let sum: usize = histogram.iter().enumerate().map(|(d, n)| d * n).sum();
+
+    let mut data = File::create(file_name)?;
+    if let Some(label) = label {
+        println!("# label: {}", label);
+        writeln!(&mut data, "# label: {}", label)?;
+    }
+    writeln!(
+        &mut data,
+        "# delta\tevents\tfraction, {} is saturated",
+        DELTA_MASK
+    )?;
+    for (delta, &n) in histogram.iter().enumerate().filter(|(_, &n)| n > 0) {
+        writeln!(&mut data, "{}\t{}\t{}", delta, n, n as f64 / count as f64)?;
+    }
+
+    println!(
+        "Deltas of {} events: mean {:.2}, median {}, p99 {}, {:.2}% saturated at {}",
+        format_num!(",.0", count as f64),
+        sum as f64 / count.max(1) as f64,
+        percentile(&histogram, 0.5),
+        percentile(&histogram, 0.99),
+        100.0 * histogram[DELTA_MASK] as f64 / count.max(1) as f64,
+        DELTA_MASK
+    );
+    Ok(())
+}
+
+/// The storage unit of the printed table; bp.dat is always in KiB
+#[derive(Copy, Clone, Default)]
+enum SizeUnit {
+    Bits,
+    Bytes,
+    #[default]
+    KiB,
+}
+
+impl str::FromStr for SizeUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bits" => Ok(SizeUnit::Bits),
+            "bytes" => Ok(SizeUnit::Bytes),
+            "KiB" => Ok(SizeUnit::KiB),
+            _ => Err(format!("unknown size unit {}", s)),
+        }
+    }
+}
+
+impl SizeUnit {
+    fn format(self, bits: usize) -> String {
+        match self {
+            SizeUnit::Bits => format!("{:9} bits", bits),
+            SizeUnit::Bytes => format!("{:8.0} B", bits as f64 / 8.0),
+            SizeUnit::KiB => format!("{:6.1} KiB", bits as f64 / 8192.0),
+        }
+    }
+}
+
+/// What an event's delta counts, for how many instructions it retires
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+enum InstretPolicy {
+    /// The instructions before the branch, so the branch is one more
+    #[default]
+    DeltaPlusOne,
+    /// The instructions up to and including the branch
+    Delta,
+    /// Nothing; every branch counts as one instruction
+    BranchCount,
+}
+
+impl str::FromStr for InstretPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delta-plus-one" => Ok(InstretPolicy::DeltaPlusOne),
+            "delta" => Ok(InstretPolicy::Delta),
+            "branch-count" => Ok(InstretPolicy::BranchCount),
+            _ => Err(format!("unknown instret policy {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for InstretPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InstretPolicy::DeltaPlusOne => write!(f, "delta + 1 per branch"),
+            InstretPolicy::Delta => write!(f, "delta per branch"),
+            InstretPolicy::BranchCount => write!(f, "1 per branch"),
+        }
+    }
+}
+
+/// The instructions an event retires, weighted
+fn instructions(event: &BranchEvent, opts: &Options) -> usize {
+    let counted = match opts.instret_policy {
+        InstretPolicy::DeltaPlusOne => event.delta + 1,
+        InstretPolicy::Delta => event.delta,
+        InstretPolicy::BranchCount => 1,
+    };
+    (counted + opts.delay_slots) * event.weight
+}
+
+/// How --smt interleaves the traces of the two hardware threads
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Interleave {
+    /// A branch of each in turn
+    RoundRobin,
+    /// A branch of whichever has retired fewer instructions, as if
+    /// both ran at the same IPC
+    Instret,
+}
+
+impl str::FromStr for Interleave {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Interleave::RoundRobin),
+            "instret" => Ok(Interleave::Instret),
+            _ => Err(format!("unknown interleaving {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Interleave {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Interleave::RoundRobin => write!(f, "round-robin"),
+            Interleave::Instret => write!(f, "instret"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    counter_histogram: Option<String>,
+    weighted: bool,
+    profile: Option<String>,
+    pc_buckets: Option<usize>,
+    history_policy: HistoryPolicy,
+    dump_predictions: Option<String>,
+    // (address, predictor, the first if none) of --track-pc
+    track_pc: Option<(usize, Option<String>)>,
+    dump_sram: Option<String>,
+    compare_baseline: Option<Option<String>>,
+    stats: bool,
+    // Break the predictions down by predicted and actual direction
+    confusion: bool,
+    log_loss: bool,
+    flip_rate: bool,
+    two_bit_limit: bool,
+    label: Option<String>,
+    replot: Option<String>,
+    not_taken_filter: Option<usize>,
+    update_delay: usize,
+    transitions_only: bool,
+    timing: bool,
+    gating: bool,
+    resize_at: Option<(usize, usize)>,
+    // Only branches in start..end are counted, though all are learnt
+    pc_range: Option<(usize, usize)>,
+    resize_preserve: bool,
+    throttle: Option<u64>,
+    history_reset_on_miss: usize,
+    // (tolerance in % of miss rate, windows)
+    stop_when_stable: Option<(f64, usize)>,
+    // Events per window whose MPKI percentiles are reported
+    window_percentiles: Option<usize>,
+    // Run on this instead of the INPUT traces
+    synthetic: Option<Pattern>,
+    // Skip an event of the same address and delta as the one before
+    collapse_repeats: bool,
+    // Instructions retired after each branch on top of its delta
+    delay_slots: usize,
+    instret_policy: InstretPolicy,
+    precision: usize,
+    size_unit: SizeUnit,
+    // In bits, ascending
+    size_brackets: Vec<usize>,
+    // Outcomes of history in the context
+    entropy_bound: Option<usize>,
+    chain_training: ChainTraining,
+    // Branches predicted with the same global history
+    fetch_width: usize,
+    // Show the two predictors side by side rather than the table
+    compare: bool,
+    init_from_profile: Option<BiasProfile>,
+    arbitration_trace: Option<String>,
+    arbitration_events: usize,
+    smt: Option<Interleave>,
+    // Under --smt, a global history per thread rather than one shared
+    per_thread_history: bool,
+}
+
+/// The cost of timing an empty region, subtracted from each timed
+/// prediction so cheap predictors aren't dominated by the clock
+fn timer_overhead() -> Duration {
+    const SAMPLES: u32 = 10_000;
+    let start = Instant::now();
+    for _ in 0..SAMPLES {
+        std::hint::black_box(Instant::now().elapsed());
+    }
+    start.elapsed() / SAMPLES
+}
+
+/// What a trace contributes to the suite summary: its instret and each
+/// predictor's (name, config, misses), in predictor order
+struct TraceResult {
+    instret: usize,
+    misses: Vec<(String, Vec<usize>, usize)>,
+}
+
+type Trace = BufReader<Box<dyn Read>>;
+
+/// The events up to the first that fails to read, the error left in
+/// `error` for when they've been run
+fn until_error<'a>(
+    events: impl Iterator<Item = std::io::Result<BranchEvent>> + 'a,
+    error: &'a mut Option<std::io::Error>,
+) -> impl Iterator<Item = BranchEvent> + 'a {
+    events.map_while(move |event| event.map_err(|e| *error = Some(e)).ok())
+}
+
+/// Opens a trace and reads past its 1024 byte header, returned too
+fn open_trace(file_name: &str) -> std::io::Result<(Trace, [u8; HEADER_BYTES])> {
+    // `-` reads the trace from stdin, eg. `zcat trace.gz | bp -`
+    let input: Box<dyn Read> = if file_name == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(File::open(file_name)?)
+    };
+    let mut reader = BufReader::new(input);
+    let mut header = [0; HEADER_BYTES];
+    reader.read_exact(&mut header)?;
+    Ok((reader, header))
+}
+
+/*
+ * A uniform sample of K events in one pass, by reservoir sampling: the
+ * n-th event replaces a random one of the K kept with probability K/n.
+ * The sample is written in trace order, but the events between are
+ * gone, so it shows the distribution of branches and outcomes, not the
+ * sequences the predictors learn from; running them on it says little.
+ */
+fn sample_events(events: impl Iterator<Item = BranchEvent>, k: usize) -> Vec<BranchEvent> {
+    // Seeded so the same trace gives the same sample
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut reservoir: Vec<(usize, BranchEvent)> = Vec::with_capacity(k);
+    for (n, event) in events.enumerate() {
+        if reservoir.len() < k {
+            reservoir.push((n, event));
+        } else {
+            let i = rng.gen_range(0..=n);
+            if i < k {
+                reservoir[i] = (n, event);
+            }
+        }
+    }
+    reservoir.sort_unstable_by_key(|&(n, _)| n);
+    reservoir.into_iter().map(|(_, event)| event).collect()
+}
+
+/// Writes a --sample of the traces to `out`, with the first's header
+fn write_sample(inputs: &[&str], k: usize, weighted: bool, out: &str) -> std::io::Result<usize> {
+    let mut traces = vec![];
+    let mut header = None;
+    for input in inputs {
+        let (reader, h) = open_trace(input)?;
+        header.get_or_insert(h);
+        traces.push(reader);
+    }
+    let mut error = None;
+    let events = traces
+        .iter_mut()
+        .flat_map(|reader| EventReader::new(reader, weighted));
+    let sample = sample_events(until_error(events, &mut error), k);
+    if let Some(e) = error {
+        return Err(e);
+    }
+
+    let mut w = BufWriter::new(File::create(out)?);
+    w.write_all(&header.unwrap_or([0; HEADER_BYTES]))?;
+    for event in &sample {
+        write_event(&mut w, event, weighted)?;
+    }
+    w.flush()?;
+    Ok(sample.len())
+}
+
+/// Writes the events of all the traces as one dataset, see features.rs
+fn export_features(inputs: &[&str], depth: usize, weighted: bool) -> std::io::Result<usize> {
+    let out = BufWriter::new(File::create("bp.features.csv")?);
+    let mut features = FeatureWriter::new(out, depth, weighted)?;
+    let mut rows = 0;
+    for input in inputs {
+        let (mut reader, _) = open_trace(input)?;
+        for event in EventReader::new(&mut reader, weighted) {
+            let event = event?;
+            features.record(event.addr, event.was_taken, event.weight)?;
+            rows += 1;
+        }
+    }
+    features.finish()?;
+    Ok(rows)
+}
+
+/// What simulate measured, counts in predictor order
+struct Simulation {
+    counts: Vec<Counts>,
+    totals: Totals,
+    elapsed: Duration,
+}
+
+/// Runs the events through the predictors, profiling the one picked by
+/// `profiled`.  This is run without the trace file and the reporting.
+fn simulate(
+    predictors: &mut [Box<dyn Predictor>],
+    events: impl Iterator<Item = BranchEvent>,
+    profiled: &mut Option<(usize, Profile)>,
+    opts: &Options,
+) -> Simulation {
+    let timer_overhead = if opts.timing {
+        timer_overhead()
+    } else {
+        Duration::ZERO
+    };
+
+    let start = Instant::now();
+    let mut slept = Duration::ZERO;
+
+    // Predictors learn once per event, but statistics count `weight` events
+    let mut counts = vec![Counts::default(); predictors.len()];
+    let mut last_outcomes: HashMap<usize, bool> = HashMap::new();
+    let mut outcomes = 0;
+    let mut totals = Totals::default();
+    if opts.two_bit_limit {
+        totals.two_bit_limit = Some(Default::default());
+    }
+    let mut previous_site = None;
+    // The miss rates, in %, at the last --stop-when-stable check
+    let mut last_rates: Vec<f64> = vec![];
+    let mut stable_windows = 0;
+    // The instret and misses at the start of the --window-percentiles window
+    let mut window_start = (0, vec![0; predictors.len()]);
+    for event in events {
+        // Some instrumentation emits a branch once per micro-op; those
+        // repeats neither retire instructions nor get predicted
+        let site = (event.addr, event.delta);
+        if opts.collapse_repeats && previous_site.replace(site) == Some(site) {
+            totals.collapsed += event.weight;
+            continue;
+        }
+
+        if let Some((start, end)) = opts.pc_range {
+            if !(start..end).contains(&event.addr) {
+                for p in predictors.iter_mut() {
+                    p.predict_and_update(event.addr, event.was_taken);
+                }
+                totals.outside += event.weight;
+                continue;
+            }
+        }
+
+        let mut resize_side = None;
+        if let Some((at, bits)) = opts.resize_at {
+            if totals.events == at {
+                for p in predictors.iter_mut() {
+                    if let Err(e) = p.resize(bits, opts.resize_preserve) {
+                        let (alg, config, _) = p.report();
+                        eprintln!("--resize-at: {} {:?} {}", alg, config, e);
+                    }
+                }
+            }
+            if at <= totals.events + RESIZE_WINDOW && totals.events < at + RESIZE_WINDOW {
+                let side = (totals.events >= at) as usize;
+                totals.resize_count[side] += event.weight;
+                resize_side = Some(side);
+            }
+        }
+
+        totals.instret += instructions(&event, opts);
+        totals.taken += event.was_taken as usize * event.weight;
+        totals.delta += event.delta * event.weight;
+        let mut always_taken = false;
+        if opts.stats {
+            let directions = totals.directions.entry(event.addr).or_default();
+            directions[event.was_taken as usize] += event.weight;
+            always_taken = directions[0] == 0;
+        }
+        if let Some(bits) = opts.entropy_bound {
+            let context = (event.addr, outcomes & ((1 << bits) - 1));
+            totals.contexts.entry(context).or_default()[event.was_taken as usize] += event.weight;
+            outcomes = outcomes << 1 | event.was_taken as usize;
+        }
+        if let Some((counters, misses)) = &mut totals.two_bit_limit {
+            let counter = counters
+                .entry(event.addr)
+                .or_insert_with(|| TwoBitCounter::new(true));
+            if counter.value() != event.was_taken {
+                *misses += event.weight;
+            }
+            counter.update(event.was_taken);
+        }
+        let transition = opts.transitions_only
+            && last_outcomes
+                .insert(event.addr, event.was_taken)
+                .is_some_and(|last| last != event.was_taken);
+        if transition {
+            totals.transitions += event.weight;
+        }
+
+        for (i, (p, counts)) in predictors.iter_mut().zip(counts.iter_mut()).enumerate() {
+            if opts.log_loss {
+                let taken = p.predict_prob(event.addr);
+                let p_outcome = if event.was_taken { taken } else { 1.0 - taken };
+                counts.log_loss -= p_outcome.max(1e-12).log2() * event.weight as f64;
+            }
+            let start = opts.timing.then(Instant::now);
+            let (predicted, confident) = if opts.gating {
+                p.predict_with_confidence(event.addr, event.was_taken)
+            } else {
+                (p.predict_and_update(event.addr, event.was_taken), true)
+            };
+            if let Some(start) = start {
+                counts.time += start.elapsed().saturating_sub(timer_overhead);
+            }
+
+            let missed = predicted != event.was_taken;
+            if !confident {
+                counts.unconfident += event.weight;
+            } else if missed {
+                counts.confident_misses += event.weight;
+            }
+            if missed {
+                counts.misses += event.weight;
+                if predicted {
+                    counts.false_taken += event.weight;
+                }
+                if let Some(side) = resize_side {
+                    counts.resize_misses[side] += event.weight;
+                }
+                if transition {
+                    counts.transition_misses += event.weight;
+                }
+                if always_taken {
+                    *counts.taken_only_misses.entry(event.addr).or_default() += event.weight;
+                }
+            }
+            if let Some((profiled_i, profile)) = profiled.as_mut() {
+                if *profiled_i == i {
+                    profile.record(event.addr, missed, event.weight);
+                }
+            }
+        }
+
+        totals.events += 1;
+        totals.count += event.weight;
+
+        if totals.events % INTERRUPT_POLL == 0 && interrupted() {
+            totals.interrupted = true;
+            break;
+        }
+
+        if let Some(window) = opts.window_percentiles {
+            if totals.events % window == 0 {
+                let instret = totals.instret - window_start.0;
+                for (c, start) in counts.iter_mut().zip(window_start.1.iter_mut()) {
+                    c.window_mpkis.push(mpki(c.misses - *start, instret.max(1)));
+                    *start = c.misses;
+                }
+                window_start.0 = totals.instret;
+            }
+        }
+
+        if let Some((tolerance, windows)) = opts.stop_when_stable {
+            if totals.events % STABLE_WINDOW == 0 {
+                let rates: Vec<f64> = counts
+                    .iter()
+                    .map(|c| 100.0 * c.misses as f64 / totals.count as f64)
+                    .collect();
+                let stable = rates.len() == last_rates.len()
+                    && rates
+                        .iter()
+                        .zip(&last_rates)
+                        .all(|(rate, last)| (rate - last).abs() <= tolerance);
+                stable_windows = if stable { stable_windows + 1 } else { 0 };
+                last_rates = rates;
+                if stable_windows >= windows {
+                    totals.stopped_early = true;
+                    break;
+                }
+            }
+        }
+
+        if let Some(rate) = opts.throttle {
+            // Sleep off any lead over `rate` events/s, excluded from the timing
+            let due = Duration::from_secs_f64(totals.events as f64 / rate as f64);
+            let ahead = due.saturating_sub(start.elapsed());
+            if !ahead.is_zero() {
+                let sleep_start = Instant::now();
+                std::thread::sleep(ahead);
+                slept += sleep_start.elapsed();
+            }
+        }
+    }
+
+    Simulation {
+        counts,
+        totals,
+        elapsed: start.elapsed().saturating_sub(slept),
+    }
+}
+
+/// A PC in hex with a 0x prefix or in decimal
+fn parse_pc(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn run(
+    mut predictors: Vec<Box<dyn Predictor>>,
+    per_branch: Vec<Box<dyn Predictor>>,
+    file_name: &str,
+    opts: &Options,
+) -> Result<TraceResult, std::io::Error> {
+    // Nothing to report, so don't read what may be a long trace
+    if predictors.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "no predictors configured",
+        ));
+    }
+
+    let mut read_error = None;
+    let events: Box<dyn Iterator<Item = BranchEvent>> = match opts.synthetic {
+        Some(pattern) => Box::new(pattern.events(SYNTHETIC_EVENTS)),
+        None => {
+            let (reader, _) = open_trace(file_name)?;
+            let events = EventReader::new(reader, opts.weighted);
+            Box::new(until_error(events, &mut read_error))
+        }
+    };
+
+    // The per-branch history twins run after the predictors proper
+    let twins = per_branch.len();
+    if twins > 0 {
+        for p in predictors.iter_mut() {
+            p.set_fetch_width(opts.fetch_width);
+        }
+        predictors.extend(per_branch);
+    }
+
+    for p in predictors.iter_mut() {
+        p.set_history_policy(opts.history_policy);
+        p.set_history_reset_on_miss(opts.history_reset_on_miss);
+    }
+
+    if opts.update_delay > 0 {
+        predictors = predictors
+            .into_iter()
+            .map(|p| -> Box<dyn Predictor> { Box::new(DelayedBp::new(p, opts.update_delay)) })
+            .collect();
+    }
+
+    if let Some(streak) = opts.not_taken_filter {
+        predictors = predictors
+            .into_iter()
+            .map(|p| -> Box<dyn Predictor> { Box::new(NotTakenFilter::new(p, streak)) })
+            .collect();
+    }
+
+    if opts.flip_rate {
+        predictors = predictors
+            .into_iter()
+            .map(|p| -> Box<dyn Predictor> { Box::new(Stability::new(p)) })
+            .collect();
+    }
+
+    let dump_error = WriteError::default();
+    if let Some(spec) = &opts.dump_predictions {
+        match find_predictor(&predictors, spec) {
+            Some(i) => {
+                let out = BufWriter::new(File::create("bp.predictions.bin")?);
+                let inner = predictors.remove(i);
+                let dump = DumpPredictions::new(inner, out, dump_error.clone());
+                predictors.insert(i, Box::new(dump));
+            }
+            None => eprintln!("--dump-predictions: no predictor matches {}", spec),
+        }
+    }
+
+    let track_error = WriteError::default();
+    if let Some((addr, spec)) = &opts.track_pc {
+        let tracked = match spec {
+            Some(spec) => find_predictor(&predictors, spec),
+            None => Some(0),
+        };
+        match tracked {
+            Some(i) => {
+                let out = BufWriter::new(File::create("bp.track.csv")?);
+                let inner = predictors.remove(i);
+                let track = TrackPc::new(inner, *addr, out, track_error.clone())?;
+                predictors.insert(i, Box::new(track));
+            }
+            None => eprintln!(
+                "--track-predictor: no predictor matches {}",
+                spec.as_ref().unwrap()
+            ),
+        }
+    }
+
+    let profiled = match &opts.profile {
+        Some(spec) => match find_predictor(&predictors, spec) {
+            Some(i) => Some((
+                i,
+                match opts.pc_buckets {
+                    Some(bits) => Profile::new().with_buckets(bits),
+                    None => Profile::new(),
+                },
+            )),
+            None => {
+                eprintln!("--profile: no predictor matches {}", spec);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut profiled = profiled;
+
+    let arbitrated = match &opts.arbitration_trace {
+        Some(spec) => match find_predictor(&predictors, spec) {
+            Some(i) if predictors[i].log_arbitration(opts.arbitration_events) => Some(i),
+            Some(_) => {
+                eprintln!(
+                    "--arbitration-trace: {} doesn't arbitrate between tables",
+                    spec
+                );
+                None
+            }
+            None => {
+                eprintln!("--arbitration-trace: no predictor matches {}", spec);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let Simulation {
+        mut counts,
+        totals,
+        elapsed,
+    } = simulate(&mut predictors, events, &mut profiled, opts);
+    if let Some(e) = read_error {
+        return Err(e);
+    }
+    // Leaving them for the writers to see they're done
+    for (file_name, error) in [
+        ("bp.predictions.bin", &dump_error),
+        ("bp.track.csv", &track_error),
+    ] {
+        if let Some(e) = error.borrow().as_ref() {
+            let e = std::io::Error::new(e.kind(), format!("{}: {}", file_name, e));
+            return Err(e);
+        }
+    }
+
+    let per_branch = counts.split_off(counts.len() - twins);
+    for (counts, twin) in counts.iter_mut().zip(per_branch) {
+        counts.per_branch_misses = Some(twin.misses);
+    }
+    let lanes = predictors.capacity();
+    predictors.truncate(predictors.len() - twins);
+
+    if let Some(spec) = &opts.counter_histogram {
+        match find_predictor(&predictors, spec) {
+            Some(i) => write_counter_histogram(
+                predictors[i].as_ref(),
+                "bp.hist.dat",
+                opts.label.as_deref(),
+            )?,
+            None => eprintln!("--counter-histogram: no predictor matches {}", spec),
+        }
+    }
+
+    if let Some(i) = arbitrated {
+        write_arbitration(predictors[i].arbitration_log(), "bp.arbitration.csv")?;
+    }
+
+    if let Some(spec) = &opts.dump_sram {
+        match find_predictor(&predictors, spec) {
+            Some(i) => {
+                let mut image = vec![];
+                match predictors[i].dump_sram(&mut image) {
+                    Ok(()) => File::create("bp.sram.bin")?.write_all(&image)?,
+                    Err(e) => eprintln!("--dump-sram: {}: {}", spec, e),
+                }
+            }
+            None => eprintln!("--dump-sram: no predictor matches {}", spec),
+        }
+    }
+
+    let profiled_name = profiled.as_ref().map(|(i, _)| predictors[*i].report());
+    let result = TraceResult {
+        instret: totals.instret,
+        misses: predictors
+            .iter()
+            .zip(&counts)
+            .map(|(p, counts)| {
+                let (alg, config, _) = p.report();
+                (alg, config, counts.misses)
+            })
+            .collect(),
+    };
+
+    let results = predictors
+        .iter()
+        .zip(counts)
+        .map(|(p, counts)| {
+            let (alg, config, size) = p.report();
+            let (ports, stability) = (p.port_stats(), p.stability());
+            (
+                alg,
+                config,
+                size,
+                Counts {
+                    ports,
+                    stability,
+                    ..counts
+                },
+            )
+        })
+        .collect();
+    report(
+        results,
+        lanes,
+        elapsed,
+        &totals,
+        &Provenance::now(file_name),
+        opts,
+    )?;
+
+    if let (Some((_, profile)), Some((alg, config, _))) = (profiled, profiled_name) {
+        println!();
+        if let Some(label) = &opts.label {
+            println!("# label: {}", label);
+        }
+        println!("Worst offenders for {} {:?}:", alg, config);
+        profile.print(16);
+        profile.write_buckets("bp.buckets.dat", opts.label.as_deref())?;
+    }
+
+    Ok(result)
+}
+
+/// Merges the threads' events in the order `policy` fetches them,
+/// tagged with their thread, the rest of one running alone once the
+/// other has ended
+fn interleave<I: Iterator<Item = BranchEvent>>(
+    threads: Vec<I>,
+    policy: Interleave,
+) -> impl Iterator<Item = (usize, BranchEvent)> {
+    let mut threads: Vec<_> = threads.into_iter().map(Iterator::peekable).collect();
+    let mut instret = vec![0; threads.len()];
+    let mut turn = 0;
+    std::iter::from_fn(move || {
+        let n = threads.len();
+        // Those with events left, from whose turn it is, which breaks ties
+        let mut ready = (0..n)
+            .map(|i| (turn + i) % n)
+            .filter(|&t| threads[t].peek().is_some());
+        let next = match policy {
+            Interleave::RoundRobin => ready.next(),
+            Interleave::Instret => ready.min_by_key(|&t| instret[t]),
+        }?;
+        let event = threads[next].next().unwrap();
+        instret[next] += (event.delta + 1) * event.weight;
+        turn = next + 1;
+        Some((next, event))
+    })
+}
+
+/// What a run of thread tagged events measured, by thread: instret,
+/// and the misses of each predictor
+struct ThreadCounts {
+    instret: Vec<usize>,
+    misses: Vec<Vec<usize>>,
+    interrupted: bool,
+}
+
+fn simulate_threads(
+    predictors: &mut [Box<dyn Predictor>],
+    events: impl Iterator<Item = (usize, BranchEvent)>,
+    threads: usize,
+    opts: &Options,
+) -> ThreadCounts {
+    let mut counts = ThreadCounts {
+        instret: vec![0; threads],
+        misses: vec![vec![0; threads]; predictors.len()],
+        interrupted: false,
+    };
+    for (i, (thread, event)) in events.enumerate() {
+        if i % INTERRUPT_POLL == 0 && interrupted() {
+            counts.interrupted = true;
+            break;
+        }
+        counts.instret[thread] += instructions(&event, opts);
+        for (p, misses) in predictors.iter_mut().zip(counts.misses.iter_mut()) {
+            if opts.per_thread_history {
+                p.set_thread(thread);
+            }
+            if p.predict_and_update(event.addr, event.was_taken) != event.was_taken {
+                misses[thread] += event.weight;
+            }
+        }
+    }
+    counts
+}
+
+/*
+ * --smt: the two traces are run as hardware threads sharing one set of
+ * predictor tables, and then each alone on its own set, the difference
+ * in a thread's misses being the interference of the other.  Without
+ * --per-thread-history the threads also share the global history.
+ */
+fn run_smt(
+    mut shared: Vec<Box<dyn Predictor>>,
+    mut alone: [Vec<Box<dyn Predictor>>; 2],
+    inputs: &[&str],
+    policy: Interleave,
+    opts: &Options,
+) -> Result<(), std::io::Error> {
+    for p in shared.iter_mut().chain(alone.iter_mut().flatten()) {
+        p.set_history_policy(opts.history_policy);
+        p.set_history_reset_on_miss(opts.history_reset_on_miss);
+    }
+
+    let mut traces = vec![];
+    for input in inputs {
+        traces.push(open_trace(input)?.0);
+    }
+    let mut errors: Vec<Option<std::io::Error>> = inputs.iter().map(|_| None).collect();
+    let events = traces
+        .into_iter()
+        .zip(errors.iter_mut())
+        .map(|(reader, error)| until_error(EventReader::new(reader, opts.weighted), error))
+        .collect();
+    let together = simulate_threads(&mut shared, interleave(events, policy), 2, opts);
+    if let Some(e) = errors.into_iter().flatten().next() {
+        return Err(e);
+    }
+
+    // Only whole runs alone compare with the shared one
+    let mut apart = vec![];
+    for (thread, (input, predictors)) in inputs.iter().zip(alone.iter_mut()).enumerate() {
+        if interrupted() {
+            break;
+        }
+        let (reader, _) = open_trace(input)?;
+        let mut error = None;
+        let events = until_error(EventReader::new(reader, opts.weighted), &mut error);
+        let counts = simulate_threads(predictors, events.map(|event| (thread, event)), 2, opts);
+        if let Some(e) = error {
+            return Err(e);
+        }
+        if !counts.interrupted {
+            apart.push(counts);
+        }
+    }
+
+    if let Some(label) = &opts.label {
+        println!("# label: {}", label);
+    }
+    let history = if opts.per_thread_history {
+        "per thread"
+    } else {
+        "shared"
+    };
+    println!("SMT, {} interleaving, {} history:", policy, history);
+    if interrupted() {
+        println!("Interrupted, so these are partial results, leaving out the runs alone that didn't finish");
+    }
+    for (thread, input) in inputs.iter().enumerate() {
+        println!(
+            "  t{}: {}, {} instructions",
+            thread,
+            input,
+            format_num!(",.0", together.instret[thread] as f64)
+        );
+    }
+    println!(
+        "{:>9} {:>9} {:>9}  {:>9} {:>9} {:>9}  mpki and interference",
+        "t0 alone", "shared", "", "t1 alone", "shared", ""
+    );
+    let p = opts.precision;
+    for (i, predictor) in shared.iter().enumerate() {
+        let (alg, config, _) = predictor.report();
+        let mut line = String::new();
+        for thread in 0..2 {
+            let instret = together.instret[thread];
+            let shared = together.misses[i][thread];
+            let Some(apart) = apart.get(thread) else {
+                line += &format!(
+                    "{:>9} {:9.p$} {:>9}  ",
+                    "",
+                    mpki(shared, instret),
+                    "",
+                    p = p
+                );
+                continue;
+            };
+            let alone = apart.misses[i][thread];
+            let interference = if alone != 0 {
+                format!(
+                    "{:+8.1}%",
+                    100.0 * (shared as f64 - alone as f64) / alone as f64
+                )
+            } else {
+                format!("{:>9}", "")
+            };
+            line += &format!(
+                "{:9.p$} {:9.p$} {}  ",
+                mpki(alone, instret),
+                mpki(shared, instret),
+                interference,
+                p = p
+            );
+        }
+        println!("{}{} {:?}", line, alg, config);
+    }
+    Ok(())
+}
+
+/*
+ * --reverse: the whole trace is buffered and run forward through one
+ * set of predictors and backward through a fresh one.  The reversed
+ * trace has the same branches and outcomes, so a large gap points at
+ * learning that depends on the order, like slow warm up or hysteresis
+ * that suits one direction.
+ */
+fn run_reverse(
+    mut forward: Vec<Box<dyn Predictor>>,
+    mut backward: Vec<Box<dyn Predictor>>,
+    file_name: &str,
+    opts: &Options,
+) -> Result<(), std::io::Error> {
+    for p in forward.iter_mut().chain(backward.iter_mut()) {
+        p.set_history_policy(opts.history_policy);
+        p.set_history_reset_on_miss(opts.history_reset_on_miss);
+    }
+    let (reader, _) = open_trace(file_name)?;
+    let events: Vec<BranchEvent> =
+        EventReader::new(reader, opts.weighted).collect::<std::io::Result<_>>()?;
+
+    let ahead = simulate(&mut forward, events.iter().copied(), &mut None, opts);
+    // Only a whole reversed run compares with the forward one
+    let behind = (!ahead.totals.interrupted)
+        .then(|| simulate(&mut backward, events.iter().rev().copied(), &mut None, opts))
+        .filter(|behind| !behind.totals.interrupted);
+
+    if let Some(label) = &opts.label {
+        println!("# label: {}", label);
+    }
+    println!(
+        "Forward versus reversed, {} branch events:",
+        format_num!(",.0", ahead.totals.events as f64)
+    );
+    if interrupted() {
+        println!("Interrupted, so these are partial results, without the reversed run if it didn't finish");
+    }
+    println!("{:>9} {:>9}", "forward", "reversed");
+    let p = opts.precision;
+    for (i, (predictor, a)) in forward.iter().zip(&ahead.counts).enumerate() {
+        let (alg, config, _) = predictor.report();
+        let before = mpki(a.misses, ahead.totals.instret);
+        let Some(behind) = &behind else {
+            println!("{:9.p$} {:>9} mpki {} {:?}", before, "", alg, config, p = p);
+            continue;
+        };
+        let after = mpki(behind.counts[i].misses, behind.totals.instret);
+        println!(
+            "{:9.p$} {:9.p$} mpki ({:+.1}%) {} {:?}",
+            before,
+            after,
+            100.0 * (after - before) / before.max(f64::MIN_POSITIVE),
+            alg,
+            config,
+            p = p
+        );
+    }
+    Ok(())
+}
+
+/*
+ * --multi-run: a trace of several runs concatenated, each behind a
+ * header like the first, see read_run_event.  Each run gets fresh
+ * predictors, as it would running alone, and the runs are reported
+ * one by one and together.
+ */
+fn run_multi(
+    build: impl Fn() -> Vec<Box<dyn Predictor>>,
+    file_name: &str,
+    opts: &Options,
+) -> Result<(), std::io::Error> {
+    let (mut reader, header) = open_trace(file_name)?;
+    let magic: [u8; 8] = header[..8].try_into().unwrap();
+    if magic == [0; 8] {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the header has no magic to find the next runs' by",
+        ));
+    }
+
+    if let Some(label) = &opts.label {
+        println!("# label: {}", label);
+    }
+    let p = opts.precision;
+    let mut all: Vec<(String, Vec<usize>, usize)> = vec![];
+    let (mut runs, mut events, mut instret) = (0, 0, 0);
+    loop {
+        let mut predictors = build();
+        for p in predictors.iter_mut() {
+            p.set_history_policy(opts.history_policy);
+            p.set_history_reset_on_miss(opts.history_reset_on_miss);
+        }
+        let (mut new_run, mut error) = (false, None);
+        let run_events =
+            std::iter::from_fn(|| read_run_event(&mut reader, &magic, opts.weighted, &mut new_run));
+        let sim = simulate(
+            &mut predictors,
+            until_error(run_events, &mut error),
+            &mut None,
+            opts,
+        );
+        if let Some(e) = error {
+            return Err(e);
+        }
+        runs += 1;
+        events += sim.totals.events;
+        instret += sim.totals.instret;
+        println!(
+            "Run {}, {} branch events:",
+            runs,
+            format_num!(",.0", sim.totals.events as f64)
+        );
+        for (i, (predictor, counts)) in predictors.iter().zip(&sim.counts).enumerate() {
+            let (alg, config, _) = predictor.report();
+            println!(
+                "{:9.p$} mpki {} {:?}",
+                mpki(counts.misses, sim.totals.instret),
+                alg,
+                config,
+                p = p
+            );
+            match all.get_mut(i) {
+                Some(total) => total.2 += counts.misses,
+                None => all.push((alg, config, counts.misses)),
+            }
+        }
+        if sim.totals.interrupted {
+            println!("Interrupted, so these are partial results");
+            break;
+        }
+        if !new_run {
+            break;
+        }
+    }
+
+    println!(
+        "All {} runs, {} branch events:",
+        runs,
+        format_num!(",.0", events as f64)
+    );
+    for (alg, config, misses) in &all {
+        println!(
+            "{:9.p$} mpki {} {:?}",
+            mpki(*misses, instret),
+            alg,
+            config,
+            p = p
+        );
+    }
+    Ok(())
+}
+
+// Zero MPKI traces are counted as this in the geometric mean
+const MIN_MPKI: f64 = 0.001;
+
+/// Prints, per predictor, the instret weighted mean MPKI over the
+/// traces, ie. total misses over total instructions, and the geometric
+/// mean of the per trace MPKIs, which weights every trace equally.
+fn print_suite_summary(traces: &[TraceResult]) {
+    let instret: usize = traces.iter().map(|t| t.instret).sum();
+    let mut rows: Vec<(f64, f64, &str, &[usize])> = traces[0]
+        .misses
+        .iter()
+        .enumerate()
+        .map(|(i, (alg, config, _))| {
+            let misses: usize = traces.iter().map(|t| t.misses[i].2).sum();
+            let log_sum: f64 = traces
+                .iter()
+                .map(|t| mpki(t.misses[i].2, t.instret).max(MIN_MPKI).ln())
+                .sum();
+            (
+                mpki(misses, instret),
+                (log_sum / traces.len() as f64).exp(),
+                alg.as_str(),
+                config.as_slice(),
+            )
+        })
+        .collect();
+    rows.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+
+    println!();
+    println!(
+        "Suite of {} traces (weighted mean, geometric mean):",
+        traces.len()
+    );
+    for (mean, geomean, alg, config) in rows {
+        println!(
+            "{:5.1} mpki {:5.1} mpki {} {:?}",
+            mean, geomean, alg, config
+        );
+    }
+}
+
+/// A predictor whose MPKI rose by more than the tolerance over the
+/// reference run: (name, config, reference MPKI, MPKI)
+type Regression = (String, Vec<usize>, f64, f64);
+
+/// Compares a trace's results against the reference rows, matching
+/// predictors by name and config.  `tolerance` is the fraction the MPKI
+/// may rise by; predictors missing from either side aren't compared.
+fn regressions(
+    reference: &[Row],
+    reference_instret: usize,
+    result: &TraceResult,
+    tolerance: f64,
+) -> Vec<Regression> {
+    result
+        .misses
+        .iter()
+        .filter_map(|(alg, config, misses)| {
+            let id = predictor_id(alg, config);
+            let (_, _, _, counts) = reference
+                .iter()
+                .find(|(ref_alg, ref_config, _, _)| predictor_id(ref_alg, ref_config) == id)?;
+            let before = mpki(counts.misses, reference_instret);
+            let after = mpki(*misses, result.instret);
+            (after > before * (1.0 + tolerance))
+                .then(|| (alg.clone(), config.clone(), before, after))
+        })
+        .collect()
+}
+
+fn gen_predictors() -> Vec<Box<dyn Predictor>> {
+    let mut predictors: Vec<Box<dyn Predictor>> = if false {
+        vec![Box::new(NoneTakenBp::new()), Box::new(LocalBp::new(14))]
+    } else {
+        vec![]
+    };
+
+    if false {
+        for s in 12..=18 {
+            predictors.push(Box::new(GshareBp::new(s)));
+        }
+        for s in 10..=17 {
+            predictors.push(Box::new(BimodalBp::new(s)));
+        }
+    }
+
+    if true {
+        for d in 0..5 {
+            let s = 13;
+            predictors.push(Box::new(YagsBp::yags1(s, s - d, 6)));
+            predictors.push(Box::new(YagsBp::yags2(s, s - d, 6)));
+            predictors.push(Box::new(YagsBp::yags3(s, s - d, 6)));
+            predictors.push(Box::new(YagsBp::yags4(s, s - d, 6)));
+        }
+        let lengths = geometric_lengths(5, TAGE_MIN_HISTORY, TAGE_MAX_HISTORY);
+        for s in 11..=13 {
+            predictors.push(Box::new(TageBp::new(s, &lengths, 8)));
+        }
+    }
+
+    //    predictors.push(Box::new(YagsBp::yags5(22, 22, 22)));
+
+    // Limit test
+    // (or -p yags1:22,40,6, which is skipped if it can't be allocated)
+    // predictors.push(Box::new(YagsBp::yags1(22, 40)));
+    predictors
+}
+
+// Optional cargo features and whether this build has them
+const FEATURES: &[(&str, bool)] = &[("simd", cfg!(feature = "simd"))];
+
+/// Prints the version with what this build can run, as it's generated
+/// from the registry it can't go stale
+fn version_detailed() {
+    println!("bp {}", env!("CARGO_PKG_VERSION"));
+    println!("predictors:");
+    for entry in registry::entries() {
+        println!("  {}", entry.usage());
+    }
+    let features: Vec<String> = FEATURES
+        .iter()
+        .map(|(name, enabled)| format!("{}{}", if *enabled { "+" } else { "-" }, name))
+        .collect();
+    if features.is_empty() {
+        println!("features: none");
+    } else {
+        println!("features: {}", features.join(" "));
+    }
+}
+
+/// Drops predictors whose (name, config) already appeared, as a sweep
+/// listing one twice would just run it twice
+fn dedup_predictors(predictors: Vec<Box<dyn Predictor>>, warn: bool) -> Vec<Box<dyn Predictor>> {
+    let mut seen = vec![];
+    predictors
+        .into_iter()
+        .filter(|p| {
+            let id = p.id();
+            if seen.contains(&id) {
+                if warn {
+                    eprintln!(
+                        "Dropping duplicate {}, use --allow-duplicates to keep it",
+                        id
+                    );
+                }
+                false
+            } else {
+                seen.push(id);
+                true
+            }
+        })
+        .collect()
+}
+
+/// The predictors, largest first, that would have to go for the
+/// footprints to fit in `limit` bytes, none when they already do
+fn memory_offenders(predictors: &[Box<dyn Predictor>], copies: usize, limit: usize) -> Vec<usize> {
+    let footprint = |i: usize| predictors[i].footprint_bytes() * copies;
+    let mut total: usize = (0..predictors.len()).map(footprint).sum();
+    let mut largest: Vec<usize> = (0..predictors.len()).collect();
+    largest.sort_by_key(|&i| std::cmp::Reverse(footprint(i)));
+    largest
+        .into_iter()
+        .take_while(|&i| {
+            let over = total > limit;
+            total -= footprint(i);
+            over
+        })
+        .collect()
+}
+
+const GIB: f64 = (1u64 << 30) as f64;
+
+/// Prints the predictors that would run, without touching the trace
+fn dry_run(predictors: &[Box<dyn Predictor>]) {
+    let mut total = 0;
+    for p in predictors {
+        let (alg, config, size) = p.report();
+        println!("{:6.1} KiB {} {:?}", size as f64 / 8192.0, alg, config);
+        total += size;
+    }
+    println!(
+        "{} predictors, {:.1} KiB total",
+        predictors.len(),
+        total as f64 / 8192.0
+    );
+}
+
+/// Exits with the complaint that --NAME expects EXPECTS
+fn arg_error(name: &str, expects: &str) -> ! {
+    eprintln!("--{} expects {}", name, expects);
+    std::process::exit(1);
+}
+
+/// --NAME parsed as a T, if given, exiting if it doesn't parse
+fn parse_arg<T: str::FromStr>(matches: &ArgMatches, name: &str, expects: &str) -> Option<T> {
+    parse_arg_if(matches, name, expects, |_| true)
+}
+
+/// --NAME parsed as a T, if given, exiting unless it's also valid
+fn parse_arg_if<T: str::FromStr>(
+    matches: &ArgMatches,
+    name: &str,
+    expects: &str,
+    valid: impl Fn(&T) -> bool,
+) -> Option<T> {
+    matches.value_of(name).map(|s| match s.parse() {
+        Ok(value) if valid(&value) => value,
+        _ => arg_error(name, expects),
+    })
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("Bp")
+        .version("1.0")
+        .author("Tommy Thorn <tommy.thorn@gmail.com>")
+        .about("Exercizes Branch Predictor Algorithms")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sets the input files to use, - for stdin")
+                .multiple(true)
+                .required_unless_one(&[
+                    "verify",
+                    "dry-run",
+                    "replot",
+                    "version-detailed",
+                    "synthetic",
+                ])
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("label")
+                .long("label")
+                .value_name("STR")
+                .help("Labels every output of this run with STR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("precision")
+                .long("precision")
+                .value_name("N")
+                .help("Prints MPKI and hit rate with N decimals")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("size-unit")
+                .long("size-unit")
+                .help("Prints predictor sizes in this unit")
+                .possible_values(&["bits", "bytes", "KiB"])
+                .default_value("KiB"),
+        )
+        .arg(
+            Arg::with_name("size-brackets")
+                .long("size-brackets")
+                .value_name("KIB,..")
+                .help("Names the best predictor within each of these sizes in KiB, none if empty")
+                .takes_value(true)
+                .default_value("1,4,16,64"),
+        )
+        .arg(
+            Arg::with_name("predictor")
+                .short("p")
+                .long("predictor")
+                .value_name("SPEC")
+                .help("Runs the predictor SPEC (name[:param,..], or SPEC[@ODDS] > SPEC .. to chain them, each chosen when it gives its prediction ODDS, by default 0.75) instead of the built-in set")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("version-detailed")
+                .long("version-detailed")
+                .help("Prints the version, the predictors built in, and the enabled features"),
+        )
+        .arg(
+            Arg::with_name("replot")
+                .long("replot")
+                .value_name("FILE")
+                .help("Regenerates the table and plot from a saved bp.results.json without a trace")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("compare")
+                .long("compare")
+                .help("Shows the metrics of exactly two predictors side by side with their difference, rather than the table"),
+        )
+        .arg(
+            Arg::with_name("multi-run")
+                .long("multi-run")
+                .conflicts_with("reverse")
+                .help("Treats each trace as several runs concatenated, each behind its own header, running each with fresh predictors and reporting them apart and together"),
+        )
+        .arg(
+            Arg::with_name("reverse")
+                .long("reverse")
+                .help("Also runs fresh predictors over each trace backward and compares their MPKI against the forward run, buffering the trace"),
+        )
+        .arg(
+            Arg::with_name("smt")
+                .long("smt")
+                .value_name("INTERLEAVE")
+                .help("Runs the two INPUT traces as hardware threads sharing the predictors, fetching alternately or by fewest retired instructions, and reports each thread's interference from the other")
+                .possible_values(&["round-robin", "instret"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("per-thread-history")
+                .long("per-thread-history")
+                .requires("smt")
+                .help("Gives each --smt thread its own global history"),
+        )
+        .arg(
+            Arg::with_name("merge")
+                .long("merge")
+                .help("Treats INPUT as the bp.results.json of shards of a trace, reporting them added up"),
+        )
+        .arg(
+            Arg::with_name("check-against")
+                .long("check-against")
+                .value_name("FILE")
+                .help("Compares the MPKI against a saved bp.results.json and exits nonzero if any predictor regressed")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("check-tolerance")
+                .long("check-tolerance")
+                .value_name("PERCENT")
+                .help("How much --check-against lets the MPKI rise")
+                .default_value("0.5")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fetch-width")
+                .long("fetch-width")
+                .value_name("K")
+                .help("Predicts K branches at a time with the same global history, comparing against per-branch updates")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-memory")
+                .long("max-memory")
+                .value_name("GiB")
+                .help("Refuses to run predictors whose tables together take more than GiB")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Prints the predictors that would run and exits"),
+        )
+        .arg(
+            Arg::with_name("decode-check")
+                .long("decode-check")
+                .value_name("N")
+                .help("Lists the first N events of each trace as decoded, in hex, instead of running predictors")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("delta-histogram")
+                .long("delta-histogram")
+                .help("Writes the distribution of the distances between branches to bp.delta.dat instead of running predictors"),
+        )
+        .arg(
+            Arg::with_name("arbitration-trace")
+                .long("arbitration-trace")
+                .value_name("SPEC")
+                .help("Writes which table each prediction of the Bimodal or YAGS predictor SPEC came from to bp.arbitration.csv")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("arbitration-events")
+                .long("arbitration-events")
+                .value_name("N")
+                .help("How many events --arbitration-trace records")
+                .default_value("1000")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("write-bias")
+                .long("write-bias")
+                .value_name("FILE")
+                .help("Writes how often each branch was taken to FILE, for --init-from-profile, instead of running predictors")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("init-from-profile")
+                .long("init-from-profile")
+                .value_name("FILE")
+                .help("Seeds the address indexed counters from a --write-bias profile rather than starting cold")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sample")
+                .long("sample")
+                .value_names(&["K", "OUT"])
+                .help("Writes K events sampled uniformly from the traces to the trace OUT, for studying the distribution of branches, instead of running predictors")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export-features")
+                .long("export-features")
+                .value_name("N")
+                .help("Writes each event with its last N outcomes and PCs to bp.features.csv instead of running predictors")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("entropy-bound")
+                .long("entropy-bound")
+                .value_name("BITS")
+                .help("Rates the predictors against the least miss rate possible from the PC and BITS outcomes of history")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chain-training")
+                .long("chain-training")
+                .help("Which components of a predictor chain (-p SPEC > SPEC ..) learn from each branch")
+                .possible_values(&["all", "consulted"])
+                .default_value("all"),
+        )
+        .arg(
+            Arg::with_name("allow-duplicates")
+                .long("allow-duplicates")
+                .help("Runs predictors with the same name and configuration more than once"),
+        )
+        .arg(
+            Arg::with_name("compare-baseline")
+                .long("compare-baseline")
+                .value_name("PREDICTOR")
+                .help("Shows the miss reduction relative to NoneTaken or PREDICTOR (name[:config])")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("counter-histogram")
+                .long("counter-histogram")
+                .value_name("PREDICTOR")
+                .help("Writes the counter state distribution of PREDICTOR (name[:config]) to bp.hist.dat")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("PREDICTOR")
+                .help("Reports the worst mispredicted branches of PREDICTOR (name[:config])")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pc-buckets")
+                .long("pc-buckets")
+                .value_name("BITS")
+                .requires("profile")
+                .help("Also writes the --profile predictor's misses per 2^BITS byte PC region to bp.buckets.dat")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dump-predictions")
+                .long("dump-predictions")
+                .value_name("PREDICTOR")
+                .help("Writes the predictions of PREDICTOR (name[:config]) as packed bits to bp.predictions.bin")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("track-pc")
+                .long("track-pc")
+                .value_name("ADDR")
+                .help("Writes the prediction, its strength and the outcome of every occurrence of the branch at ADDR to bp.track.csv")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("track-predictor")
+                .long("track-predictor")
+                .value_name("PREDICTOR")
+                .requires("track-pc")
+                .help("The predictor (name[:config]) whose predictions --track-pc writes, by default the first")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("update-delay")
+                .long("update-delay")
+                .value_name("N")
+                .help("Delays each predictor learning an outcome by N events, as a pipelined update does, so predictions see tables N outcomes stale")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("not-taken-filter")
+                .long("not-taken-filter")
+                .value_name("STREAK")
+                .help("Predicts not taken, bypassing the predictor, for branches not taken STREAK times in a row")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dump-sram")
+                .long("dump-sram")
+                .value_name("PREDICTOR")
+                .help("Writes the final tables of PREDICTOR (name[:config]) as an SRAM image to bp.sram.bin")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("confusion")
+                .long("confusion")
+                .help("Reports each predictor's confusion matrix of predicted versus actual direction, with the precision and recall of taken")
+        )
+        .arg(
+            Arg::with_name("two-bit-limit")
+                .long("two-bit-limit")
+                .help("Also reports the 2bc-limit, the misses of a two-bit counter per branch with no aliasing, which bounds the counter based predictors")
+        )
+        .arg(
+            Arg::with_name("log-loss")
+                .long("log-loss")
+                .help("Reports the log-loss of the odds each predictor gives of taken, from the confidence of its counters, which rewards calibrated confidence that miss rates can't tell apart"),
+        )
+        .arg(
+            Arg::with_name("flip-rate")
+                .long("flip-rate")
+                .help("Reports how often each predictor's prediction for a branch differs from the one it made the branch's previous occurrence, a churn accuracy doesn't show"),
+        )
+        .arg(
+            Arg::with_name("gating")
+                .long("gating")
+                .help("Reports how a fetch gate on low confidence predictions would fare")
+        )
+        .arg(
+            Arg::with_name("history-policy")
+                .long("history-policy")
+                .help("Updates global history at retire or speculatively with repair")
+                .possible_values(&["retire", "speculative"])
+                .default_value("retire"),
+        )
+        .arg(
+            Arg::with_name("history-reset-on-miss")
+                .long("history-reset-on-miss")
+                .value_name("K")
+                .help("Clears the low K bits of global history on each misprediction, modelling history lost in pipeline recovery; with the speculative policy the repaired history is the cleared one")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("resize-at")
+                .long("resize-at")
+                .value_name("N,BITS")
+                .help("Resizes the predictor tables to BITS of index before event N")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pc-range")
+                .long("pc-range")
+                .value_name("START:END")
+                .help("Only counts the branches from START up to END, eg. 0x4005d0:0x400700, while the predictors still learn from all")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("resize-preserve")
+                .long("resize-preserve")
+                .requires("resize-at")
+                .help("Fills resized tables from the entries they alias rather than cold"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help("Reports additional statistics derived from the run"),
+        )
+        .arg(
+            Arg::with_name("instret-policy")
+                .long("instret-policy")
+                .help("Counts the instructions of a branch event as its delta plus the branch, the delta alone when it includes the branch, or only the branch")
+                .possible_values(&["delta-plus-one", "delta", "branch-count"])
+                .default_value("delta-plus-one"),
+        )
+        .arg(
+            Arg::with_name("delay-slots")
+                .long("delay-slots")
+                .value_name("N")
+                .help("Counts N delay slot instructions after each branch toward instret, for matching MPKI with ISAs and simulators whose deltas leave them out")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("collapse-repeats")
+                .long("collapse-repeats")
+                .help("Skips events of the same address and delta as the one just before, as instrumentation emitting a branch per micro-op does; they count toward neither instret nor misses"),
+        )
+        .arg(
+            Arg::with_name("stop-when-stable")
+                .long("stop-when-stable")
+                .value_name("TOL,WINDOWS")
+                .help("Stops once every predictor's running miss rate has moved by at most TOL percentage points at each of WINDOWS consecutive checks, one per 100,000 events, reporting what it got so far")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("window-percentiles")
+                .long("window-percentiles")
+                .value_name("N")
+                .help("Also reports the 50th, 90th and 99th percentile of each predictor's MPKI over the windows of N events, telling apart predictors of the same mean but worse phases")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("throttle")
+                .long("throttle")
+                .value_name("N")
+                .help("Slows processing to at most N events per second, for demos")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timing")
+                .long("timing")
+                .help("Measures each predictor's time per prediction"),
+        )
+        .arg(
+            Arg::with_name("transitions-only")
+                .long("transitions-only")
+                .help("Also reports accuracy on branches that changed direction since their last occurrence"),
+        )
+        .arg(
+            Arg::with_name("synthetic")
+                .long("synthetic")
+                .value_name("PATTERN")
+                .help("Runs the predictors on a million events of PATTERN instead of a trace: loop:N, alternate, random:P or two-correlated")
+                .conflicts_with_all(&[
+                    "INPUT",
+                    "multi-run",
+                    "reverse",
+                    "smt",
+                    "merge",
+                    "decode-check",
+                    "delta-histogram",
+                    "write-bias",
+                    "sample",
+                    "export-features",
+                ])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .help("Checks that predictors expected to agree do so on a synthetic stream"),
+        )
+        .arg(
+            Arg::with_name("weighted")
+                .long("weighted")
+                .help("Each event is followed by a little-endian u64 weight"),
+        )
+}
+
+fn options(matches: &ArgMatches) -> Options {
+    Options {
+        counter_histogram: matches.value_of("counter-histogram").map(String::from),
+        weighted: matches.is_present("weighted"),
+        profile: matches.value_of("profile").map(String::from),
+        pc_buckets: parse_arg_if(matches, "pc-buckets", "a number of bits", |&bits| {
+            bits < usize::BITS as usize
+        }),
+        history_policy: matches.value_of("history-policy").unwrap().parse().unwrap(),
+        history_reset_on_miss: parse_arg(
+            matches,
+            "history-reset-on-miss",
+            "a number of history bits",
+        )
+        .unwrap(),
+        dump_predictions: matches.value_of("dump-predictions").map(String::from),
+        track_pc: matches.value_of("track-pc").map(|s| match parse_pc(s) {
+            Some(addr) => (addr, matches.value_of("track-predictor").map(String::from)),
+            None => {
+                eprintln!("--track-pc expects an address");
+                std::process::exit(1);
+            }
+        }),
+        dump_sram: matches.value_of("dump-sram").map(String::from),
+        compare_baseline: matches
+            .is_present("compare-baseline")
+            .then(|| matches.value_of("compare-baseline").map(String::from)),
+        stats: matches.is_present("stats"),
+        transitions_only: matches.is_present("transitions-only"),
+        timing: matches.is_present("timing"),
+        gating: matches.is_present("gating"),
+        confusion: matches.is_present("confusion"),
+        log_loss: matches.is_present("log-loss"),
+        flip_rate: matches.is_present("flip-rate"),
+        two_bit_limit: matches.is_present("two-bit-limit"),
+        resize_at: matches.value_of("resize-at").map(|s| {
+            match s.split_once(',').map(|(n, b)| (n.parse(), b.parse())) {
+                Some((Ok(n), Ok(bits))) => (n, bits),
+                _ => {
+                    eprintln!("--resize-at expects N,BITS");
+                    std::process::exit(1);
+                }
+            }
+        }),
+        resize_preserve: matches.is_present("resize-preserve"),
+        pc_range: matches.value_of("pc-range").map(|s| {
+            match s.split_once(':').map(|(a, b)| (parse_pc(a), parse_pc(b))) {
+                Some((Some(start), Some(end))) if start < end => (start, end),
+                _ => {
+                    eprintln!("--pc-range expects START:END with START below END");
+                    std::process::exit(1);
+                }
+            }
+        }),
+        collapse_repeats: matches.is_present("collapse-repeats"),
+        instret_policy: matches.value_of("instret-policy").unwrap().parse().unwrap(),
+        delay_slots: parse_arg(matches, "delay-slots", "a number of instructions").unwrap(),
+        stop_when_stable: matches.value_of("stop-when-stable").map(|s| {
+            match s.split_once(',').map(|(t, w)| (t.parse(), w.parse())) {
+                Some((Ok(tolerance), Ok(windows))) if tolerance >= 0.0 && windows > 0 => {
+                    (tolerance, windows)
+                }
+                _ => {
+                    eprintln!("--stop-when-stable expects TOL,WINDOWS");
+                    std::process::exit(1);
+                }
+            }
+        }),
+        synthetic: matches.value_of("synthetic").map(|s| {
+            s.parse().unwrap_or_else(|e| {
+                eprintln!("--synthetic: {}", e);
+                std::process::exit(1);
+            })
+        }),
+        window_percentiles: parse_arg_if(
+            matches,
+            "window-percentiles",
+            "a positive number of events",
+            |&window| window > 0,
+        ),
+        throttle: parse_arg_if(
+            matches,
+            "throttle",
+            "a positive number of events per second",
+            |&rate| rate > 0,
+        ),
+        label: matches.value_of("label").map(String::from),
+        replot: matches.value_of("replot").map(String::from),
+        precision: parse_arg(matches, "precision", "a number of decimals").unwrap(),
+        update_delay: parse_arg(matches, "update-delay", "a number of events").unwrap(),
+        not_taken_filter: parse_arg(matches, "not-taken-filter", "a streak length"),
+        size_unit: matches.value_of("size-unit").unwrap().parse().unwrap(),
+        chain_training: matches.value_of("chain-training").unwrap().parse().unwrap(),
+        arbitration_trace: matches.value_of("arbitration-trace").map(String::from),
+        arbitration_events: parse_arg(matches, "arbitration-events", "a number of events").unwrap(),
+        init_from_profile: matches.value_of("init-from-profile").map(|file_name| {
+            BiasProfile::read(file_name).unwrap_or_else(|e| {
+                eprintln!("--init-from-profile: {}: {}", file_name, e);
+                std::process::exit(1);
+            })
+        }),
+        compare: matches.is_present("compare"),
+        smt: matches.value_of("smt").map(|s| s.parse().unwrap()),
+        per_thread_history: matches.is_present("per-thread-history"),
+        fetch_width: parse_arg_if(
+            matches,
+            "fetch-width",
+            "a positive number of branches",
+            |&width| width > 0,
+        )
+        .unwrap(),
+        entropy_bound: parse_arg_if(
+            matches,
+            "entropy-bound",
+            "a number of history bits",
+            |&bits| bits < usize::BITS as usize,
+        ),
+        size_brackets: {
+            let brackets: Result<Vec<f64>, _> = matches
+                .value_of("size-brackets")
+                .unwrap()
+                .split(',')
+                .map(str::trim)
+                .filter(|b| !b.is_empty())
+                .map(str::parse)
+                .collect();
+            match brackets {
+                Ok(kib) if kib.iter().all(|k| *k > 0.0) && kib.windows(2).all(|w| w[0] < w[1]) => {
+                    kib.iter().map(|k| (k * 8192.0) as usize).collect()
+                }
+                _ => {
+                    eprintln!("--size-brackets expects ascending sizes in KiB, eg. 1,4,16");
+                    std::process::exit(1);
+                }
+            }
+        },
+    }
+}
+
+fn replot(file_name: &str, opts: Options) {
+    match read_results(file_name) {
+        Ok((rows, lanes, elapsed, totals, provenance, label)) => {
+            let opts = Options {
+                label: opts.label.clone().or(label),
+                ..opts
+            };
+            report(rows, lanes, elapsed, &totals, &provenance, &opts)
+                .expect("failed to write results");
+        }
+        Err(e) => {
+            eprintln!("--replot: {}: {}", file_name, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn merge(shards: &[&str], opts: Options) {
+    let mut merged: Option<SavedResults> = None;
+    for &file_name in shards {
+        let shard = read_results(file_name).and_then(|shard| match &mut merged {
+            None => {
+                merged = Some(shard);
+                Ok(())
+            }
+            Some((rows, lanes, elapsed, totals, provenance, _)) => {
+                *lanes = (*lanes).max(shard.1);
+                *elapsed += shard.2;
+                if provenance.trace != shard.4.trace {
+                    provenance.trace = format!("{}+{}", provenance.trace, shard.4.trace);
+                }
+                provenance.date = provenance.date.clone().max(shard.4.date);
+                merge_results(rows, totals, &shard.0, &shard.3)
+            }
+        });
+        if let Err(e) = shard {
+            eprintln!("--merge: {}: {}", file_name, e);
+            std::process::exit(1);
+        }
+    }
+    let Some((rows, lanes, elapsed, totals, provenance, label)) = merged else {
+        eprintln!("--merge needs the results of the shards as INPUT");
+        std::process::exit(1);
+    };
+    let opts = Options {
+        label: opts.label.clone().or(label),
+        ..opts
+    };
+    report(rows, lanes, elapsed, &totals, &provenance, &opts).expect("failed to write results");
+}
+
+/// Runs the mode that only reads the traces, if one was asked for,
+/// returning whether it did
+fn trace_tool(matches: &ArgMatches, traces: &[&str], opts: &Options) -> bool {
+    if let Some(n) = parse_arg(matches, "decode-check", "a number of events") {
+        for &input in traces {
+            let result = open_trace(input).and_then(|(mut reader, _)| {
+                decode_check(&mut std::io::stdout().lock(), &mut reader, n, opts.weighted)
+            });
+            if let Err(e) = result {
+                eprintln!("--decode-check: {}: {}", input, e);
+                std::process::exit(1);
+            }
+        }
+    } else if matches.is_present("delta-histogram") {
+        if let Err(e) =
+            delta_histogram(traces, opts.weighted, "bp.delta.dat", opts.label.as_deref())
+        {
+            eprintln!("--delta-histogram: {}", e);
+            std::process::exit(1);
+        }
+    } else if let Some(file_name) = matches.value_of("write-bias") {
+        match write_bias(traces, opts.weighted, file_name) {
+            Ok(branches) => println!("Wrote the bias of {} branches to {}", branches, file_name),
+            Err(e) => {
+                eprintln!("--write-bias: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else if let Some(mut args) = matches.values_of("sample") {
+        let (k, out) = (args.next().unwrap(), args.next().unwrap());
+        let k = k
+            .parse()
+            .unwrap_or_else(|_| arg_error("sample", "a number of events"));
+        match write_sample(traces, k, opts.weighted, out) {
+            Ok(n) => println!("Wrote {} events to {}", format_num!(",.0", n as f64), out),
+            Err(e) => {
+                eprintln!("--sample: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else if let Some(depth) = parse_arg(matches, "export-features", "a history depth") {
+        match export_features(traces, depth, opts.weighted) {
+            Ok(rows) => println!(
+                "Wrote {} rows to bp.features.csv",
+                format_num!(",.0", rows as f64)
+            ),
+            Err(e) => {
+                eprintln!("--export-features: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+/// The predictors of the -p specs, or the default set, freshly built
+/// for each trace, warning about those left out only the first time
+fn build_predictors(matches: &ArgMatches, opts: &Options, warn: bool) -> Vec<Box<dyn Predictor>> {
+    let predictors = match matches.values_of("predictor") {
+        Some(specs) => {
+            let mut predictors = vec![];
+            for spec in specs {
+                match registry::parse_chain(spec, opts.chain_training) {
+                    Ok(p) => predictors.push(p),
+                    // Too big for this machine needn't stop the rest of a sweep
+                    Err(registry::SpecError::Alloc(e)) => {
+                        if warn {
+                            eprintln!("Predictor {} {}, skipping", spec, e);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if predictors.is_empty() {
+                eprintln!("No predictors left to run");
+                std::process::exit(1);
+            }
+            predictors
+        }
+        None => gen_predictors(),
+    };
+    let mut predictors = if matches.is_present("allow-duplicates") {
+        predictors
+    } else {
+        dedup_predictors(predictors, warn)
+    };
+    if let Some(profile) = &opts.init_from_profile {
+        for p in predictors.iter_mut() {
+            p.init_from_bias(profile);
+        }
+    }
+    predictors
+}
+
+/// Exits listing the predictors to drop if they need more than `gib`
+fn check_memory(predictors: &[Box<dyn Predictor>], gib: f64, opts: &Options) {
+    // --fetch-width runs a per-branch twin of each
+    let copies = if opts.fetch_width > 1 { 2 } else { 1 };
+    let offenders = memory_offenders(predictors, copies, (gib * GIB) as usize);
+    if offenders.is_empty() {
+        return;
+    }
+    let total: usize = predictors
+        .iter()
+        .map(|p| p.footprint_bytes() * copies)
+        .sum();
+    eprintln!(
+        "The predictors need {:.2} GiB, more than --max-memory {} GiB; these would have to go:",
+        total as f64 / GIB,
+        gib
+    );
+    for i in offenders {
+        let (alg, config, _) = predictors[i].report();
+        eprintln!(
+            "{:8.2} GiB {} {:?}",
+            (predictors[i].footprint_bytes() * copies) as f64 / GIB,
+            alg,
+            config
+        );
+    }
+    std::process::exit(1);
+}
+
+/// Heads the output of each of several traces with its name
+fn print_trace_header(inputs: &[&str], i: usize) {
+    if inputs.len() > 1 {
+        if i > 0 {
+            println!();
+        }
+        println!("{}:", inputs[i]);
+    }
+}
+
+fn run_multi_traces(inputs: &[&str], build: impl Fn() -> Vec<Box<dyn Predictor>>, opts: &Options) {
+    for (i, input) in inputs.iter().enumerate() {
+        print_trace_header(inputs, i);
+        if let Err(e) = run_multi(&build, input, opts) {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(1);
+        }
+        exit_if_interrupted();
+    }
+}
+
+fn run_reverse_traces(
+    inputs: &[&str],
+    predictors: Vec<Box<dyn Predictor>>,
+    build: impl Fn() -> Vec<Box<dyn Predictor>>,
+    opts: &Options,
+) {
+    let mut forward = Some(predictors);
+    for (i, input) in inputs.iter().enumerate() {
+        print_trace_header(inputs, i);
+        let forward = forward.take().unwrap_or_else(&build);
+        if let Err(e) = run_reverse(forward, build(), input, opts) {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(1);
+        }
+        exit_if_interrupted();
+    }
+}
+
+/// Runs each trace in turn, stopping early if interrupted
+fn run_traces(
+    inputs: &[&str],
+    predictors: Vec<Box<dyn Predictor>>,
+    build: impl Fn() -> Vec<Box<dyn Predictor>>,
+    opts: &Options,
+) -> Vec<TraceResult> {
+    let mut predictors = Some(predictors);
+    let mut traces = vec![];
+    for (i, input) in inputs.iter().enumerate() {
+        print_trace_header(inputs, i);
+        let predictors = predictors.take().unwrap_or_else(&build);
+        let per_branch = if opts.fetch_width > 1 {
+            build()
+        } else {
+            vec![]
+        };
+        match run(predictors, per_branch, input, opts) {
+            Ok(result) => traces.push(result),
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                std::process::exit(1);
+            }
+        }
+        if interrupted() {
+            break;
+        }
+    }
+    traces
+}
+
+/// The saved results of --check-against: file, rows, their instret
+/// and the tolerance
+type Baseline<'a> = (&'a str, Vec<Row>, usize, f64);
+
+fn read_baseline<'a>(matches: &'a ArgMatches, inputs: &[&str]) -> Option<Baseline<'a>> {
+    let file_name = matches.value_of("check-against")?;
+    if inputs.len() != 1 {
+        eprintln!("--check-against compares a single trace");
+        std::process::exit(1);
+    }
+    let percent: f64 =
+        parse_arg_if(matches, "check-tolerance", "a percentage", |&p| p >= 0.0).unwrap();
+    let tolerance = percent / 100.0;
+    match read_results(file_name) {
+        Ok((rows, _, _, totals, provenance, _)) => {
+            if provenance.trace != inputs[0] {
+                eprintln!(
+                    "--check-against: {} is of trace {}, not {}",
+                    file_name, provenance.trace, inputs[0]
+                );
+            }
+            Some((file_name, rows, totals.instret, tolerance))
+        }
+        Err(e) => {
+            eprintln!("--check-against: {}: {}", file_name, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Lists the regressions against the baseline, exiting if there are any
+fn check_baseline((file_name, rows, instret, tolerance): Baseline, trace: &TraceResult) {
+    let regressed = regressions(&rows, instret, trace, tolerance);
+    println!();
+    if regressed.is_empty() {
+        println!("No regressions against {}", file_name);
+        return;
+    }
+    println!(
+        "Regressions against {} (tolerance {}%):",
+        file_name,
+        100.0 * tolerance
+    );
+    for (alg, config, before, after) in regressed {
+        println!(
+            "{:8.3} -> {:8.3} mpki ({:+.1}%) {} {:?}",
+            before,
+            after,
+            100.0 * (after - before) / before.max(f64::MIN_POSITIVE),
+            alg,
+            config
+        );
+    }
+    std::process::exit(1);
+}
+
+pub fn main() {
+    let matches = app().get_matches();
+    let opts = options(&matches);
+
+    if matches.is_present("version-detailed") {
+        version_detailed();
+        return;
+    }
+
+    if matches.is_present("verify") {
+        std::process::exit(if verify::verify(1_000_000) { 0 } else { 1 });
+    }
+
+    if let Some(file_name) = opts.replot.clone() {
+        replot(&file_name, opts);
+        return;
+    }
+
+    // None with the modes that don't need them, eg. --dry-run
+    let traces: Vec<&str> = matches.values_of("INPUT").into_iter().flatten().collect();
+
+    if matches.is_present("merge") {
+        merge(&traces, opts);
+        return;
+    }
+
+    if trace_tool(&matches, &traces, &opts) {
+        return;
+    }
+
+    let build = || build_predictors(&matches, &opts, false);
+    let predictors = build_predictors(&matches, &opts, true);
+
+    if matches.is_present("dry-run") {
+        dry_run(&predictors);
+        return;
+    }
+
+    if opts.compare && predictors.len() != 2 {
+        eprintln!(
+            "--compare needs exactly two predictors, not {}",
+            predictors.len()
+        );
+        std::process::exit(1);
+    }
+
+    if let Some(gib) = parse_arg(&matches, "max-memory", "a size in GiB") {
+        check_memory(&predictors, gib, &opts);
+    }
+
+    // --synthetic stands in for the traces, named by its pattern
+    let synthetic = opts
+        .synthetic
+        .map(|pattern| format!("synthetic {}", pattern));
+    let inputs: Vec<&str> = match &synthetic {
+        Some(name) => vec![name],
+        None => traces,
+    };
+
+    // Every mode from here on stops early on ^C with what it has so far
+    catch_interrupts();
+
+    if let Some(policy) = opts.smt {
+        if inputs.len() != 2 {
+            eprintln!("--smt runs exactly two traces, not {}", inputs.len());
+            std::process::exit(1);
+        }
+        if let Err(e) = run_smt(predictors, [build(), build()], &inputs, policy, &opts) {
+            eprintln!("--smt: {}", e);
+            std::process::exit(1);
+        }
+        exit_if_interrupted();
+        return;
+    }
+
+    if matches.is_present("multi-run") {
+        run_multi_traces(&inputs, build, &opts);
+        return;
+    }
+
+    if matches.is_present("reverse") {
+        run_reverse_traces(&inputs, predictors, build, &opts);
+        return;
+    }
+
+    // Loaded up front to not find out it's unusable after the run
+    let baseline = read_baseline(&matches, &inputs);
+
+    let traces = run_traces(&inputs, predictors, build, &opts);
+
+    if traces.len() > 1 {
+        print_suite_summary(&traces);
+    }
+
+    if interrupted() {
+        if traces.len() < inputs.len() {
+            eprintln!("Interrupted, skipping the remaining traces");
+        }
+        // Partial results would only show as regressions
+        if baseline.is_some() {
+            eprintln!("Interrupted, not checking against the saved results");
+        }
+        std::process::exit(130);
+    }
+
+    if let Some(baseline) = baseline {
+        check_baseline(baseline, &traces[0]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_duplicate_predictors() {
+        let predictors: Vec<Box<dyn Predictor>> = vec![
+            Box::new(GshareBp::new(10)),
+            Box::new(GshareBp::new(12)),
+            Box::new(GshareBp::new(10)),
+            Box::new(LocalBp::new(10)),
+        ];
+        let kept: Vec<_> = dedup_predictors(predictors, false)
+            .iter()
+            .map(|p| p.report().1[0])
+            .collect();
+        assert_eq!(kept, [10, 12, 10]);
+    }
+
+    #[test]
+    fn refuses_empty_predictor_set() {
+        // Fails before opening the trace, which doesn't exist
+        let Err(e) = run(
+            vec![],
+            vec![],
+            "/nonexistent/trace.bin",
+            &Options::default(),
+        ) else {
+            panic!("ran without predictors");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "no predictors configured");
+    }
+
+    #[test]
+    fn merges_shards() {
+        let events = synthetic_events(20_000, 3, 2);
+        let new_predictors = || -> Vec<Box<dyn Predictor>> {
+            vec![Box::new(GshareBp::new(10)), Box::new(LocalBp::new(8))]
+        };
+        let opts = Options {
+            stats: true,
+            ..Options::default()
+        };
+        let rows = |sim: &Simulation| -> Vec<Row> {
+            sim.counts
+                .iter()
+                .zip(new_predictors())
+                .map(|(counts, p)| {
+                    let (alg, config, size) = p.report();
+                    (alg, config, size, counts.clone())
+                })
+                .collect()
+        };
+        let whole = simulate(
+            &mut new_predictors(),
+            events.iter().copied(),
+            &mut None,
+            &opts,
+        );
+        let (a, b) = events.split_at(7_000);
+        let a = simulate(&mut new_predictors(), a.iter().copied(), &mut None, &opts);
+        let b = simulate(&mut new_predictors(), b.iter().copied(), &mut None, &opts);
+
+        let (mut merged, mut totals) = (rows(&a), a.totals);
+        merge_results(&mut merged, &mut totals, &rows(&b), &b.totals).unwrap();
+        assert_eq!(totals.events, whole.totals.events);
+        assert_eq!(totals.count, whole.totals.count);
+        assert_eq!(totals.instret, whole.totals.instret);
+        assert_eq!(totals.taken, whole.totals.taken);
+        assert_eq!(totals.delta, whole.totals.delta);
+        assert_eq!(totals.directions, whole.totals.directions);
+        for (i, (_, _, _, counts)) in merged.iter().enumerate() {
+            assert_eq!(counts.misses, a.counts[i].misses + b.counts[i].misses);
+        }
+
+        assert!(merge_results(&mut merged, &mut totals, &rows(&b)[..1], &b.totals).is_err());
+    }
+
+    #[test]
+    fn finds_memory_offenders() {
+        let predictors: Vec<Box<dyn Predictor>> = vec![
+            Box::new(GshareBp::new(10)),
+            Box::new(GshareBp::new(16)),
+            Box::new(LocalBp::new(14)),
+        ];
+        let footprints: Vec<usize> = predictors.iter().map(|p| p.footprint_bytes()).collect();
+        assert!(footprints[1] > 1 << 16 && footprints[2] > 1 << 14);
+        let total: usize = footprints.iter().sum();
+        assert!(memory_offenders(&predictors, 1, total).is_empty());
+        assert_eq!(memory_offenders(&predictors, 1, total - 1), [1]);
+        assert_eq!(memory_offenders(&predictors, 1, footprints[0]), [1, 2]);
+        assert_eq!(memory_offenders(&predictors, 2, total), [1]);
+    }
+
+    #[test]
+    fn compares_two_predictors() {
+        let row = |size, misses| -> Row {
+            let counts = Counts {
+                misses,
+                ..Default::default()
+            };
+            ("Gshare".to_string(), vec![size], 1 << (size + 1), counts)
+        };
+        let totals = Totals {
+            count: 1000,
+            instret: 10_000,
+            ..Totals::default()
+        };
+        let metrics = comparison(&row(12, 100), &row(13, 80), &totals, &Options::default());
+        assert_eq!(
+            metrics,
+            [
+                ("size KiB", 1.0, 2.0),
+                ("misses", 100.0, 80.0),
+                ("mpki", 10.0, 8.0),
+                ("miss rate %", 10.0, 8.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn breaks_down_predictions() {
+        let events = synthetic_events(10_000, 0, 2);
+        let mut predictors: Vec<Box<dyn Predictor>> =
+            vec![Box::new(NoneTakenBp::new()), Box::new(GshareBp::new(10))];
+        let sim = simulate(
+            &mut predictors,
+            events.iter().copied(),
+            &mut None,
+            &Options::default(),
+        );
+
+        let mut gshare = GshareBp::new(10);
+        let mut expected = [[0; 2]; 2];
+        for e in &events {
+            let predicted = gshare.predict_and_update(e.addr, e.was_taken);
+            expected[predicted as usize][e.was_taken as usize] += e.weight;
+        }
+        assert_eq!(confusion(&sim.counts[1], &sim.totals), expected);
+
+        let taken = sim.totals.taken;
+        let not_taken = sim.totals.count - taken;
+        assert_eq!(
+            confusion(&sim.counts[0], &sim.totals),
+            [[not_taken, taken], [0, 0]]
+        );
+    }
+
+    #[test]
+    fn groups_families_by_size() {
+        let row =
+            |alg: &str, size| -> Row { (alg.to_string(), vec![size], size, Counts::default()) };
+        let results = [
+            row("YAGS3", 40),
+            row("Gshare", 20),
+            row("YAGS3", 10),
+            row("Gshare", 10),
+            row("YAGS3", 30),
+        ];
+        let sizes: Vec<(&str, Vec<usize>)> = families(&results)
+            .into_iter()
+            .map(|(alg, rows)| (alg, rows.iter().map(|r| r.2).collect()))
+            .collect();
+        assert_eq!(
+            sizes,
+            [("YAGS3", vec![10, 30, 40]), ("Gshare", vec![10, 20])]
+        );
+        assert_eq!(series_file("YAGS3"), "bp.yags3.dat");
+        assert_eq!(series_file("YAGS1[alloc=50%]"), "bp.yags1_alloc_50__.dat");
+    }
+
+    #[test]
+    fn styles_series_by_family() {
+        let (color, point) = series_style("YAGS3");
+        assert_eq!(series_style("YAGS3"), (color, point));
+        assert_eq!(series_style("YAGS3[alloc=50%]").0, color);
+        assert!((1..=12).contains(&point));
+        let colors: std::collections::HashSet<&str> =
+            ["Gshare", "YAGS1", "YAGS3", "Two-level", "Bimodal"]
+                .iter()
+                .map(|alg| series_style(alg).0)
+                .collect();
+        assert!(colors.len() > 1);
+    }
+
+    #[test]
+    fn flags_larger_configs_missing_more() {
+        let row = |alg: &str, size, misses| -> Row {
+            let counts = Counts {
+                misses,
+                ..Counts::default()
+            };
+            (alg.to_string(), vec![size], size, counts)
+        };
+        let results = [
+            row("Gshare", 10, 500),
+            row("Gshare", 20, 400),
+            // Worse than 20, though not 10
+            row("Gshare", 30, 450),
+            row("Gshare", 40, 300),
+            // Ties within a size and plateaus are fine
+            row("YAGS3", 10, 500),
+            row("YAGS3", 10, 600),
+            row("YAGS3", 20, 500),
+            // Other families don't count
+            row("Two-level", 5, 100),
+        ];
+        let anomalies: Vec<(usize, usize)> = size_anomalies(&results)
+            .into_iter()
+            .map(|(smaller, larger)| (smaller.2, larger.2))
+            .collect();
+        assert_eq!(anomalies, [(20, 30)]);
+    }
+
+    #[test]
+    fn finds_pareto_frontier() {
+        let row = |alg: &str, size, misses| -> Row {
+            let counts = Counts {
+                misses,
+                ..Counts::default()
+            };
+            (alg.to_string(), vec![size], size, counts)
+        };
+        let results = [
+            row("Gshare", 20, 400),
+            row("Gshare", 10, 500),
+            // Beaten by the smaller Gshare 20
+            row("YAGS3", 30, 450),
+            row("YAGS3", 40, 300),
+            // Tied with Gshare 20
+            row("YAGS1", 20, 400),
+            // As small as Gshare 10 but worse
+            row("Two-level", 10, 600),
+        ];
+        let frontier: Vec<(&str, usize)> = pareto_frontier(&results)
+            .into_iter()
+            .map(|r| (r.0.as_str(), r.2))
+            .collect();
+        assert_eq!(
+            frontier,
+            [("Gshare", 10), ("Gshare", 20), ("YAGS1", 20), ("YAGS3", 40)]
+        );
+    }
+
+    #[test]
+    fn lists_decoded_events() {
+        let mut bytes = vec![];
+        for (addr, was_taken, delta) in [(0x4005d0, true, 3), (0x7fff_ffff_fffe, false, DELTA_MASK)]
+        {
+            let event = BranchEvent {
+                addr,
+                was_taken,
+                delta,
+                weight: 1,
+            };
+            write_event(&mut bytes, &event, false).unwrap();
+        }
+        let mut out = vec![];
+        let events = decode_check(&mut out, &mut BufReader::new(&bytes[..]), 5, false).unwrap();
+        assert_eq!(events, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# event\tword\taddr\ttaken\tdelta\tweight\n\
+             0\t0x80030000004005d0\t0x4005d0\t1\t0x3\t1\n\
+             1\t0x7fff7ffffffffffe\t0x7ffffffffffe\t0\t0x7fff (saturated)\t1\n"
+        );
+    }
+
+    #[test]
+    fn samples_uniformly() {
+        let events = synthetic_events(100_000, 0, 1);
+        let sample = sample_events(events.iter().copied(), 1000);
+        assert_eq!(sample.len(), 1000);
+        // In trace order, and about as often taken as the whole
+        let taken = |events: &[BranchEvent]| {
+            events.iter().filter(|e| e.was_taken).count() as f64 / events.len() as f64
+        };
+        assert_close(taken(&sample), taken(&events), 0.05);
+        assert_eq!(sample_events(events[..10].iter().copied(), 1000).len(), 10);
+    }
+
+    #[test]
+    fn interleaves_threads() {
+        let events = |deltas: &[usize]| -> Vec<BranchEvent> {
+            deltas
+                .iter()
+                .map(|&delta| BranchEvent {
+                    addr: 0x1000,
+                    was_taken: true,
+                    delta,
+                    weight: 1,
+                })
+                .collect()
+        };
+        let threads = |policy| -> Vec<usize> {
+            let traces = vec![events(&[9, 9, 9]).into_iter(), events(&[0; 5]).into_iter()];
+            interleave(traces, policy)
+                .map(|(thread, _)| thread)
+                .collect()
+        };
+        assert_eq!(threads(Interleave::RoundRobin), [0, 1, 0, 1, 0, 1, 1, 1]);
+        // Thread 1 retires a branch per instruction to thread 0's ten
+        assert_eq!(threads(Interleave::Instret), [0, 1, 1, 1, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn counts_misses_per_thread() {
+        // A periodic branch, and a random one polluting shared history
+        let mut rng = StdRng::seed_from_u64(1);
+        let event = |addr, was_taken| BranchEvent {
+            addr,
+            was_taken,
+            delta: 0,
+            weight: 1,
+        };
+        let periodic: Vec<_> = (0..5000).map(|i| event(0x1000, i % 3 != 0)).collect();
+        let random: Vec<_> = (0..5000).map(|_| event(0x2000, rng.gen())).collect();
+        let run = |per_thread_history| {
+            let mut predictors: Vec<Box<dyn Predictor>> = vec![Box::new(GshareBp::new(10))];
+            let traces = vec![periodic.iter().copied(), random.iter().copied()];
+            let events = interleave(traces, Interleave::RoundRobin);
+            let opts = Options {
+                per_thread_history,
+                ..Options::default()
+            };
+            simulate_threads(&mut predictors, events, 2, &opts).misses[0].clone()
+        };
+        let (shared, own) = (run(false), run(true));
+        assert!(own[0] < 10, "{:?}", own);
+        assert!(shared[0] > 2 * own[0], "{:?} {:?}", shared, own);
+    }
+
+    #[test]
+    fn picks_best_per_bracket() {
+        let row = |name: &str, size, misses| -> Row {
+            let counts = Counts {
+                misses,
+                ..Default::default()
+            };
+            (name.to_string(), vec![], size, counts)
+        };
+        let results = [
+            row("small", 4096, 300),
+            row("smaller", 2048, 400),
+            row("medium", 16384, 200),
+            row("large", 65536, 100),
+        ];
+        let best: Vec<_> = best_per_bracket(&results, &[1024, 8192, 32768])
+            .into_iter()
+            .map(|(budget, best)| (budget, best.map(|r| r.0.as_str())))
+            .collect();
+        assert_eq!(
+            best,
+            [(1024, None), (8192, Some("small")), (32768, Some("medium"))]
+        );
+    }
+
+    #[test]
+    fn finds_regressions() {
+        let row = |name: &str, misses| -> Row {
+            let counts = Counts {
+                misses,
+                ..Default::default()
+            };
+            (name.to_string(), vec![10], 2048, counts)
+        };
+        let reference = [row("same", 100), row("worse", 100), row("better", 100)];
+        let result = TraceResult {
+            instret: 1_000_000,
+            misses: vec![
+                ("same".to_string(), vec![10], 100),
+                ("worse".to_string(), vec![10], 102),
+                ("better".to_string(), vec![10], 90),
+                ("new".to_string(), vec![10], 500),
+                ("same".to_string(), vec![12], 500),
+            ],
+        };
+        let regressed = regressions(&reference, 1_000_000, &result, 0.01);
+        assert_eq!(regressed, [("worse".to_string(), vec![10], 0.1, 0.102)]);
+        assert!(regressions(&reference, 1_000_000, &result, 0.05).is_empty());
+    }
+
+    // Gshare [10] on synthetic_events(50_000, 4, _)
+    const GSHARE_MPKI: f64 = 79.24;
+
+    /// Asserts that a rate is within `tolerance` of what's expected, as
+    /// asserting on the printed digits would break on rounding
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} isn't within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    /// The synthetic stream as events `delta` instructions apart
+    fn synthetic_events(n: usize, delta: usize, weight: usize) -> Vec<BranchEvent> {
+        verify::synthetic_stream(n, 1)
+            .into_iter()
+            .map(|(addr, was_taken)| BranchEvent {
+                addr,
+                was_taken,
+                delta,
+                weight,
+            })
+            .collect()
+    }
+
+    fn simulate_mpki(events: Vec<BranchEvent>) -> Vec<f64> {
+        let mut predictors: Vec<Box<dyn Predictor>> =
+            vec![Box::new(NoneTakenBp::new()), Box::new(GshareBp::new(10))];
+        let sim = simulate(
+            &mut predictors,
+            events.into_iter(),
+            &mut None,
+            &Options::default(),
+        );
+        sim.counts
+            .iter()
+            .map(|c| mpki(c.misses, sim.totals.instret))
+            .collect()
+    }
+
+    #[test]
+    fn simulate_measures_mpki() {
+        let events = synthetic_events(50_000, 4, 1);
+        let taken = events.iter().filter(|e| e.was_taken).count();
+        let mpki = simulate_mpki(events);
+        // NoneTaken misses exactly the taken branches
+        assert_close(mpki[0], 1000.0 * taken as f64 / 250_000.0, 1e-9);
+        assert_close(mpki[1], GSHARE_MPKI, 0.05);
+    }
+
+    #[test]
+    fn counts_misses_of_always_taken_branches() {
+        let events = [0x100, 0x200, 0x100, 0x200, 0x300, 0x200, 0x100]
+            .iter()
+            .enumerate()
+            .map(|(i, &addr)| BranchEvent {
+                addr,
+                was_taken: i != 5,
+                delta: 0,
+                weight: 1,
+            });
+        let mut predictors: Vec<Box<dyn Predictor>> = vec![Box::new(NoneTakenBp::new())];
+        let opts = Options {
+            stats: true,
+            ..Options::default()
+        };
+        let sim = simulate(&mut predictors, events, &mut None, &opts);
+        // 0x200 wasn't taken the third time, so only 0x100 and 0x300 count
+        assert_eq!(sim.counts[0].misses, 6);
+        assert_eq!(
+            always_taken_misses(&sim.totals.directions, &sim.counts[0]),
+            4
+        );
+    }
+
+    #[test]
+    fn weights_and_spacing_scale_mpki() {
+        // Weighting every event alike changes nothing per instruction,
+        // twice the spacing halves it
+        let mpki = simulate_mpki(synthetic_events(50_000, 4, 3));
+        assert_close(mpki[1], GSHARE_MPKI, 0.05);
+        let mpki = simulate_mpki(synthetic_events(50_000, 9, 1));
+        assert_close(mpki[1], GSHARE_MPKI / 2.0, 0.05);
+    }
+
+    #[test]
+    fn instret_policies() {
+        let instret = |instret_policy, delay_slots| {
+            let opts = Options {
+                instret_policy,
+                delay_slots,
+                ..Options::default()
+            };
+            let events = synthetic_events(1000, 4, 3);
+            simulate(&mut [], events.into_iter(), &mut None, &opts)
+                .totals
+                .instret
+        };
+        assert_eq!(instret(InstretPolicy::DeltaPlusOne, 0), 15_000);
+        assert_eq!(instret(InstretPolicy::Delta, 0), 12_000);
+        assert_eq!(instret(InstretPolicy::BranchCount, 0), 3000);
+        assert_eq!(instret(InstretPolicy::BranchCount, 1), 6000);
+    }
+
+    #[test]
+    fn delay_slots_add_to_instret() {
+        let events = synthetic_events(50_000, 4, 1);
+        let mut predictors: Vec<Box<dyn Predictor>> = vec![Box::new(GshareBp::new(10))];
+        let opts = Options {
+            delay_slots: 5,
+            ..Options::default()
+        };
+        let sim = simulate(&mut predictors, events.into_iter(), &mut None, &opts);
+        assert_eq!(sim.totals.instret, 50_000 * 10);
+        assert_close(
+            mpki(sim.counts[0].misses, sim.totals.instret),
+            GSHARE_MPKI / 2.0,
+            0.05,
+        );
+    }
+
+    #[test]
+    fn counts_only_pc_range() {
+        let events = synthetic_events(20_000, 4, 2);
+        let mut addrs: Vec<usize> = events.iter().map(|e| e.addr).collect();
+        addrs.sort_unstable();
+        let range = (addrs[0], addrs[addrs.len() / 2]);
+
+        // The branches outside still train, so get in the history
+        let mut gshare = GshareBp::new(10);
+        let mut misses = 0;
+        for e in &events {
+            let missed = gshare.predict_and_update(e.addr, e.was_taken) != e.was_taken;
+            if (range.0..range.1).contains(&e.addr) {
+                misses += missed as usize * e.weight;
+            }
+        }
+
+        let mut predictors: Vec<Box<dyn Predictor>> = vec![Box::new(GshareBp::new(10))];
+        let opts = Options {
+            pc_range: Some(range),
+            ..Options::default()
+        };
+        let sim = simulate(&mut predictors, events.iter().copied(), &mut None, &opts);
+        assert_eq!(sim.counts[0].misses, misses);
+        assert_eq!(sim.totals.count + sim.totals.outside, 2 * events.len());
+        assert!(sim.totals.outside > 0 && sim.totals.count > 0);
+        assert_eq!(parse_pc("0x4005d0"), Some(0x4005d0));
+        assert_eq!(parse_pc("4005d0"), None);
+    }
+
+    #[test]
+    fn scores_log_loss() {
+        let events = synthetic_events(20_000, 4, 3);
+        let mut predictors: Vec<Box<dyn Predictor>> = vec![
+            Box::new(NoneTakenBp::new()),
+            Box::new(LocalBp::new(10)),
+            Box::new(ProbabilisticLocalBp::try_new(10, 0).unwrap()),
+        ];
+        let opts = Options {
+            log_loss: true,
+            ..Options::default()
+        };
+        let sim = simulate(&mut predictors, events.iter().copied(), &mut None, &opts);
+        // Always confidently not taken, so 0.1 odds of taken
+        let (taken, count) = (sim.totals.taken as f64, sim.totals.count as f64);
+        let expected = -(taken * 0.1f64.log2() + (count - taken) * 0.9f64.log2());
+        assert_close(sim.counts[0].log_loss, expected, 1e-6);
+        for c in &sim.counts[1..] {
+            assert!(c.log_loss < sim.counts[0].log_loss);
+        }
+    }
+
+    #[test]
+    fn stops_when_stable() {
+        let events: Vec<BranchEvent> = (0..1_000_000)
+            .map(|i| BranchEvent {
+                addr: 0x100 + (i % 8) * 2,
+                was_taken: i % 3 != 0,
+                delta: 4,
+                weight: 1,
+            })
+            .collect();
+        let run = |stop_when_stable| {
+            let mut predictors: Vec<Box<dyn Predictor>> =
+                vec![Box::new(NoneTakenBp::new()), Box::new(GshareBp::new(10))];
+            let opts = Options {
+                stop_when_stable,
+                ..Options::default()
+            };
+            simulate(&mut predictors, events.iter().copied(), &mut None, &opts).totals
+        };
+        // A check to get a rate to compare to, then three stable ones
+        let totals = run(Some((0.1, 3)));
+        assert!(totals.stopped_early);
+        assert_eq!(totals.events, 4 * STABLE_WINDOW);
+        let totals = run(Some((0.0, 20)));
+        assert!(!totals.stopped_early);
+        assert_eq!(totals.events, events.len());
+    }
+
+    #[test]
+    fn windows_mpki_percentiles() {
+        assert_eq!(quantile(&[], 0.5), 0.0);
+        let values = [5.0, 1.0, 4.0, 2.0, 3.0];
+        assert_eq!(quantile(&values, 0.5), 3.0);
+        assert_eq!(quantile(&values, 0.9), 5.0);
+        assert_eq!(quantile(&values, 0.0), 1.0);
+
+        // NoneTaken misses every taken branch, all taken from event 250
+        let events: Vec<BranchEvent> = (0..1_050)
+            .map(|i| BranchEvent {
+                addr: 0x100,
+                was_taken: i >= 250,
+                delta: 9,
+                weight: 1,
+            })
+            .collect();
+        let mut predictors: Vec<Box<dyn Predictor>> = vec![Box::new(NoneTakenBp::new())];
+        let opts = Options {
+            window_percentiles: Some(100),
+            ..Options::default()
+        };
+        let sim = simulate(&mut predictors, events.into_iter(), &mut None, &opts);
+        // The last 50 events don't make a full window
+        assert_eq!(
+            sim.counts[0].window_mpkis,
+            [0.0, 0.0, 50.0, 100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 100.0]
+        );
+    }
+
+    #[test]
+    fn two_bit_limit_is_unaliased_local() {
+        // The addresses fit in a 20 bit table, so nothing aliases there
+        let events = synthetic_events(20_000, 4, 2);
+        let mut predictors: Vec<Box<dyn Predictor>> =
+            vec![Box::new(LocalBp::new(20)), Box::new(LocalBp::new(6))];
+        let opts = Options {
+            two_bit_limit: true,
+            ..Options::default()
+        };
+        let sim = simulate(&mut predictors, events.into_iter(), &mut None, &opts);
+        let (_, limit) = sim.totals.two_bit_limit.unwrap();
+        assert_eq!(limit, sim.counts[0].misses);
+        assert!(limit < sim.counts[1].misses);
+    }
+
+    #[test]
+    fn collapses_repeats() {
+        let events = synthetic_events(10_000, 4, 1);
+        let repeated: Vec<BranchEvent> = events.iter().flat_map(|&e| [e, e, e]).collect();
+        let run = |events: &[BranchEvent], collapse_repeats| {
+            let mut predictors: Vec<Box<dyn Predictor>> = vec![Box::new(GshareBp::new(10))];
+            let opts = Options {
+                collapse_repeats,
+                ..Options::default()
+            };
+            let sim = simulate(&mut predictors, events.iter().copied(), &mut None, &opts);
+            (
+                sim.counts[0].misses,
+                sim.totals.instret,
+                sim.totals.collapsed,
+            )
+        };
+        // The stream's own back to back repeats are collapsed too
+        let (misses, instret, collapsed) = run(&events, true);
+        assert_eq!(
+            run(&repeated, true),
+            (misses, instret, 2 * events.len() + collapsed)
+        );
+        assert_eq!(run(&repeated, false).1, 3 * run(&events, false).1);
+    }
+
+    #[test]
+    fn entropy_bounds_miss_rate() {
+        let contexts = |counts: &[[usize; 2]]| -> HashMap<(usize, usize), [usize; 2]> {
+            counts
+                .iter()
+                .enumerate()
+                .map(|(i, c)| ((i, 0), *c))
+                .collect()
+        };
+        // Determined by the context, then coin flips
+        assert_close(entropy_bound(&contexts(&[[10, 0], [0, 30]])), 0.0, 1e-9);
+        // H_b is flat around 1/2 so that's only found to about 1e-8
+        assert_close(entropy_bound(&contexts(&[[50, 50]])), 0.5, 1e-6);
+        assert_close(entropy_bound(&contexts(&[[90, 10], [90, 10]])), 0.1, 1e-9);
+        // Mixing contexts can only lower the bound below the mean rate
+        assert!(entropy_bound(&contexts(&[[90, 10], [0, 100]])) < 0.05);
+    }
+
+    #[test]
+    fn delta_percentiles() {
+        let mut histogram = vec![0; 10];
+        histogram[2] = 50;
+        histogram[3] = 49;
+        histogram[9] = 1;
+        assert_eq!(percentile(&histogram, 0.5), 2);
+        assert_eq!(percentile(&histogram, 0.51), 3);
+        assert_eq!(percentile(&histogram, 0.99), 3);
+        assert_eq!(percentile(&histogram, 1.0), 9);
+        assert_eq!(percentile(&[0, 0], 0.5), 0);
+    }
+
+    #[test]
+    fn plot_titles() {
+        assert_eq!(utc_date(0), "1970-01-01");
+        assert_eq!(utc_date(951_782_400), "2000-02-29");
+        assert_eq!(utc_date(1_791_936_000), "2026-10-14");
+        let provenance = Provenance {
+            trace: "tommy's.bin".to_string(),
+            date: "2026-10-14".to_string(),
+        };
+        assert_eq!(
+            plot_title(Some("x"), &provenance, 1234567),
+            "'x: tommy''s.bin, 1,234,567 events, 2026-10-14'"
+        );
+        assert_eq!(
+            plot_title(None, &provenance, 5),
+            "'tommy''s.bin, 5 events, 2026-10-14'"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::io::{self, prelude::*};
+
+/// A branch of the trace, with the instructions retired before it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BranchEvent {
+    pub addr: usize,
+    pub was_taken: bool,
+    pub delta: usize,
+    // How many real events this (sampled) event stands in for
+    pub weight: usize,
+}
+
+/*
+ * An event is a little-endian 64-bit word:
+ *
+ *   bit  63      was_taken
+ *   bits 62..48  delta, instructions retired since the previous branch
+ *   bits 47..0   addr
+ *
+ * The taken flag has a bit of its own; it doesn't share the address's
+ * top bit.  Only 48 address bits are kept, which is enough for the
+ * canonical addresses of x86-64, RISC-V Sv48, and AArch64 with 48-bit
+ * VAs: bits 63..48 of such an address are all copies of bit 47, so
+ * decoding sign-extends from bit 47 and high (kernel) addresses such as
+ * 0xffff_8000_0000_0000 come back intact.  An address that isn't
+ * canonical can't be represented, and encode_event refuses it rather
+ * than let it decode as a different address.
+ */
+pub const ADDR_BITS: u32 = 48;
+pub const DELTA_MASK: usize = 0x7FFF;
+
+pub fn decode_event(event: i64) -> (usize, bool, usize) {
+    let addr = ((event << (64 - ADDR_BITS)) >> (64 - ADDR_BITS)) as usize;
+    let was_taken = event < 0;
+    let delta = (event as usize >> ADDR_BITS) & DELTA_MASK;
+    (addr, was_taken, delta)
+}
+
+/// The inverse of decode_event, failing for what it can't represent
+pub fn encode_event(addr: usize, was_taken: bool, delta: usize) -> Result<i64, String> {
+    let canonical = ((addr << (64 - ADDR_BITS)) as i64 >> (64 - ADDR_BITS)) as usize;
+    if canonical != addr {
+        return Err(format!(
+            "address {:#x} isn't a canonical {}-bit address",
+            addr, ADDR_BITS
+        ));
+    }
+    if delta > DELTA_MASK {
+        return Err(format!("delta {} doesn't fit in 15 bits", delta));
+    }
+    let low = addr & ((1 << ADDR_BITS) - 1);
+    Ok(((was_taken as usize) << 63 | delta << ADDR_BITS | low) as i64)
+}
+
+/// Traces start with a header of this many bytes
+pub const HEADER_BYTES: usize = 1024;
+
+/// The events of a trace, read past its header.  With `weighted` each
+/// 8-byte event is followed by its weight as a little-endian u64.  An
+/// event cut short by the end of the trace ends it like a clean end
+/// does, but any other failure to read is an error, after which there
+/// are no more events.  It reads a few bytes at a time, so wants a
+/// buffered reader.
+pub struct EventReader<R> {
+    reader: R,
+    weighted: bool,
+    done: bool,
+}
+
+impl<R: Read> EventReader<R> {
+    pub fn new(reader: R, weighted: bool) -> EventReader<R> {
+        EventReader {
+            reader,
+            weighted,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for EventReader<R> {
+    type Item = io::Result<BranchEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut word = [0; 8];
+        let event = match read_full(&mut self.reader, &mut word) {
+            Ok(true) => read_rest(word, &mut self.reader, self.weighted).transpose(),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        };
+        self.done = !matches!(event, Some(Ok(_)));
+        event
+    }
+}
+
+/// EventReader's next for a file of several runs concatenated, each
+/// starting with its own header: one beginning with `magic`, the first
+/// 8 bytes of the file's header, ends the run.  It's read past and
+/// `new_run` set, so the next call reads the following run's events.
+pub fn read_run_event(
+    reader: &mut impl Read,
+    magic: &[u8; 8],
+    weighted: bool,
+    new_run: &mut bool,
+) -> Option<io::Result<BranchEvent>> {
+    let mut word = [0; 8];
+    match read_full(reader, &mut word) {
+        Ok(true) => {}
+        Ok(false) => return None,
+        Err(e) => return Some(Err(e)),
+    }
+    if word == *magic {
+        let mut rest = [0; HEADER_BYTES - 8];
+        match read_full(reader, &mut rest) {
+            Ok(true) => *new_run = true,
+            Ok(false) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        return None;
+    }
+    read_rest(word, reader, weighted).transpose()
+}
+
+/// Fills `buf`, false if the trace ends first.  A pipe may deliver an
+/// event in pieces, so this insists on all of it.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// The rest of an event, once its word is read
+fn read_rest(
+    word: [u8; 8],
+    reader: &mut impl Read,
+    weighted: bool,
+) -> io::Result<Option<BranchEvent>> {
+    let (addr, was_taken, delta) = decode_event(i64::from_le_bytes(word));
+
+    let mut weight = 1;
+    if weighted {
+        let mut weight_buf = [0; 8];
+        if !read_full(reader, &mut weight_buf)? {
+            return Ok(None);
+        }
+        weight = u64::from_le_bytes(weight_buf) as usize;
+    }
+
+    Ok(Some(BranchEvent {
+        addr,
+        was_taken,
+        delta,
+        weight,
+    }))
+}
+
+/// Writes an event as EventReader reads it, failing for what
+/// encode_event can't represent
+pub fn write_event(w: &mut impl Write, event: &BranchEvent, weighted: bool) -> io::Result<()> {
+    let word = encode_event(event.addr, event.was_taken, event.delta)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    w.write_all(&word.to_le_bytes())?;
+    if weighted {
+        w.write_all(&(event.weight as u64).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_high_addresses() {
+        for (addr, was_taken, delta) in [
+            (0xffff_8000_0000_1000, true, 7),
+            (0xffff_ffff_8100_0ffe, false, 0x7fff),
+            (0x7fff_ffff_fffe, true, 0),
+            (0x8000_0000, false, 3),
+        ] {
+            let event = encode_event(addr, was_taken, delta).unwrap();
+            assert_eq!(decode_event(event), (addr, was_taken, delta));
+        }
+        assert_eq!(decode_event(-1), (usize::MAX, true, 0x7fff));
+    }
+
+    #[test]
+    fn splits_concatenated_runs() {
+        let mut header = [0; HEADER_BYTES];
+        header[..20].copy_from_slice(b"dromajo branch trace");
+        let event = |addr| BranchEvent {
+            addr,
+            was_taken: true,
+            delta: 1,
+            weight: 1,
+        };
+        let mut bytes = vec![];
+        for run in [&[0x10, 0x20][..], &[], &[0x30]] {
+            bytes.extend_from_slice(&header);
+            for &addr in run {
+                write_event(&mut bytes, &event(addr), false).unwrap();
+            }
+        }
+
+        let mut reader = &bytes[HEADER_BYTES..];
+        let magic = header[..8].try_into().unwrap();
+        let mut runs = vec![];
+        loop {
+            let mut new_run = false;
+            let run: Vec<usize> =
+                std::iter::from_fn(|| read_run_event(&mut reader, magic, false, &mut new_run))
+                    .map(|e| e.unwrap().addr)
+                    .collect();
+            runs.push(run);
+            if !new_run {
+                break;
+            }
+        }
+        assert_eq!(runs, [vec![0x10, 0x20], vec![], vec![0x30]]);
+    }
+
+    #[test]
+    fn round_trips_edge_cases() {
+        let max_addr = (1 << (ADDR_BITS - 1)) - 1;
+        for (addr, was_taken, delta) in [
+            (0, false, 0),
+            (0, true, 0),
+            (max_addr, true, DELTA_MASK),
+            (max_addr, false, 1),
+            (!max_addr, true, DELTA_MASK),
+            (0x1000, false, DELTA_MASK),
+        ] {
+            let word = encode_event(addr, was_taken, delta).unwrap();
+            let bytes = word.to_le_bytes();
+            let event = EventReader::new(&bytes[..], false).next().unwrap().unwrap();
+            assert_eq!(
+                (event.addr, event.was_taken, event.delta),
+                (addr, was_taken, delta)
+            );
+        }
+        // Counts past the field are the generator's to saturate
+        assert!(encode_event(0x1000, true, DELTA_MASK + 1).is_err());
+        assert!(encode_event(max_addr + 1, true, 0).is_err());
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let events = [
+            BranchEvent {
+                addr: 0xffff_8000_0000_1000,
+                was_taken: true,
+                delta: 7,
+                weight: 3,
+            },
+            BranchEvent {
+                addr: 0x1000,
+                was_taken: false,
+                delta: 0,
+                weight: 1,
+            },
+        ];
+        for weighted in [false, true] {
+            let mut bytes = vec![];
+            for e in &events {
+                write_event(&mut bytes, e, weighted).unwrap();
+            }
+            let read: Vec<BranchEvent> = EventReader::new(&bytes[..], weighted)
+                .collect::<io::Result<_>>()
+                .unwrap();
+            let weight = |e: &BranchEvent| if weighted { e.weight } else { 1 };
+            let written: Vec<BranchEvent> = events
+                .iter()
+                .map(|e| BranchEvent {
+                    weight: weight(e),
+                    ..*e
+                })
+                .collect();
+            assert_eq!(read, written);
+        }
+    }
+
+    #[test]
+    fn stops_at_the_end_or_an_error() {
+        let event = BranchEvent {
+            addr: 0x1000,
+            was_taken: true,
+            delta: 2,
+            weight: 5,
+        };
+        let mut bytes = vec![];
+        write_event(&mut bytes, &event, true).unwrap();
+        write_event(&mut bytes, &event, true).unwrap();
+        // A clean end, and the second event cut short in its word or weight
+        for len in [16, 17, 23] {
+            let events: Vec<_> = EventReader::new(&bytes[..len], true)
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(events, [event]);
+        }
+
+        struct Failing<'a>(&'a [u8]);
+        impl Read for Failing<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::other("disk on fire"));
+                }
+                self.0.read(buf)
+            }
+        }
+        let mut events = EventReader::new(Failing(&bytes[..16]), true);
+        assert_eq!(events.next().unwrap().unwrap(), event);
+        assert_eq!(
+            events.next().unwrap().unwrap_err().to_string(),
+            "disk on fire"
+        );
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn rejects_unrepresentable_events() {
+        // Bit 47 set without the upper bits following it
+        assert!(encode_event(0x8000_0000_1000, true, 1).is_err());
+        assert!(encode_event(0x1_0000_0000_0000, false, 1).is_err());
+        assert!(encode_event(0x1000, false, 0x8000).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::io::Write;
+
+/*
+ * A dataset of the trace for training models offline, one CSV row per
+ * branch event:
+ *
+ *   pc,h1..hN,pc1..pcN,taken[,weight]
+ *
+ * `pc` is the low PC_BITS bits of the branch address (bit 0 dropped,
+ * as the predictors do), h1..hN the N most recent outcomes (1 taken),
+ * most recent first, and pc1..pcN the same low bits of the branches
+ * they belong to.  Before N branches have gone by the missing columns
+ * are 0.  The label is `taken`; `weight` only appears for weighted
+ * traces.
+ */
+
+pub const PC_BITS: usize = 16;
+
+pub struct FeatureWriter<W: Write> {
+    out: W,
+    depth: usize,
+    weighted: bool,
+    recent: VecDeque<(usize, bool)>,
+}
+
+impl<W: Write> FeatureWriter<W> {
+    /// Writes the header row straight away
+    pub fn new(mut out: W, depth: usize, weighted: bool) -> std::io::Result<FeatureWriter<W>> {
+        let mut columns = vec!["pc".to_string()];
+        columns.extend((1..=depth).map(|i| format!("h{}", i)));
+        columns.extend((1..=depth).map(|i| format!("pc{}", i)));
+        columns.push("taken".to_string());
+        if weighted {
+            columns.push("weight".to_string());
+        }
+        writeln!(out, "{}", columns.join(","))?;
+
+        Ok(FeatureWriter {
+            out,
+            depth,
+            weighted,
+            recent: VecDeque::with_capacity(depth + 1),
+        })
+    }
+
+    pub fn record(&mut self, addr: usize, was_taken: bool, weight: usize) -> std::io::Result<()> {
+        let pc = |addr: usize| (addr >> 1) & ((1 << PC_BITS) - 1);
+        let missing = self.depth - self.recent.len();
+
+        write!(self.out, "{}", pc(addr))?;
+        for &(_, taken) in &self.recent {
+            write!(self.out, ",{}", taken as u8)?;
+        }
+        for _ in 0..missing {
+            write!(self.out, ",0")?;
+        }
+        for &(addr, _) in &self.recent {
+            write!(self.out, ",{}", pc(addr))?;
+        }
+        for _ in 0..missing {
+            write!(self.out, ",0")?;
+        }
+        write!(self.out, ",{}", was_taken as u8)?;
+        if self.weighted {
+            write!(self.out, ",{}", weight)?;
+        }
+        writeln!(self.out)?;
+
+        if self.depth > 0 {
+            if self.recent.len() == self.depth {
+                self.recent.pop_back();
+            }
+            self.recent.push_front((addr, was_taken));
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_recent_history() {
+        let mut csv = vec![];
+        let mut w = FeatureWriter::new(&mut csv, 2, true).unwrap();
+        w.record(0x10, true, 1).unwrap();
+        w.record(0x20, false, 3).unwrap();
+        w.record(0x3_0004, true, 1).unwrap();
+        w.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "pc,h1,h2,pc1,pc2,taken,weight\n\
+             8,0,0,0,0,1,1\n\
+             16,1,0,8,0,0,3\n\
+             32770,0,1,16,8,1,1\n"
+        );
+    }
+}
@@ -0,0 +1,511 @@
+/*
+ * GlobalHistory is the global branch history shift register shared by
+ * the history based predictors.  By default it's updated in retire
+ * order, that is, with the actual outcome once it's known.
+ *
+ * Real frontends instead shift in the *predicted* direction at fetch
+ * and repair the register from the architectural copy when a
+ * misprediction resolves.  As the traces are correct path only and
+ * resolution is immediate, the two policies see the same history after
+ * repair, but the bookkeeping is modelled so it can be combined with
+ * delayed resolution.
+ *
+ * A frontend fetching K branches a cycle predicts all of them with the
+ * history from before the block, so with a fetch width above one the
+ * history seen by predictions only advances every `fetch_width`
+ * branches, or on a misprediction under the speculative policy, as the
+ * redirect starts a new block.
+ */
+
+/// The width of the history registers, fixed rather than the width
+/// of usize so a run gives the same results on 32- and 64-bit hosts
+pub const HISTORY_BITS: usize = 32;
+const HISTORY_MASK: usize = ((1u64 << HISTORY_BITS) - 1) as usize;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum HistoryPolicy {
+    #[default]
+    Retire,
+    Speculative,
+}
+
+impl std::str::FromStr for HistoryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "retire" => Ok(HistoryPolicy::Retire),
+            "speculative" => Ok(HistoryPolicy::Speculative),
+            _ => Err(format!("unknown history policy {}", s)),
+        }
+    }
+}
+
+/// The history a prediction was made with, for its update to be
+/// applied later to the same counters, see Predictor::history_snapshot
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum HistorySnapshot {
+    /// Of a predictor without a global history
+    #[default]
+    None,
+    /// GlobalHistory::value
+    Global(usize),
+    /// GlobalHistory::value and the fold of the previous PCs, see
+    /// PrevPcGshareBp
+    Path(usize, usize),
+    /// The folds of a HistorySet
+    Folds(Vec<usize>),
+    /// Those of the components of a combining predictor, in order
+    Components(Vec<HistorySnapshot>),
+}
+
+impl HistorySnapshot {
+    pub fn global(&self) -> Option<usize> {
+        match *self {
+            HistorySnapshot::Global(history) => Some(history),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GlobalHistory {
+    policy: HistoryPolicy,
+    speculative: usize,
+    architectural: usize,
+    fetch_width: usize,
+    // Branches of the current fetch block so far, and the history they
+    // are predicted with
+    fetched: usize,
+    block: usize,
+    // The hardware thread whose history this is, and the (speculative,
+    // architectural) history of the others, see set_thread
+    thread: usize,
+    parked: Vec<(usize, usize)>,
+    // The low bits cleared on a misprediction, see set_reset_on_miss
+    reset_on_miss: usize,
+    // What value gives instead, see pin
+    pinned: Option<usize>,
+}
+
+impl Default for GlobalHistory {
+    fn default() -> GlobalHistory {
+        GlobalHistory::new()
+    }
+}
+
+impl GlobalHistory {
+    pub fn new() -> GlobalHistory {
+        GlobalHistory {
+            policy: HistoryPolicy::Retire,
+            speculative: 0,
+            architectural: 0,
+            fetch_width: 1,
+            fetched: 0,
+            block: 0,
+            thread: 0,
+            parked: vec![],
+            reset_on_miss: 0,
+            pinned: None,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: HistoryPolicy) {
+        self.policy = policy;
+        self.speculative = self.architectural;
+        self.start_block();
+    }
+
+    pub fn policy(&self) -> HistoryPolicy {
+        self.policy
+    }
+
+    pub fn set_fetch_width(&mut self, width: usize) {
+        assert!(width > 0);
+        self.fetch_width = width;
+        self.start_block();
+    }
+
+    /// Switches to the history of hardware thread `thread`, parking the
+    /// current thread's, so each thread only sees its own outcomes
+    pub fn set_thread(&mut self, thread: usize) {
+        if thread == self.thread {
+            return;
+        }
+        let threads = thread.max(self.thread) + 1;
+        if self.parked.len() < threads {
+            self.parked.resize(threads, (0, 0));
+        }
+        self.parked[self.thread] = (self.speculative, self.architectural);
+        (self.speculative, self.architectural) = self.parked[thread];
+        self.thread = thread;
+        self.start_block();
+    }
+
+    /// Clears the low `bits` of the history on each misprediction, as
+    /// some pipelines lose part of it recovering.  Both the speculative
+    /// and the architectural copies are cleared, so under the
+    /// speculative policy the repaired history is the cleared one.
+    pub fn set_reset_on_miss(&mut self, bits: usize) {
+        self.reset_on_miss = bits.min(HISTORY_BITS);
+    }
+
+    pub fn reset_on_miss(&self) -> usize {
+        self.reset_on_miss
+    }
+
+    fn current(&self) -> usize {
+        match self.policy {
+            HistoryPolicy::Retire => self.architectural,
+            HistoryPolicy::Speculative => self.speculative,
+        }
+    }
+
+    fn start_block(&mut self) {
+        self.fetched = 0;
+        self.block = self.current();
+    }
+
+    /// Has value give `pinned`, an earlier value, until unpinned with
+    /// None, while updates still advance the history underneath, so an
+    /// update applied late indexes as its prediction did
+    pub fn pin(&mut self, pinned: Option<usize>) {
+        self.pinned = pinned;
+    }
+
+    /// The history the next prediction is made with
+    pub fn value(&self) -> usize {
+        if let Some(pinned) = self.pinned {
+            pinned
+        } else if self.fetch_width == 1 {
+            self.current()
+        } else {
+            self.block
+        }
+    }
+
+    /// Fetch: shift the predicted direction into the speculative history
+    pub fn speculate(&mut self, predicted: bool) {
+        self.speculative = (self.speculative << 1 | predicted as usize) & HISTORY_MASK;
+    }
+
+    /// Resolve: shift in the outcome and repair the speculative history
+    /// if it went down the wrong path
+    pub fn retire(&mut self, predicted: bool, was_taken: bool) {
+        self.architectural = (self.architectural << 1 | was_taken as usize) & HISTORY_MASK;
+        if predicted != was_taken {
+            self.speculative = self.architectural;
+        }
+    }
+
+    pub fn update(&mut self, predicted: bool, was_taken: bool) {
+        if self.policy == HistoryPolicy::Speculative {
+            self.speculate(predicted);
+        }
+        self.retire(predicted, was_taken);
+
+        let reset = self.reset_on_miss > 0 && predicted != was_taken;
+        if reset {
+            let kept = !((1u64 << self.reset_on_miss) - 1) as usize;
+            self.architectural &= kept;
+            self.speculative &= kept;
+        }
+
+        self.fetched += 1;
+        let redirected = self.policy == HistoryPolicy::Speculative && predicted != was_taken;
+        if self.fetched == self.fetch_width || redirected || reset {
+            self.start_block();
+        }
+    }
+}
+
+/*
+ * FoldedHistory keeps the last `length` outcomes folded (XORed in
+ * `width` bit chunks) down to a `width` bit index or tag.  Rather than
+ * recomputing the fold for each prediction, it's maintained
+ * incrementally as a circular shift register, as in TAGE: the newest
+ * outcome is shifted in, the one falling off the end is XORed out at
+ * the position it folded to, and the bit shifted out at the top wraps
+ * around to the bottom.
+ */
+#[derive(Clone)]
+pub struct FoldedHistory {
+    length: usize,
+    width: usize,
+    outcomes: Vec<bool>,
+    head: usize,
+    folded: usize,
+}
+
+impl FoldedHistory {
+    pub fn new(length: usize, width: usize) -> FoldedHistory {
+        assert!(0 < width && width < usize::BITS as usize);
+        FoldedHistory {
+            length,
+            width,
+            outcomes: vec![false; length],
+            head: 0,
+            folded: 0,
+        }
+    }
+
+    pub fn update(&mut self, taken: bool) {
+        if self.length == 0 {
+            return;
+        }
+
+        let outgoing = self.outcomes[self.head];
+        self.outcomes[self.head] = taken;
+        self.head = (self.head + 1) % self.length;
+
+        self.folded = self.folded << 1 | taken as usize;
+        self.folded ^= (outgoing as usize) << (self.length % self.width);
+        self.folded ^= self.folded >> self.width;
+        self.folded &= (1 << self.width) - 1;
+    }
+
+    pub fn fold(&self) -> usize {
+        self.folded
+    }
+}
+
+/*
+ * HistorySet is the folded histories of one predictor, all updated
+ * together with each outcome, so that eg. the index can use an 8-bit
+ * fold of 20 outcomes while the tag uses a 16-bit fold of the same 20,
+ * or each component of a TAGE its own length.
+ */
+#[derive(Clone, Default)]
+pub struct HistorySet {
+    folds: Vec<FoldedHistory>,
+    // What fold gives instead, see pin
+    pinned: Option<Vec<usize>>,
+}
+
+/// Which fold of a HistorySet
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FoldId(usize);
+
+impl HistorySet {
+    pub fn new() -> HistorySet {
+        HistorySet::default()
+    }
+
+    /// Adds a fold of the last `length` outcomes to `width` bits
+    pub fn add(&mut self, length: usize, width: usize) -> FoldId {
+        self.folds.push(FoldedHistory::new(length, width));
+        FoldId(self.folds.len() - 1)
+    }
+
+    pub fn update(&mut self, taken: bool) {
+        for f in self.folds.iter_mut() {
+            f.update(taken);
+        }
+    }
+
+    pub fn fold(&self, id: FoldId) -> usize {
+        match &self.pinned {
+            Some(pinned) => pinned[id.0],
+            None => self.folds[id.0].fold(),
+        }
+    }
+
+    pub fn snapshot(&self) -> HistorySnapshot {
+        HistorySnapshot::Folds(
+            (0..self.folds.len())
+                .map(|i| self.fold(FoldId(i)))
+                .collect(),
+        )
+    }
+
+    /// As GlobalHistory::pin, for the folds of `snapshot`
+    pub fn pin(&mut self, snapshot: Option<&HistorySnapshot>) {
+        self.pinned = match snapshot {
+            Some(HistorySnapshot::Folds(folds)) => Some(folds.clone()),
+            _ => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Outcome `i` branches ago folds to bit `i % width`
+    fn naive_fold(outcomes: &[bool], length: usize, width: usize) -> usize {
+        outcomes
+            .iter()
+            .rev()
+            .take(length)
+            .enumerate()
+            .fold(0, |f, (i, &taken)| f ^ (taken as usize) << (i % width))
+    }
+
+    #[test]
+    fn folded_history_matches_naive() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for &(length, width) in &[(64, 10), (130, 11), (8, 12), (5, 5), (33, 1), (0, 4)] {
+            let mut folded = FoldedHistory::new(length, width);
+            let mut outcomes = vec![];
+            for _ in 0..1000 {
+                let taken = rng.gen();
+                folded.update(taken);
+                outcomes.push(taken);
+                assert_eq!(
+                    folded.fold(),
+                    naive_fold(&outcomes, length, width),
+                    "length {} width {}",
+                    length,
+                    width
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn history_is_fixed_width() {
+        let mut retire = GlobalHistory::new();
+        let mut speculative = GlobalHistory::new();
+        speculative.set_policy(HistoryPolicy::Speculative);
+        for i in 0..1000 {
+            retire.update(i % 7 != 0, true);
+            speculative.speculate(true);
+            assert!(retire.value() <= HISTORY_MASK);
+            assert!(speculative.value() <= HISTORY_MASK);
+        }
+        // All taken, and only as many of them as the register holds
+        assert_eq!(retire.value().count_ones() as usize, HISTORY_BITS);
+        assert_eq!(speculative.value(), HISTORY_MASK);
+    }
+
+    #[test]
+    fn history_set_folds_independently() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut set = HistorySet::new();
+        let index = set.add(20, 8);
+        let tag = set.add(20, 16);
+        let long = set.add(130, 11);
+        let mut outcomes = vec![];
+        for _ in 0..1000 {
+            let taken = rng.gen();
+            set.update(taken);
+            outcomes.push(taken);
+            assert_eq!(set.fold(index), naive_fold(&outcomes, 20, 8));
+            assert_eq!(set.fold(tag), naive_fold(&outcomes, 20, 16));
+            assert_eq!(set.fold(long), naive_fold(&outcomes, 130, 11));
+        }
+    }
+
+    #[test]
+    fn pinned_history_still_advances() {
+        let mut h = GlobalHistory::new();
+        h.update(true, true);
+        h.pin(Some(0b10));
+        h.update(true, true);
+        assert_eq!(h.value(), 0b10);
+        h.pin(None);
+        assert_eq!(h.value(), 0b11);
+
+        let mut set = HistorySet::new();
+        let fold = set.add(4, 4);
+        set.update(true);
+        let snapshot = set.snapshot();
+        set.update(true);
+        set.pin(Some(&snapshot));
+        assert_eq!(set.fold(fold), 0b1);
+        set.pin(None);
+        assert_eq!(set.fold(fold), 0b11);
+    }
+
+    #[test]
+    fn retire_order() {
+        let mut h = GlobalHistory::new();
+        h.update(false, true);
+        h.update(true, false);
+        h.update(true, true);
+        assert_eq!(h.value(), 0b101);
+    }
+
+    #[test]
+    fn fetch_blocks_share_history() {
+        let mut h = GlobalHistory::new();
+        h.set_fetch_width(2);
+        h.update(true, true);
+        assert_eq!(h.value(), 0);
+        h.update(true, true);
+        assert_eq!(h.value(), 0b11);
+        h.update(false, false);
+        assert_eq!(h.value(), 0b11);
+
+        // A misprediction redirects fetch, starting a new block
+        h.set_policy(HistoryPolicy::Speculative);
+        h.update(true, true);
+        assert_eq!(h.value(), 0b110);
+        h.update(true, false);
+        assert_eq!(h.value(), 0b11010);
+    }
+
+    #[test]
+    fn threads_keep_own_history() {
+        let mut h = GlobalHistory::new();
+        h.update(true, true);
+        h.set_thread(1);
+        assert_eq!(h.value(), 0);
+        h.update(false, false);
+        h.update(true, true);
+        assert_eq!(h.value(), 0b01);
+        h.set_thread(0);
+        assert_eq!(h.value(), 0b1);
+        h.set_thread(1);
+        assert_eq!(h.value(), 0b01);
+    }
+
+    #[test]
+    fn misses_clear_low_history() {
+        for policy in [HistoryPolicy::Retire, HistoryPolicy::Speculative] {
+            let mut h = GlobalHistory::new();
+            h.set_policy(policy);
+            h.set_reset_on_miss(2);
+            for _ in 0..4 {
+                h.update(true, true);
+            }
+            assert_eq!(h.value(), 0b1111);
+            h.update(false, true);
+            assert_eq!(h.value(), 0b11100);
+            h.update(true, true);
+            assert_eq!(h.value(), 0b111001);
+        }
+    }
+
+    #[test]
+    fn speculative_repair() {
+        let mut h = GlobalHistory::new();
+        h.set_policy(HistoryPolicy::Speculative);
+
+        // Two branches in flight, the second predicted wrong
+        h.speculate(true);
+        h.speculate(true);
+        assert_eq!(h.value(), 0b11);
+
+        h.retire(true, true);
+        assert_eq!(h.value(), 0b11);
+        h.retire(true, false);
+        assert_eq!(h.value(), 0b10);
+    }
+
+    #[test]
+    fn policies_agree_on_correct_path() {
+        let mut retire = GlobalHistory::new();
+        let mut speculative = GlobalHistory::new();
+        speculative.set_policy(HistoryPolicy::Speculative);
+
+        for i in 0..100 {
+            let (predicted, was_taken) = (i % 3 == 0, i % 5 < 2);
+            retire.update(predicted, was_taken);
+            speculative.update(predicted, was_taken);
+            assert_eq!(retire.value(), speculative.value());
+        }
+    }
+}
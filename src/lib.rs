@@ -0,0 +1,26 @@
+/*
+ * The branch predictors and the simulator driving them over traces.
+ * The `bp` binary is just cli::main; a crate of its own predictors
+ * can depend on this library, implement predictor::Predictor, and
+ * run the same command line with them added to the registry:
+ *
+ *   fn main() {
+ *       bp::registry::register(my_predictor::ENTRY).unwrap();
+ *       bp::cli::main();
+ *   }
+ */
+
+pub mod bias;
+pub mod cli;
+pub mod event;
+mod features;
+pub mod history;
+mod json;
+pub mod predictor;
+mod profile;
+pub mod registry;
+pub mod sram;
+mod synthetic;
+mod verify;
+pub mod weighted_bool;
+mod wrappers;
@@ -1,591 +1,3319 @@
+use crate::bias::BiasProfile;
+use crate::history::*;
+use crate::sram::BitWriter;
 use crate::weighted_bool::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// A configuration's canonical name, `name/param,..` in lower case, eg.
+/// `gshare/14` or `yags1[alloc=50%]/13,11,6`.  It depends only on
+/// the report's name and config, which together tell configs apart.
+pub fn predictor_id(name: &str, config: &[usize]) -> String {
+    let name = name.to_ascii_lowercase();
+    if config.is_empty() {
+        return name;
+    }
+    let config: Vec<String> = config.iter().map(|c| c.to_string()).collect();
+    format!("{}/{}", name, config.join(","))
+}
+
+/// The odds of taken a prediction graded as by
+/// Predictor::predict_with_confidence stands for
+pub fn graded_probability((taken, confident): (bool, bool)) -> f64 {
+    match (taken, confident) {
+        (true, true) => 0.9,
+        (true, false) => 0.6,
+        (false, false) => 0.4,
+        (false, true) => 0.1,
+    }
+}
+
+/// Implements the Predictor methods that set up and snapshot the
+/// GlobalHistory in the named field, eg. `delegate_history!(history)`.
+/// `delegate_history!(history, setters)` leaves history_snapshot and
+/// pin_history to a predictor whose index takes more than the history.
+macro_rules! delegate_history {
+    ($history:ident, setters) => {
+        fn set_history_policy(&mut self, policy: HistoryPolicy) {
+            self.$history.set_policy(policy);
+        }
+
+        fn set_fetch_width(&mut self, width: usize) {
+            self.$history.set_fetch_width(width);
+        }
+
+        fn set_thread(&mut self, thread: usize) {
+            self.$history.set_thread(thread);
+        }
+
+        fn set_history_reset_on_miss(&mut self, bits: usize) {
+            self.$history.set_reset_on_miss(bits);
+        }
+    };
+    ($history:ident) => {
+        delegate_history!($history, setters);
+
+        fn history_snapshot(&self) -> HistorySnapshot {
+            HistorySnapshot::Global(self.$history.value())
+        }
+
+        fn pin_history(&mut self, snapshot: Option<&HistorySnapshot>) {
+            self.$history
+                .pin(snapshot.and_then(HistorySnapshot::global));
+        }
+    };
+}
+
+/// A predictor implements query, what it predicts, and update, how it
+/// learns, the rest defaulting to those
+pub trait Predictor {
+    /// Feeds a single event, returning the prediction made before
+    /// learning `was_taken`, as online use such as a learning loop
+    /// outside the trace harness wants
+    fn predict_and_update(&mut self, addr: usize, was_taken: bool) -> bool {
+        self.predict_with_confidence(addr, was_taken).0
+    }
+
+    /// Learns `was_taken` for `addr`, `predicted` being what predict
+    /// gave for it just before.  A speculative GlobalHistory shifts in
+    /// the prediction rather than the outcome, and a combiner such as
+    /// Tournament passes each component its own prediction, having
+    /// consulted them all before any of them learns.
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool);
+
+    /// predict_and_update that also grades the prediction, true when it
+    /// came from a strong counter, as query grades it.  Predictors that
+    /// find a branch once for both override it.
+    fn predict_with_confidence(&mut self, addr: usize, was_taken: bool) -> (bool, bool) {
+        let prediction = self.query(addr);
+        self.update(addr, prediction.0, was_taken);
+        prediction
+    }
+
+    /// What it would predict for `addr` now, graded as by
+    /// predict_with_confidence, without changing any state
+    fn query(&self, addr: usize) -> (bool, bool);
+
+    /// The prediction for `addr` with the tables and history as they
+    /// are, learning nothing, eg. for exploring a trained predictor
+    fn predict(&self, addr: usize) -> bool {
+        self.query(addr).0
+    }
+
+    /// The odds it gives `addr` being taken, learning nothing, for
+    /// scoring how well calibrated its confidence is
+    fn predict_prob(&self, addr: usize) -> f64 {
+        graded_probability(self.query(addr))
+    }
+
+    /// predict_and_update over a run of (addr, was_taken), returning the
+    /// predictions; some predictors do it faster than one at a time
+    fn predict_and_update_batch(&mut self, events: &[(usize, bool)]) -> Vec<bool> {
+        events
+            .iter()
+            .map(|&(addr, was_taken)| self.predict_and_update(addr, was_taken))
+            .collect()
+    }
+
+    // (name, config, size in bits)
+    fn report(&self) -> (String, Vec<usize>, usize);
+
+    /// The parameters it was built with, named as in the registry, eg.
+    /// `[("addr_bits", 13), ("dir_bits", 7), ("tag_bits", 6)]`; the
+    /// values are the report's config
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![]
+    }
+
+    /// The key of its results across runs, see predictor_id
+    fn id(&self) -> String {
+        let (name, config, _) = self.report();
+        predictor_id(&name, &config)
+    }
+
+    /// The host memory it takes to simulate, which isn't the size the
+    /// hardware would need as eg. a two-bit counter takes a byte
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
+    /// The states (0..=3) of the counters in the main table, if any
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(std::iter::empty())
+    }
+
+    fn set_history_policy(&mut self, _policy: HistoryPolicy) {}
+
+    /// Seeds the counters indexed by address alone from a profile of
+    /// the branches' bias; those indexed by history are left cold
+    fn init_from_bias(&mut self, _profile: &BiasProfile) {}
+
+    /// Predicts `width` branches at a time with the same global history
+    fn set_fetch_width(&mut self, _width: usize) {}
+
+    /// Predicts for hardware thread `thread` with its own global
+    /// history from now on, the tables staying shared
+    fn set_thread(&mut self, _thread: usize) {}
+
+    /// See GlobalHistory::set_reset_on_miss
+    fn set_history_reset_on_miss(&mut self, _bits: usize) {}
+
+    /// The global history the next prediction is made with, for an
+    /// update held back past later predictions to index by, see
+    /// DelayedBp
+    fn history_snapshot(&self) -> HistorySnapshot {
+        HistorySnapshot::None
+    }
+
+    /// Has predictions and updates index by `snapshot`, taken by
+    /// history_snapshot earlier, rather than by the current history,
+    /// which updates still advance, until unpinned with None
+    fn pin_history(&mut self, _snapshot: Option<&HistorySnapshot>) {}
+
+    /// The SRAM port accounting, for predictors that keep it
+    fn port_stats(&self) -> Option<PortStats> {
+        None
+    }
+
+    /// The times the prediction for a branch changed since its previous
+    /// occurrence, and those occurrences, as counted by Stability
+    fn stability(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Starts recording where the next `events` predictions came from,
+    /// false for predictors that don't arbitrate between tables
+    fn log_arbitration(&mut self, _events: usize) -> bool {
+        false
+    }
+
+    fn arbitration_log(&self) -> &[Arbitration] {
+        &[]
+    }
+
+    /// Reallocates the tables for `new_bits` of index mid-run, either
+    /// cold or, with `preserve`, filled from the entries they alias
+    fn resize(&mut self, _new_bits: usize, _preserve: bool) -> Result<(), String> {
+        Err("isn't resizable".to_string())
+    }
+
+    /// Writes the tables as a bit-exact SRAM image, see sram.rs
+    fn dump_sram(&self, _w: &mut dyn Write) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "no SRAM mapping",
+        ))
+    }
+}
+
+/// The table a Bimodal or YAGS prediction was read from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Provider {
+    Choice,
+    DirectionTaken,
+    DirectionNotTaken,
+    Way(usize),
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Provider::Choice => write!(f, "choice"),
+            Provider::DirectionTaken => write!(f, "direction-t"),
+            Provider::DirectionNotTaken => write!(f, "direction-nt"),
+            Provider::Way(n) => write!(f, "way{}", n),
+        }
+    }
+}
+
+/// How a prediction was arbitrated, with the states as they were
+/// before the update
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Arbitration {
+    pub addr: usize,
+    pub choice: u8,
+    pub provider: Provider,
+    pub state: u8,
+    /// The way whose tag matched, even if it wasn't trusted
+    pub tag_hit: Option<usize>,
+    pub was_taken: bool,
+}
+
+/// The first `limit` arbitrations, see log_arbitration
+#[derive(Default)]
+struct ArbitrationLog {
+    limit: usize,
+    entries: Vec<Arbitration>,
+}
+
+impl ArbitrationLog {
+    fn start(&mut self, events: usize) {
+        self.limit = events;
+        self.entries = Vec::with_capacity(events);
+    }
+
+    fn wants(&self) -> bool {
+        self.entries.len() < self.limit
+    }
+}
+
+/// SRAM accesses made by the updates, against a per event budget of
+/// write ports; an update needing more writes would stall
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PortStats {
+    pub events: usize,
+    pub reads: usize,
+    pub writes: usize,
+    pub write_ports: usize,
+    pub stalls: usize,
+}
+
+impl PortStats {
+    fn record(&mut self, reads: usize, writes: usize) {
+        self.events += 1;
+        self.reads += reads;
+        self.writes += writes;
+        self.stalls += (writes > self.write_ports) as usize;
+    }
+}
+
+/// A table too big to allocate, the first of a predictor's to fail
+#[derive(Debug)]
+pub struct AllocError {
+    pub bytes: f64,
+}
+
+impl std::fmt::Display for AllocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "needs a {:.1} GiB table",
+            self.bytes / (1u64 << 30) as f64
+        )
+    }
+}
+
+/// A table of `1 << bits` copies of `init`, reporting failure rather
+/// than aborting the process when it can't be allocated
+fn table<T: Clone>(bits: usize, init: T) -> Result<Vec<T>, AllocError> {
+    let error = || AllocError {
+        bytes: 2f64.powi(bits as i32) * std::mem::size_of::<T>() as f64,
+    };
+    let entries = u32::try_from(bits)
+        .ok()
+        .and_then(|bits| 1usize.checked_shl(bits))
+        .ok_or_else(error)?;
+    let mut table = Vec::new();
+    table.try_reserve_exact(entries).map_err(|_| error())?;
+    table.resize(entries, init);
+    Ok(table)
+}
+
+/// The heap bytes of a table, for footprint_bytes
+fn table_bytes<T>(table: &Vec<T>) -> usize {
+    table.capacity() * std::mem::size_of::<T>()
+}
+
+/// The storage the hardware needs for a table of counters, for report
+fn table_bits<C: Boolish>(table: &[C]) -> usize {
+    table.len() * C::BITS
+}
+
+/// A table of `1 << bits` counters, either cold or with each entry
+/// taken from the one it aliased in `old`
+fn resized<C: Boolish + Copy>(old: &[C], bits: usize, preserve: bool) -> Vec<C> {
+    (0..1 << bits)
+        .map(|i| {
+            if preserve {
+                old[i % old.len()]
+            } else {
+                C::new(true)
+            }
+        })
+        .collect()
+}
+
+/*
+ * The `1 << bits` counters a predictor indexes, starting out weakly
+ * taken.  Indices are masked down to the table, so callers pass the
+ * hash they index by.  YAGS's direction ways alone keep Vecs, each
+ * counter sharing its index with the tag, u and valid Vecs beside it,
+ * while TAGE keeps whole entries.
+ */
+pub struct CounterTable<C> {
+    bits: usize,
+    counters: Vec<C>,
+}
+
+impl<C: Boolish + Copy> CounterTable<C> {
+    pub fn try_new(bits: usize) -> Result<CounterTable<C>, AllocError> {
+        Ok(CounterTable {
+            bits,
+            counters: table(bits, C::new(true))?,
+        })
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    /// Where `hash` lands in the table
+    pub fn index(&self, hash: usize) -> usize {
+        hash & ((1 << self.bits) - 1)
+    }
+
+    pub fn get(&self, hash: usize) -> C {
+        self.counters[self.index(hash)]
+    }
+
+    pub fn set(&mut self, hash: usize, counter: C) {
+        let index = self.index(hash);
+        self.counters[index] = counter;
+    }
+
+    pub fn get_mut(&mut self, hash: usize) -> &mut C {
+        let index = self.index(hash);
+        &mut self.counters[index]
+    }
+
+    pub fn predict(&self, hash: usize) -> bool {
+        self.get(hash).value()
+    }
+
+    /// Hints that the counter `hash` lands on is wanted soon, for
+    /// batches to overlap the cache misses of the events ahead
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    pub fn prefetch(&self, hash: usize) {
+        use std::arch::x86_64::*;
+
+        // SSE is part of x86-64, and prefetching can't fault
+        unsafe {
+            let counter = self.counters.as_ptr().add(self.index(hash));
+            _mm_prefetch::<_MM_HINT_T0>(counter as *const i8);
+        }
+    }
+
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    pub fn prefetch(&self, hash: usize) {
+        let _ = hash;
+    }
+
+    pub fn update(&mut self, hash: usize, taken: bool) {
+        let index = self.index(hash);
+        self.counters[index].update(taken);
+    }
+
+    /// The storage the hardware needs
+    pub fn size_bits(&self) -> usize {
+        table_bits(&self.counters)
+    }
+
+    /// The heap bytes, for footprint_bytes
+    pub fn heap_bytes(&self) -> usize {
+        table_bytes(&self.counters)
+    }
+
+    /// The counters in index order
+    pub fn counters(&self) -> &[C] {
+        &self.counters
+    }
+
+    pub fn counters_mut(&mut self) -> &mut [C] {
+        &mut self.counters
+    }
+
+    /// The states in index order, for table_states
+    pub fn states(&self) -> impl Iterator<Item = u8> + '_ {
+        self.counters.iter().map(|c| c.state())
+    }
+
+    /// See resized
+    pub fn resize(&mut self, bits: usize, preserve: bool) {
+        self.counters = resized(&self.counters, bits, preserve);
+        self.bits = bits;
+    }
+}
+
+/// Writes a table of counters as a 2 bit per entry SRAM image
+fn dump_counters<W: Write + ?Sized>(
+    table: &[TwoBitCounter],
+    w: &mut BitWriter<W>,
+) -> std::io::Result<()> {
+    for c in table {
+        w.write(c.state() as usize, 2)?;
+    }
+    w.align()
+}
+
+#[derive(Default)]
+pub struct NoneTakenBp {}
+
+impl NoneTakenBp {
+    pub fn new() -> NoneTakenBp {
+        NoneTakenBp {}
+    }
+}
+
+impl Predictor for NoneTakenBp {
+    fn update(&mut self, _addr: usize, _predicted: bool, _was_taken: bool) {}
+
+    fn query(&self, _addr: usize) -> (bool, bool) {
+        (false, true)
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        ("NoneTaken".to_string(), vec![], 0)
+    }
+}
+
+pub struct LocalBp {
+    pht: CounterTable<TwoBitCounter>,
+}
+
+impl LocalBp {
+    pub fn new(addr_bits: usize) -> LocalBp {
+        LocalBp::try_new(addr_bits).unwrap_or_else(|e| panic!("Two-level {}", e))
+    }
+
+    pub fn try_new(addr_bits: usize) -> Result<LocalBp, AllocError> {
+        Ok(LocalBp {
+            pht: CounterTable::try_new(addr_bits)?,
+        })
+    }
+}
+
+impl Predictor for LocalBp {
+    fn update(&mut self, addr: usize, _predicted: bool, was_taken: bool) {
+        self.pht.update(addr >> 1, was_taken);
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        let counter = self.pht.get(addr >> 1);
+        (counter.value(), counter.confident())
+    }
+
+    /*
+     * With only the PC to index by, the counter of the event LANES
+     * ahead is known and prefetched, so with a table larger than the
+     * cache the misses of a batch overlap rather than follow one
+     * another.  The events are still taken in order, so a branch
+     * repeating in a tight loop sees its own updates.
+     */
+    fn predict_and_update_batch(&mut self, events: &[(usize, bool)]) -> Vec<bool> {
+        for &(addr, _) in events.iter().take(LANES) {
+            self.pht.prefetch(addr >> 1);
+        }
+        let mut predictions = Vec::with_capacity(events.len());
+        for (i, &(addr, was_taken)) in events.iter().enumerate() {
+            if let Some(&(ahead, _)) = events.get(i + LANES) {
+                self.pht.prefetch(ahead >> 1);
+            }
+            predictions.push(self.pht.predict(addr >> 1));
+            self.pht.update(addr >> 1, was_taken);
+        }
+        predictions
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        (
+            "Two-level".to_string(),
+            vec![self.pht.bits()],
+            self.pht.size_bits(),
+        )
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![("addr_bits", self.pht.bits())]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self) + self.pht.heap_bytes()
+    }
+
+    fn init_from_bias(&mut self, profile: &BiasProfile) {
+        let mask = (1 << self.pht.bits()) - 1;
+        profile.seed(self.pht.counters_mut(), |pc| (pc >> 1) & mask);
+    }
+
+    fn resize(&mut self, new_bits: usize, preserve: bool) -> Result<(), String> {
+        self.pht.resize(new_bits, preserve);
+        Ok(())
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.pht.states())
+    }
+
+    /// The SRAM image: the PHT, 2 bits per counter
+    fn dump_sram(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        dump_counters(self.pht.counters(), &mut BitWriter::new(w))
+    }
+}
+
+/*
+ * The PC indexed table of LocalBp, but of ProbablyBool rather than
+ * TwoBitCounter, whose strengthening past Fair is left to chance.  The
+ * dice come from a seeded RNG so runs are reproducible.
+ */
+pub struct ProbabilisticLocalBp {
+    seed: u64,
+    rng: StdRng,
+    pht: CounterTable<ProbablyBool>,
+}
+
+impl ProbabilisticLocalBp {
+    pub fn try_new(addr_bits: usize, seed: u64) -> Result<ProbabilisticLocalBp, AllocError> {
+        Ok(ProbabilisticLocalBp {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            pht: CounterTable::try_new(addr_bits)?,
+        })
+    }
+}
+
+impl Predictor for ProbabilisticLocalBp {
+    fn update(&mut self, addr: usize, _predicted: bool, was_taken: bool) {
+        self.pht
+            .get_mut(addr >> 1)
+            .update_with(was_taken, &mut self.rng);
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        let counter = self.pht.get(addr >> 1);
+        (counter.value(), counter.confident())
+    }
+
+    fn predict_prob(&self, addr: usize) -> f64 {
+        self.pht.get(addr >> 1).probability()
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let name = match self.seed {
+            0 => "Probabilistic".to_string(),
+            seed => format!("Probabilistic[seed={}]", seed),
+        };
+        (name, vec![self.pht.bits()], self.pht.size_bits())
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![("addr_bits", self.pht.bits())]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self) + self.pht.heap_bytes()
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.pht.states())
+    }
+}
+
+pub struct GshareBp {
+    history: GlobalHistory,
+    pht: CounterTable<TwoBitCounter>,
+}
+
+impl GshareBp {
+    pub fn new(addr_bits: usize) -> GshareBp {
+        GshareBp::try_new(addr_bits).unwrap_or_else(|e| panic!("Gshare {}", e))
+    }
+
+    pub fn try_new(addr_bits: usize) -> Result<GshareBp, AllocError> {
+        Ok(GshareBp {
+            history: GlobalHistory::new(),
+            pht: CounterTable::try_new(addr_bits)?,
+        })
+    }
+}
+
+impl Predictor for GshareBp {
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        self.pht
+            .update((addr >> 1) ^ self.history.value(), was_taken);
+        self.history.update(predicted, was_taken);
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        let counter = self.pht.get((addr >> 1) ^ self.history.value());
+        (counter.value(), counter.confident())
+    }
+
+    /*
+     * The retired history doesn't depend on the predictions, so the
+     * indices of LANES events can be found ahead of them, fetch blocks
+     * and all, and when they are all different the counters are
+     * independent and updated together, prefetched as the indices are
+     * found.  Chunks that collide go one at a time, as does everything
+     * under the speculative policy or clearing history on misses,
+     * where the history does depend on the predictions.
+     */
+    fn predict_and_update_batch(&mut self, events: &[(usize, bool)]) -> Vec<bool> {
+        let mut predictions = Vec::with_capacity(events.len());
+        for chunk in events.chunks(LANES) {
+            let mut history = self.history.clone();
+            let mut indices = [0; LANES];
+            for (index, &(addr, was_taken)) in indices.iter_mut().zip(chunk) {
+                *index = self.pht.index((addr >> 1) ^ history.value());
+                self.pht.prefetch(*index);
+                history.update(was_taken, was_taken);
+            }
+            let independent = self.history.policy() == HistoryPolicy::Retire
+                && self.history.reset_on_miss() == 0
+                && chunk.len() == LANES
+                && (1..LANES).all(|i| !indices[..i].contains(&indices[i]));
+            if !independent {
+                for &(addr, was_taken) in chunk {
+                    predictions.push(self.predict_and_update(addr, was_taken));
+                }
+                continue;
+            }
+
+            let mut counters = indices.map(|i| self.pht.get(i));
+            let taken = std::array::from_fn(|i| chunk[i].1);
+            let predicted = update_lanes(&mut counters, &taken);
+            for (&index, counter) in indices.iter().zip(counters) {
+                self.pht.set(index, counter);
+            }
+            for (&predicted, &(_, was_taken)) in predicted.iter().zip(chunk) {
+                self.history.update(predicted, was_taken);
+            }
+            predictions.extend(predicted);
+        }
+        predictions
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        (
+            "Gshare".to_string(),
+            vec![self.pht.bits()],
+            self.pht.size_bits(),
+        )
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![("addr_bits", self.pht.bits())]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self) + self.pht.heap_bytes()
+    }
+
+    fn resize(&mut self, new_bits: usize, preserve: bool) -> Result<(), String> {
+        self.pht.resize(new_bits, preserve);
+        Ok(())
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.pht.states())
+    }
+
+    delegate_history!(history);
+
+    /// The SRAM image: the PHT, 2 bits per counter
+    fn dump_sram(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        dump_counters(self.pht.counters(), &mut BitWriter::new(w))
+    }
+}
+
+/*
+ * TaggedGshare: gshare with a short partial tag, the address bits above
+ * the index, kept alongside each counter.  The counter is only trusted
+ * when the tag matches; otherwise the entry belongs to another branch
+ * and the fallback predicts, and the entry is taken over.  It's a
+ * cheaper point than YAGS for studying how much tags cut aliasing.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum TagFallback {
+    /// Predict not taken, unconfidently
+    #[default]
+    NotTaken,
+    /// A PC indexed table of counters, as large as the PHT
+    Bimodal,
+}
+
+impl std::fmt::Display for TagFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TagFallback::NotTaken => write!(f, "not-taken"),
+            TagFallback::Bimodal => write!(f, "bimodal"),
+        }
+    }
+}
+
+impl std::str::FromStr for TagFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "not-taken" => Ok(TagFallback::NotTaken),
+            "bimodal" => Ok(TagFallback::Bimodal),
+            _ => Err(format!("unknown tag fallback {}", s)),
+        }
+    }
+}
+
+pub struct TaggedGshareBp {
+    tag_bits: usize,
+    history: GlobalHistory,
+    pht: CounterTable<TwoBitCounter>,
+    tags: Vec<usize>,
+    // Whether the entry has been taken over, else it matches no tag
+    valid: Vec<bool>,
+    fallback: Option<CounterTable<TwoBitCounter>>,
+}
+
+impl TaggedGshareBp {
+    pub fn new(index_bits: usize, tag_bits: usize) -> TaggedGshareBp {
+        TaggedGshareBp::try_new(index_bits, tag_bits)
+            .unwrap_or_else(|e| panic!("TaggedGshare {}", e))
+    }
+
+    pub fn try_new(index_bits: usize, tag_bits: usize) -> Result<TaggedGshareBp, AllocError> {
+        Ok(TaggedGshareBp {
+            tag_bits,
+            history: GlobalHistory::new(),
+            pht: CounterTable::try_new(index_bits)?,
+            tags: table(index_bits, 0)?,
+            valid: table(index_bits, false)?,
+            fallback: None,
+        })
+    }
+
+    pub fn try_with_fallback(
+        mut self,
+        fallback: TagFallback,
+    ) -> Result<TaggedGshareBp, AllocError> {
+        self.fallback = match fallback {
+            TagFallback::NotTaken => None,
+            TagFallback::Bimodal => Some(CounterTable::try_new(self.pht.bits())?),
+        };
+        Ok(self)
+    }
+
+    /// The PHT index of `addr` and whether its entry is `addr`'s, and
+    /// if not the tag to take it over with
+    fn lookup(&self, addr: usize) -> (usize, usize, bool) {
+        let index = self.pht.index((addr >> 1) ^ self.history.value());
+        let tag = (addr >> 1 >> self.pht.bits()) & ((1 << self.tag_bits) - 1);
+        (index, tag, self.valid[index] && self.tags[index] == tag)
+    }
+}
+
+impl Predictor for TaggedGshareBp {
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        let (index, tag, hit) = self.lookup(addr);
+        if hit {
+            self.pht.update(index, was_taken);
+        } else {
+            self.tags[index] = tag;
+            self.valid[index] = true;
+            self.pht.set(index, TwoBitCounter::new(was_taken));
+        }
+        if let Some(fallback) = &mut self.fallback {
+            fallback.update(addr >> 1, was_taken);
+        }
+        self.history.update(predicted, was_taken);
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        let (index, _, hit) = self.lookup(addr);
+        let counter = if hit {
+            self.pht.get(index)
+        } else {
+            match &self.fallback {
+                Some(fallback) => fallback.get(addr >> 1),
+                None => return (false, false),
+            }
+        };
+        (counter.value(), counter.confident())
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let name = match self.fallback {
+            None => "TaggedGshare".to_string(),
+            Some(_) => format!("TaggedGshare[fallback={}]", TagFallback::Bimodal),
+        };
+        (
+            name,
+            vec![self.pht.bits(), self.tag_bits],
+            self.pht.size_bits()
+                + self.tags.len() * (self.tag_bits + 1)
+                + self.fallback.as_ref().map_or(0, CounterTable::size_bits),
+        )
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![("index_bits", self.pht.bits()), ("tag_bits", self.tag_bits)]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.pht.heap_bytes()
+            + table_bytes(&self.tags)
+            + table_bytes(&self.valid)
+            + self.fallback.as_ref().map_or(0, CounterTable::heap_bytes)
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.pht.states())
+    }
+
+    delegate_history!(history);
+}
+
+/*
+ * GAg: the PHT is indexed by the global history alone, ignoring the
+ * address, which shows how much the PC contributes to the others.
+ */
+pub struct GAgBp {
+    history: GlobalHistory,
+    pht: CounterTable<TwoBitCounter>,
+}
+
+impl GAgBp {
+    pub fn try_new(history_bits: usize) -> Result<GAgBp, AllocError> {
+        Ok(GAgBp {
+            history: GlobalHistory::new(),
+            pht: CounterTable::try_new(history_bits)?,
+        })
+    }
+}
+
+impl Predictor for GAgBp {
+    fn update(&mut self, _addr: usize, predicted: bool, was_taken: bool) {
+        self.pht.update(self.history.value(), was_taken);
+        self.history.update(predicted, was_taken);
+    }
+
+    fn query(&self, _addr: usize) -> (bool, bool) {
+        let counter = self.pht.get(self.history.value());
+        (counter.value(), counter.confident())
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        (
+            "GAg".to_string(),
+            vec![self.pht.bits()],
+            self.pht.size_bits(),
+        )
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![("history_bits", self.pht.bits())]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self) + self.pht.heap_bytes()
+    }
+
+    fn resize(&mut self, new_bits: usize, preserve: bool) -> Result<(), String> {
+        self.pht.resize(new_bits, preserve);
+        Ok(())
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.pht.states())
+    }
+
+    delegate_history!(history);
+}
+
+/*
+ * Gshare with the path folded in: the PCs of the previous `prev_pcs`
+ * branches are XORed into the index, each shifted by its distance, so
+ * that a branch reached from different places gets different counters
+ * even when the outcomes on the way were the same.
+ */
+pub struct PrevPcGshareBp {
+    prev_pcs: usize,
+    history: GlobalHistory,
+    recent: VecDeque<usize>,
+    // What path gives instead, see pin_history
+    pinned_path: Option<usize>,
+    pht: CounterTable<TwoBitCounter>,
+}
+
+impl PrevPcGshareBp {
+    pub fn try_new(addr_bits: usize, prev_pcs: usize) -> Result<PrevPcGshareBp, AllocError> {
+        Ok(PrevPcGshareBp {
+            prev_pcs,
+            history: GlobalHistory::new(),
+            recent: VecDeque::with_capacity(prev_pcs + 1),
+            pinned_path: None,
+            pht: CounterTable::try_new(addr_bits)?,
+        })
+    }
+}
+
+impl PrevPcGshareBp {
+    /// The previous PCs, each shifted by its distance
+    fn path(&self) -> usize {
+        self.pinned_path.unwrap_or_else(|| {
+            self.recent
+                .iter()
+                .enumerate()
+                .fold(0, |path, (i, pc)| path ^ (pc >> 1) << (i + 1))
+        })
+    }
+
+    fn hash(&self, addr: usize) -> usize {
+        (addr >> 1) ^ self.history.value() ^ self.path()
+    }
+}
+
+impl Predictor for PrevPcGshareBp {
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        self.pht.update(self.hash(addr), was_taken);
+        self.history.update(predicted, was_taken);
+
+        if self.prev_pcs > 0 {
+            if self.recent.len() == self.prev_pcs {
+                self.recent.pop_back();
+            }
+            self.recent.push_front(addr);
+        }
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        let counter = self.pht.get(self.hash(addr));
+        (counter.value(), counter.confident())
+    }
+
+    // The previous PCs only need their index bits kept
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        (
+            "PrevPcGshare".to_string(),
+            vec![self.pht.bits(), self.prev_pcs],
+            self.pht.size_bits() + self.prev_pcs * self.pht.bits(),
+        )
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![("addr_bits", self.pht.bits()), ("prev_pcs", self.prev_pcs)]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.pht.heap_bytes()
+            + self.recent.capacity() * std::mem::size_of::<usize>()
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.pht.states())
+    }
+
+    delegate_history!(history, setters);
+
+    fn history_snapshot(&self) -> HistorySnapshot {
+        HistorySnapshot::Path(self.history.value(), self.path())
+    }
+
+    fn pin_history(&mut self, snapshot: Option<&HistorySnapshot>) {
+        let (history, path) = match snapshot {
+            Some(&HistorySnapshot::Path(history, path)) => (Some(history), Some(path)),
+            _ => (None, None),
+        };
+        self.history.pin(history);
+        self.pinned_path = path;
+    }
+
+    /// The SRAM image: the PHT, 2 bits per counter
+    fn dump_sram(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        dump_counters(self.pht.counters(), &mut BitWriter::new(w))
+    }
+}
+
+/// When BimodalBp updates its choice PHT
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ChoiceUpdate {
+    /// Unless it was wrong but the chosen direction PHT was right (the paper)
+    #[default]
+    Paper,
+    Always,
+    /// Only when the prediction was wrong
+    Mispredict,
+    /// Only when the choice agreed with the outcome
+    Agreement,
+}
+
+impl std::fmt::Display for ChoiceUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChoiceUpdate::Paper => write!(f, "paper"),
+            ChoiceUpdate::Always => write!(f, "always"),
+            ChoiceUpdate::Mispredict => write!(f, "mispredict"),
+            ChoiceUpdate::Agreement => write!(f, "agreement"),
+        }
+    }
+}
+
+impl std::str::FromStr for ChoiceUpdate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "paper" => Ok(ChoiceUpdate::Paper),
+            "always" => Ok(ChoiceUpdate::Always),
+            "mispredict" => Ok(ChoiceUpdate::Mispredict),
+            "agreement" => Ok(ChoiceUpdate::Agreement),
+            _ => Err(format!("unknown choice update policy {}", s)),
+        }
+    }
+}
+
+/// The state BimodalBp's counters start out in
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum CounterInit {
+    #[default]
+    WeaklyTaken,
+    StronglyTaken,
+    WeaklyNotTaken,
+    StronglyNotTaken,
+}
+
+impl CounterInit {
+    pub fn counter(self) -> TwoBitCounter {
+        match self {
+            CounterInit::WeaklyTaken => TwoBitCounter::new(true),
+            CounterInit::StronglyTaken => TwoBitCounter::strong(true),
+            CounterInit::WeaklyNotTaken => TwoBitCounter::new(false),
+            CounterInit::StronglyNotTaken => TwoBitCounter::strong(false),
+        }
+    }
+}
+
+impl std::fmt::Display for CounterInit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CounterInit::WeaklyTaken => write!(f, "weak-taken"),
+            CounterInit::StronglyTaken => write!(f, "strong-taken"),
+            CounterInit::WeaklyNotTaken => write!(f, "weak-not-taken"),
+            CounterInit::StronglyNotTaken => write!(f, "strong-not-taken"),
+        }
+    }
+}
+
+impl std::str::FromStr for CounterInit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weak-taken" => Ok(CounterInit::WeaklyTaken),
+            "strong-taken" => Ok(CounterInit::StronglyTaken),
+            "weak-not-taken" => Ok(CounterInit::WeaklyNotTaken),
+            "strong-not-taken" => Ok(CounterInit::StronglyNotTaken),
+            _ => Err(format!("unknown counter initialization {}", s)),
+        }
+    }
+}
+
+pub struct BimodalBp {
+    choice_update: ChoiceUpdate,
+    init: CounterInit,
+    history: GlobalHistory,
+    choice_pht: CounterTable<TwoBitCounter>,
+    direction_pht_nt: CounterTable<TwoBitCounter>,
+    direction_pht_t: CounterTable<TwoBitCounter>,
+    arbitration: ArbitrationLog,
+}
+
+impl BimodalBp {
+    pub fn new(addr_bits: usize) -> BimodalBp {
+        BimodalBp::try_new(addr_bits).unwrap_or_else(|e| panic!("Bimodal {}", e))
+    }
+
+    pub fn try_new(addr_bits: usize) -> Result<BimodalBp, AllocError> {
+        let choice_pht = CounterTable::try_new(addr_bits)?;
+        let direction_pht_nt = CounterTable::try_new(addr_bits)?;
+        let direction_pht_t = CounterTable::try_new(addr_bits)?;
+        Ok(BimodalBp {
+            choice_update: ChoiceUpdate::Paper,
+            init: CounterInit::WeaklyTaken,
+            history: GlobalHistory::new(),
+            choice_pht,
+            direction_pht_nt,
+            direction_pht_t,
+            arbitration: ArbitrationLog::default(),
+        })
+    }
+
+    pub fn with_choice_update(mut self, choice_update: ChoiceUpdate) -> BimodalBp {
+        self.choice_update = choice_update;
+        self
+    }
+
+    /// Starts all three PHTs in `init`, which trades warm up misses
+    /// on branches biased one way for those on branches biased the other
+    pub fn with_init(mut self, init: CounterInit) -> BimodalBp {
+        self.init = init;
+        self.reset_tables();
+        self
+    }
+
+    fn reset_tables(&mut self) {
+        for t in [
+            &mut self.choice_pht,
+            &mut self.direction_pht_nt,
+            &mut self.direction_pht_t,
+        ] {
+            t.counters_mut().fill(self.init.counter());
+        }
+    }
+}
+
+impl Predictor for BimodalBp {
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        let choice_hash = addr >> 1;
+        let direction_hash = (addr >> 1) ^ self.history.value();
+
+        let choice = self.choice_pht.predict(choice_hash);
+
+        if self.arbitration.wants() {
+            let (provider, table) = if choice {
+                (Provider::DirectionTaken, &self.direction_pht_t)
+            } else {
+                (Provider::DirectionNotTaken, &self.direction_pht_nt)
+            };
+            self.arbitration.entries.push(Arbitration {
+                addr,
+                choice: self.choice_pht.get(choice_hash).state(),
+                provider,
+                state: table.get(direction_hash).state(),
+                tag_hit: None,
+                was_taken,
+            });
+        }
+
+        if choice {
+            self.direction_pht_t.update(direction_hash, was_taken);
+        } else {
+            self.direction_pht_nt.update(direction_hash, was_taken);
+        }
+
+        /* "The choice PHT is normally updated too, but not if it
+         * gives a prediction contradicting the branch outcome and the
+         * direction PHT chosen gives the correct prediction."
+         *
+         * That is, it's updated if we mispredicted or it disagreed
+         * with the actual direction */
+
+        let update_choice = match self.choice_update {
+            ChoiceUpdate::Paper => predicted != was_taken || choice != was_taken,
+            ChoiceUpdate::Always => true,
+            ChoiceUpdate::Mispredict => predicted != was_taken,
+            ChoiceUpdate::Agreement => choice == was_taken,
+        };
+        if update_choice {
+            self.choice_pht.update(choice_hash, was_taken);
+        }
+
+        self.history.update(predicted, was_taken);
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        let direction = if self.choice_pht.predict(addr >> 1) {
+            &self.direction_pht_t
+        } else {
+            &self.direction_pht_nt
+        };
+        let counter = direction.get((addr >> 1) ^ self.history.value());
+        (counter.value(), counter.confident())
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let mut options = vec![];
+        if self.choice_update != ChoiceUpdate::Paper {
+            options.push(format!("choice={}", self.choice_update));
+        }
+        if self.init != CounterInit::WeaklyTaken {
+            options.push(format!("init={}", self.init));
+        }
+        let name = if options.is_empty() {
+            "Bimodal".to_string()
+        } else {
+            format!("Bimodal[{}]", options.join(","))
+        };
+        (
+            name,
+            vec![self.choice_pht.bits()],
+            self.choice_pht.size_bits()
+                + self.direction_pht_t.size_bits()
+                + self.direction_pht_nt.size_bits(),
+        )
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![("addr_bits", self.choice_pht.bits())]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.choice_pht.heap_bytes()
+            + self.direction_pht_nt.heap_bytes()
+            + self.direction_pht_t.heap_bytes()
+    }
+
+    fn log_arbitration(&mut self, events: usize) -> bool {
+        self.arbitration.start(events);
+        true
+    }
+
+    fn arbitration_log(&self) -> &[Arbitration] {
+        &self.arbitration.entries
+    }
+
+    fn init_from_bias(&mut self, profile: &BiasProfile) {
+        let mask = (1 << self.choice_pht.bits()) - 1;
+        profile.seed(self.choice_pht.counters_mut(), |pc| (pc >> 1) & mask);
+    }
+
+    fn resize(&mut self, new_bits: usize, preserve: bool) -> Result<(), String> {
+        self.choice_pht.resize(new_bits, preserve);
+        self.direction_pht_nt.resize(new_bits, preserve);
+        self.direction_pht_t.resize(new_bits, preserve);
+        if !preserve {
+            self.reset_tables();
+        }
+        Ok(())
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.choice_pht.states())
+    }
+
+    delegate_history!(history);
+
+    /// The SRAM image: the choice, not taken, and taken PHTs in that
+    /// order, 2 bits per counter
+    fn dump_sram(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        let mut w = BitWriter::new(w);
+        dump_counters(self.choice_pht.counters(), &mut w)?;
+        dump_counters(self.direction_pht_nt.counters(), &mut w)?;
+        dump_counters(self.direction_pht_t.counters(), &mut w)
+    }
+}
+
+/*
+ * YAGS: a choice PHT indexed by address plus tagged direction tables
+ * indexed by address ^ history that only hold the exceptions to the
+ * choice.  The variants differ in how the tag is formed and in the
+ * associativity of the direction tables:
+ *
+ *   YAGS1 = YAGS with a single direction table
+ *   YAGS2 = YAGS1 + history hashed tag
+ *   YAGS3 = YAGS1 + u-bits + 2-way associative directions
+ *   YAGS4 = YAGS2 + YAGS3
+ */
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TagHash {
+    /// The low address bits
+    Address,
+    /// {address_bits[1:4], address_bits[5:8] ^ history_bits}
+    Folded,
+}
+
+/// When a choice misprediction allocates a direction entry
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AllocPolicy {
+    /// Whenever the choice mispredicts (the paper)
+    Always,
+    /// With the given probability, in percent
+    Percent(u32),
+}
+
+impl std::fmt::Display for AllocPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AllocPolicy::Always => write!(f, "always"),
+            AllocPolicy::Percent(p) => write!(f, "{}%", p),
+        }
+    }
+}
+
+impl std::str::FromStr for AllocPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(AllocPolicy::Always),
+            _ => match s.trim_end_matches('%').parse() {
+                Ok(p) if p <= 100 => Ok(AllocPolicy::Percent(p)),
+                _ => Err(format!("bad allocation policy {}", s)),
+            },
+        }
+    }
+}
+
+/// Which way predicts when more than one direction way's tag hits
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum TagMatch {
+    /// The lowest numbered way
+    #[default]
+    First,
+    /// The way whose counter is strongest, the first of equals
+    MostConfident,
+    /// The majority of the hits and the choice, the first hit breaking
+    /// ties, which is then the way that predicts with the majority
+    Vote,
+}
+
+impl std::fmt::Display for TagMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TagMatch::First => write!(f, "first"),
+            TagMatch::MostConfident => write!(f, "confident"),
+            TagMatch::Vote => write!(f, "vote"),
+        }
+    }
+}
+
+impl std::str::FromStr for TagMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(TagMatch::First),
+            "confident" => Ok(TagMatch::MostConfident),
+            "vote" => Ok(TagMatch::Vote),
+            _ => Err(format!("bad tag match policy {}", s)),
+        }
+    }
+}
+
+pub struct YagsBp {
+    name: &'static str,
+    dir_bits: usize,
+    tag_bits: usize,
+    tag_hash: TagHash,
+    index_shift: usize,
+    alloc_policy: AllocPolicy,
+    trust_threshold: u8,
+    tag_match: TagMatch,
+    index_seed: usize,
+    tag_seed: usize,
+    ports: PortStats,
+    rng: StdRng,
+    history: GlobalHistory,
+    choice_pht: CounterTable<TwoBitCounter>,
+    direction_pht: Vec<Vec<TwoBitCounter>>,
+    direction_tag: Vec<Vec<usize>>,
+    direction_u: Vec<Vec<bool>>,
+    direction_valid: Vec<Vec<bool>>,
+    valid_bits: bool,
+    dir_mask: usize,
+    tag_mask: usize,
+    arbitration: ArbitrationLog,
+}
+
+impl YagsBp {
+    /// `ways` of 1 or 2 direction tables, the latter with u-bits
+    pub fn try_new(
+        name: &'static str,
+        ways: usize,
+        tag_hash: TagHash,
+        addr_bits: usize,
+        dir_bits: usize,
+        tag_bits: usize,
+    ) -> Result<YagsBp, AllocError> {
+        assert!(ways == 1 || ways == 2);
+        let mut direction_pht = vec![];
+        let mut direction_tag = vec![];
+        let mut direction_u = vec![];
+        let mut direction_valid = vec![];
+        for _ in 0..ways {
+            direction_pht.push(table(dir_bits, TwoBitCounter::new(true))?);
+            direction_tag.push(table(dir_bits, 0)?);
+            direction_u.push(table(dir_bits, false)?);
+            direction_valid.push(table(dir_bits, false)?);
+        }
+        let dir_entries = 1 << dir_bits;
+        Ok(YagsBp {
+            name,
+            dir_bits,
+            tag_bits,
+            tag_hash,
+            index_shift: 0,
+            alloc_policy: AllocPolicy::Always,
+            trust_threshold: 1,
+            tag_match: TagMatch::First,
+            index_seed: 0,
+            tag_seed: 0,
+            ports: PortStats {
+                write_ports: 1,
+                ..PortStats::default()
+            },
+            rng: StdRng::seed_from_u64(0),
+            history: GlobalHistory::new(),
+            choice_pht: CounterTable::try_new(addr_bits)?,
+            direction_pht,
+            direction_tag,
+            direction_u,
+            direction_valid,
+            valid_bits: true,
+            dir_mask: dir_entries - 1,
+            tag_mask: (1 << tag_bits) - 1,
+            arbitration: ArbitrationLog::default(),
+        })
+    }
+
+    pub fn with_tag_hash(mut self, tag_hash: TagHash) -> YagsBp {
+        self.tag_hash = tag_hash;
+        self
+    }
+
+    /// Drop `index_shift` more address bits from the direction index
+    pub fn with_index_shift(mut self, index_shift: usize) -> YagsBp {
+        self.index_shift = index_shift;
+        self
+    }
+
+    pub fn with_alloc_policy(mut self, alloc_policy: AllocPolicy) -> YagsBp {
+        self.alloc_policy = alloc_policy;
+        self
+    }
+
+    /// The strength (see Boolish) a hit's counter needs for its
+    /// prediction to be used over the choice's, 1 always trusting it.
+    /// Either way the hit entry is what gets updated.
+    pub fn with_trust_threshold(mut self, trust_threshold: u8) -> YagsBp {
+        self.trust_threshold = trust_threshold;
+        self
+    }
+
+    /// How the two ways of YAGS3 and YAGS4 settle hitting together
+    pub fn with_tag_match(mut self, tag_match: TagMatch) -> YagsBp {
+        self.tag_match = tag_match;
+        self
+    }
+
+    /// Scrambles the address bits that form the direction index and
+    /// the tag with independent seeds, 0 leaving them as is
+    pub fn with_hash_seeds(mut self, index_seed: usize, tag_seed: usize) -> YagsBp {
+        self.index_seed = index_seed;
+        self.tag_seed = tag_seed;
+        self
+    }
+
+    /// The writes an update may make in the same cycle before stalling
+    pub fn with_write_ports(mut self, write_ports: usize) -> YagsBp {
+        self.ports.write_ports = write_ports;
+        self
+    }
+
+    /// Without valid bits an entry never allocated matches a tag of 0,
+    /// as the original implementation did
+    pub fn with_valid_bits(mut self, valid_bits: bool) -> YagsBp {
+        self.valid_bits = valid_bits;
+        self
+    }
+
+    fn ways(&self) -> usize {
+        self.direction_pht.len()
+    }
+
+    /// The bits of a direction table entry: its counter, tag and, as
+    /// enabled, the valid bit and, for the associative variants, the u-bit
+    fn entry_bits(&self) -> usize {
+        let u_bits = (self.ways() > 1) as usize;
+        TwoBitCounter::BITS + self.tag_bits + u_bits + self.valid_bits as usize
+    }
+
+    fn hits(&self, n: usize, index: usize, tag: usize) -> bool {
+        (self.direction_valid[n][index] || !self.valid_bits) && self.direction_tag[n][index] == tag
+    }
+
+    /// The way, if any, that predicts of those whose tag hits
+    fn matching_way(&self, addr_index: usize, index: usize, tag: usize) -> Option<usize> {
+        let mut hits = (0..self.ways()).filter(|&n| self.hits(n, index, tag));
+        let counter = |n: usize| self.direction_pht[n][index];
+        match self.tag_match {
+            TagMatch::First => hits.next(),
+            TagMatch::MostConfident => hits.fold(None, |best: Option<usize>, n| match best {
+                Some(b) if counter(b).strength() >= counter(n).strength() => Some(b),
+                _ => Some(n),
+            }),
+            TagMatch::Vote => {
+                let first = hits.next()?;
+                let voters: Vec<usize> = std::iter::once(first).chain(hits).collect();
+                let taken = voters.iter().filter(|&&n| counter(n).value()).count()
+                    + self.choice_pht.predict(addr_index) as usize;
+                let votes = voters.len() + 1;
+                let majority = match (2 * taken).cmp(&votes) {
+                    std::cmp::Ordering::Greater => true,
+                    std::cmp::Ordering::Less => false,
+                    std::cmp::Ordering::Equal => return Some(first),
+                };
+                voters
+                    .into_iter()
+                    .find(|&n| counter(n).value() == majority)
+                    .or(Some(first))
+            }
+        }
+    }
+
+    fn tag(&self, addr: usize) -> usize {
+        /*
+         * For the history bits in the tag, use a different fold than
+         * what you use in the index something like
+         *
+         *   {address_bits[1:4], address_bits[5:8] ^ history_bits}
+         *
+         * for the index having folding is good if you decide you want
+         * larger history than your index bit Us
+         *
+         *   ((addr >> 1) & 0xf) << 4) | (((addr >> 4) ^ hist) & 0xf)
+         *
+         * when you add history to the tag, keep some bits from being
+         * xored probably easier to say:
+         *
+         *   addr ^ ((hist << 4) & 0xff)
+         *
+         */
+        match self.tag_hash {
+            TagHash::Address => addr & self.tag_mask,
+            TagHash::Folded => {
+                ((addr & 30) << 4 | (addr >> 5 ^ self.history.value()) & 15) & self.tag_mask
+            }
+        }
+    }
+}
+
+/// Where a branch lands in the YAGS tables and what they make of it
+struct YagsAccess {
+    // The address with the constant zero LSB dropped
+    addr: usize,
+    addr_index: usize,
+    hash_index: usize,
+    hash_tag: usize,
+    // The way whose tag hit
+    used: Option<usize>,
+    provider: Provider,
+    counter: TwoBitCounter,
+}
+
+impl YagsBp {
+    /// Reads the choice and every direction way, in parallel in hardware
+    fn access(&self, addr: usize) -> YagsAccess {
+        // First drop the constant zero LSB
+        let addr = addr >> 1;
+
+        let addr_index = self.choice_pht.index(addr >> 1);
+        let hash_index = (seeded_hash(addr >> self.index_shift, self.index_seed)
+            ^ self.history.value())
+            & self.dir_mask;
+        let hash_tag = self.tag(seeded_hash(addr, self.tag_seed));
+
+        let used = self.matching_way(addr_index, hash_index, hash_tag);
+        let (provider, counter) = match used {
+            Some(n) if self.direction_pht[n][hash_index].strength() >= self.trust_threshold => {
+                (Provider::Way(n), self.direction_pht[n][hash_index])
+            }
+            _ => (Provider::Choice, self.choice_pht.get(addr_index)),
+        };
+        YagsAccess {
+            addr,
+            addr_index,
+            hash_index,
+            hash_tag,
+            used,
+            provider,
+            counter,
+        }
+    }
+
+    /// Learns `was_taken` where `access` found the branch
+    fn learn(&mut self, access: YagsAccess, was_taken: bool) {
+        let YagsAccess {
+            addr,
+            addr_index,
+            hash_index,
+            hash_tag,
+            used,
+            provider,
+            counter,
+        } = access;
+        let mut writes = 0;
+        let predicted = counter.value();
+
+        if self.arbitration.wants() {
+            self.arbitration.entries.push(Arbitration {
+                addr,
+                choice: self.choice_pht.get(addr_index).state(),
+                provider,
+                state: counter.state(),
+                tag_hit: used,
+                was_taken,
+            });
+        }
+
+        // Update
+        match used {
+            Some(n) => {
+                // The u-bit shares the entry's write
+                writes += 1;
+                self.direction_pht[n][hash_index].update(was_taken);
+                if self.ways() > 1 {
+                    self.direction_u[n][hash_index] =
+                        self.direction_pht[n][hash_index].value() == was_taken;
+                }
+            }
+            None => {
+                // The choice is updated on misses
+                writes += 1;
+                self.choice_pht.update(addr_index, was_taken);
+
+                let allocate = match self.alloc_policy {
+                    AllocPolicy::Always => true,
+                    AllocPolicy::Percent(p) => self.rng.gen_range(0..100) < p,
+                };
+
+                // NB: this is key no not waste an entry needlessly
+                if self.choice_pht.predict(addr_index) != was_taken && allocate {
+                    match (0..self.ways()).find(|&n| !self.direction_u[n][hash_index]) {
+                        Some(n) => {
+                            writes += 1;
+                            self.direction_tag[n][hash_index] = hash_tag;
+                            self.direction_valid[n][hash_index] = true;
+                            self.direction_pht[n][hash_index] = TwoBitCounter::new(was_taken);
+                        }
+                        None => {
+                            writes += self.ways();
+                            for u in self.direction_u.iter_mut() {
+                                u[hash_index] = false;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        self.ports.record(1 + self.ways(), writes);
+        self.history.update(predicted, was_taken);
+    }
+}
+
+/// A multiplicative hash of `x` keyed by `seed`, the identity for seed
+/// 0.  It's computed in 64 bits whatever the width of usize.
+fn seeded_hash(x: usize, seed: usize) -> usize {
+    if seed == 0 {
+        return x;
+    }
+    let h = ((x ^ seed) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (h ^ h >> 32) as usize
+}
+
+impl Predictor for YagsBp {
+    // One access of the tables for both the prediction and the update
+    fn predict_and_update(&mut self, addr: usize, was_taken: bool) -> bool {
+        self.predict_with_confidence(addr, was_taken).0
+    }
+
+    fn predict_with_confidence(&mut self, addr: usize, was_taken: bool) -> (bool, bool) {
+        let access = self.access(addr);
+        let prediction = (access.counter.value(), access.counter.confident());
+        self.learn(access, was_taken);
+
+        prediction
+    }
+
+    fn update(&mut self, addr: usize, _predicted: bool, was_taken: bool) {
+        let access = self.access(addr);
+        self.learn(access, was_taken);
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        let counter = self.access(addr).counter;
+        (counter.value(), counter.confident())
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let mut options = vec![];
+        if self.alloc_policy != AllocPolicy::Always {
+            options.push(format!("alloc={}", self.alloc_policy));
+        }
+        if self.trust_threshold != 1 {
+            options.push(format!("trust={}", self.trust_threshold));
+        }
+        if self.tag_match != TagMatch::First {
+            options.push(format!("match={}", self.tag_match));
+        }
+        if self.index_seed != 0 || self.tag_seed != 0 {
+            options.push(format!("index_seed={}", self.index_seed));
+            options.push(format!("tag_seed={}", self.tag_seed));
+        }
+        if !self.valid_bits {
+            options.push("valid=false".to_string());
+        }
+        let name = if options.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{}[{}]", self.name, options.join(","))
+        };
+        (
+            name,
+            vec![self.choice_pht.bits(), self.dir_bits, self.tag_bits],
+            self.choice_pht.size_bits()
+                + self.direction_pht.iter().map(Vec::len).sum::<usize>() * self.entry_bits(),
+        )
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("addr_bits", self.choice_pht.bits()),
+            ("dir_bits", self.dir_bits),
+            ("tag_bits", self.tag_bits),
+        ]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        let ways: usize = self.direction_pht.iter().map(table_bytes).sum::<usize>()
+            + self.direction_tag.iter().map(table_bytes).sum::<usize>()
+            + self.direction_u.iter().map(table_bytes).sum::<usize>()
+            + self.direction_valid.iter().map(table_bytes).sum::<usize>();
+        std::mem::size_of_val(self)
+            + self.choice_pht.heap_bytes()
+            + table_bytes(&self.direction_pht)
+            + table_bytes(&self.direction_tag)
+            + table_bytes(&self.direction_u)
+            + table_bytes(&self.direction_valid)
+            + ways
+    }
+
+    fn init_from_bias(&mut self, profile: &BiasProfile) {
+        let mask = (1 << self.choice_pht.bits()) - 1;
+        profile.seed(self.choice_pht.counters_mut(), |pc| (pc >> 1) & mask);
+    }
+
+    fn port_stats(&self) -> Option<PortStats> {
+        Some(self.ports)
+    }
+
+    fn log_arbitration(&mut self, events: usize) -> bool {
+        self.arbitration.start(events);
+        true
+    }
+
+    fn arbitration_log(&self) -> &[Arbitration] {
+        &self.arbitration.entries
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.choice_pht.states())
+    }
+
+    delegate_history!(history);
+
+    /// The SRAM image: the choice PHT, 2 bits per counter, followed by
+    /// each way of the direction table with entries of {counter[1:0],
+    /// u, valid, tag}, the u-bit only being present with two ways and
+    /// the valid bit unless disabled
+    fn dump_sram(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        let mut w = BitWriter::new(w);
+        dump_counters(self.choice_pht.counters(), &mut w)?;
+        for n in 0..self.ways() {
+            for i in 0..self.direction_pht[n].len() {
+                w.write(self.direction_pht[n][i].state() as usize, 2)?;
+                if self.ways() > 1 {
+                    w.write(self.direction_u[n][i] as usize, 1)?;
+                }
+                if self.valid_bits {
+                    w.write(self.direction_valid[n][i] as usize, 1)?;
+                }
+                w.write(self.direction_tag[n][i], self.tag_bits)?;
+            }
+            w.align()?;
+        }
+        Ok(())
+    }
+}
+
+impl YagsBp {
+    /// YAGS1 through YAGS4 by number
+    pub fn try_variant(
+        variant: usize,
+        addr_bits: usize,
+        dir_bits: usize,
+        tag_bits: usize,
+    ) -> Result<YagsBp, AllocError> {
+        let (name, ways, tag_hash) = match variant {
+            1 => ("YAGS1", 1, TagHash::Address),
+            2 => ("YAGS2", 1, TagHash::Folded),
+            3 => ("YAGS3", 2, TagHash::Address),
+            4 => ("YAGS4", 2, TagHash::Folded),
+            _ => panic!("there's no YAGS{}", variant),
+        };
+        let p = YagsBp::try_new(name, ways, tag_hash, addr_bits, dir_bits, tag_bits)?;
+        // NB: unlike the others YAGS4 indexes the directions with (addr >> 1) ^ history
+        Ok(if variant == 4 {
+            p.with_index_shift(1)
+        } else {
+            p
+        })
+    }
+
+    fn variant(variant: usize, addr_bits: usize, dir_bits: usize, tag_bits: usize) -> YagsBp {
+        YagsBp::try_variant(variant, addr_bits, dir_bits, tag_bits)
+            .unwrap_or_else(|e| panic!("YAGS{} {}", variant, e))
+    }
+
+    pub fn yags1(addr_bits: usize, dir_bits: usize, tag_bits: usize) -> YagsBp {
+        YagsBp::variant(1, addr_bits, dir_bits, tag_bits)
+    }
+
+    pub fn yags2(addr_bits: usize, dir_bits: usize, tag_bits: usize) -> YagsBp {
+        YagsBp::variant(2, addr_bits, dir_bits, tag_bits)
+    }
+
+    pub fn yags3(addr_bits: usize, dir_bits: usize, tag_bits: usize) -> YagsBp {
+        YagsBp::variant(3, addr_bits, dir_bits, tag_bits)
+    }
+
+    pub fn yags4(addr_bits: usize, dir_bits: usize, tag_bits: usize) -> YagsBp {
+        YagsBp::variant(4, addr_bits, dir_bits, tag_bits)
+    }
+}
+
+/*
+ * TAGE (Seznec and Michaud, "A case for (partially) TAgged GEometric
+ * history length branch prediction", 2006): a bimodal base plus
+ * tagged tables, each indexed and tagged by a hash of the address and
+ * a fold of a longer global history than the one before, the lengths
+ * typically growing geometrically.  The longest table whose tag hits
+ * provides the prediction, unless its entry was only just allocated
+ * and such entries have been doing worse than the next longest hit,
+ * the alternate.  A misprediction allocates an entry in one of the
+ * longer tables whose useful counter is 0, or, failing that, ages
+ * theirs.  The useful counters of all tables are halved every
+ * TAGE_U_PERIOD updates so stale entries can be replaced.
+ *
+ * The histories are the predictor's own folds of the outcomes, so
+ * unlike the predictors sharing GlobalHistory it's always updated in
+ * retire order, one branch at a time.
+ */
+
+/// The history lengths of the registry's TAGE by default
+pub const TAGE_MIN_HISTORY: usize = 4;
+pub const TAGE_MAX_HISTORY: usize = 64;
+
+// The tagged tables have 1/8 the entries of the base
+const TAGE_TABLE_SHRINK: usize = 3;
+const TAGE_CTR_MAX: i8 = 3;
+const TAGE_CTR_MIN: i8 = -4;
+const TAGE_CTR_BITS: usize = 3;
+const TAGE_U_MAX: u8 = 3;
+const TAGE_U_BITS: usize = 2;
+const TAGE_ALT_MAX: i8 = 7;
+const TAGE_ALT_MIN: i8 = -8;
+const TAGE_ALT_BITS: usize = 4;
+const TAGE_U_PERIOD: usize = 1 << 18;
+
+/// `tables` history lengths from `min` to `max` in a geometric series
+pub fn geometric_lengths(tables: usize, min: usize, max: usize) -> Vec<usize> {
+    if tables == 1 {
+        return vec![min];
+    }
+    let ratio = max as f64 / min.max(1) as f64;
+    (0..tables)
+        .map(|i| {
+            let length = min as f64 * ratio.powf(i as f64 / (tables - 1) as f64);
+            length.round() as usize
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Default)]
+struct TageEntry {
+    // Until allocated, whatever the tag, it matches no branch
+    valid: bool,
+    // A 3-bit signed counter, taken at 0 and above
+    ctr: i8,
+    tag: usize,
+    u: u8,
+}
+
+impl TageEntry {
+    fn taken(&self) -> bool {
+        self.ctr >= 0
+    }
+
+    fn weak(&self) -> bool {
+        self.ctr == 0 || self.ctr == -1
+    }
+
+    /// As allocated, weak and of no proven use
+    fn fresh(&self) -> bool {
+        self.weak() && self.u == 0
+    }
+}
+
+struct TageTable {
+    entries: Vec<TageEntry>,
+    index_fold: FoldId,
+    tag_folds: [FoldId; 2],
+}
+
+pub struct TageBp {
+    base: CounterTable<TwoBitCounter>,
+    lengths: Vec<usize>,
+    table_bits: usize,
+    tag_bits: usize,
+    tables: Vec<TageTable>,
+    folds: HistorySet,
+    // Whether the alternate does better than fresh providers, at 0 and above
+    use_alt_on_na: i8,
+    updates: usize,
+    rng: StdRng,
+}
+
+/// What the TAGE tables make of a branch
+struct TageAccess {
+    provider: Option<usize>,
+    alt_taken: bool,
+    predicted: bool,
+    confident: bool,
+}
+
+impl TageBp {
+    pub fn new(base_bits: usize, lengths: &[usize], tag_bits: usize) -> TageBp {
+        TageBp::try_new(base_bits, lengths, tag_bits).unwrap_or_else(|e| panic!("TAGE {}", e))
+    }
+
+    /// A base of `1 << base_bits` counters and a tagged table of an
+    /// eighth of that per history length, shortest first
+    pub fn try_new(
+        base_bits: usize,
+        lengths: &[usize],
+        tag_bits: usize,
+    ) -> Result<TageBp, AllocError> {
+        assert!(base_bits > TAGE_TABLE_SHRINK && tag_bits >= 2 && !lengths.is_empty());
+        let table_bits = base_bits - TAGE_TABLE_SHRINK;
+        let mut folds = HistorySet::new();
+        let mut tables = vec![];
+        for &length in lengths {
+            tables.push(TageTable {
+                entries: table(table_bits, TageEntry::default())?,
+                index_fold: folds.add(length, table_bits),
+                tag_folds: [folds.add(length, tag_bits), folds.add(length, tag_bits - 1)],
+            });
+        }
+        Ok(TageBp {
+            base: CounterTable::try_new(base_bits)?,
+            lengths: lengths.to_vec(),
+            table_bits,
+            tag_bits,
+            tables,
+            folds,
+            use_alt_on_na: 0,
+            updates: 0,
+            rng: StdRng::seed_from_u64(0),
+        })
+    }
+
+    fn index(&self, i: usize, addr: usize) -> usize {
+        let addr = addr >> 1;
+        let fold = self.folds.fold(self.tables[i].index_fold);
+        (addr ^ addr >> (self.table_bits + i) ^ fold) & ((1 << self.table_bits) - 1)
+    }
+
+    fn tag(&self, i: usize, addr: usize) -> usize {
+        let [a, b] = self.tables[i].tag_folds;
+        (addr >> 1 ^ self.folds.fold(a) ^ self.folds.fold(b) << 1) & ((1 << self.tag_bits) - 1)
+    }
+
+    fn entry(&self, i: usize, addr: usize) -> &TageEntry {
+        &self.tables[i].entries[self.index(i, addr)]
+    }
+
+    fn entry_mut(&mut self, i: usize, addr: usize) -> &mut TageEntry {
+        let index = self.index(i, addr);
+        &mut self.tables[i].entries[index]
+    }
+
+    fn access(&self, addr: usize) -> TageAccess {
+        let mut hits = (0..self.tables.len())
+            .rev()
+            .filter(|&i| self.entry(i, addr).valid && self.entry(i, addr).tag == self.tag(i, addr));
+        let (provider, alt) = (hits.next(), hits.next());
+
+        let base = self.base.get(addr >> 1);
+        let (alt_taken, alt_confident) = match alt {
+            Some(i) => (self.entry(i, addr).taken(), !self.entry(i, addr).weak()),
+            None => (base.value(), base.confident()),
+        };
+        let (predicted, confident) = match provider {
+            Some(i) => {
+                let e = self.entry(i, addr);
+                if e.fresh() && self.use_alt_on_na >= 0 {
+                    (alt_taken, alt_confident)
+                } else {
+                    (e.taken(), !e.weak())
+                }
+            }
+            None => (alt_taken, alt_confident),
+        };
+        TageAccess {
+            provider,
+            alt_taken,
+            predicted,
+            confident,
+        }
+    }
+
+    /// Allocates an entry for `addr` in a table longer than `start`,
+    /// the first free one or, a third of the time, the second
+    fn allocate(&mut self, start: usize, addr: usize, was_taken: bool) {
+        let free: Vec<usize> = (start..self.tables.len())
+            .filter(|&i| self.entry(i, addr).u == 0)
+            .collect();
+        let chosen = match free[..] {
+            [] => None,
+            [first] => Some(first),
+            [first, second, ..] => Some(if self.rng.gen_bool(1.0 / 3.0) {
+                second
+            } else {
+                first
+            }),
+        };
+        match chosen {
+            Some(i) => {
+                let tag = self.tag(i, addr);
+                *self.entry_mut(i, addr) = TageEntry {
+                    valid: true,
+                    ctr: if was_taken { 0 } else { -1 },
+                    tag,
+                    u: 0,
+                };
+            }
+            None => {
+                for i in start..self.tables.len() {
+                    let e = self.entry_mut(i, addr);
+                    e.u = e.u.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    /// Learns `was_taken` where `access` found `addr`
+    fn learn(&mut self, addr: usize, access: TageAccess, was_taken: bool) {
+        let TageAccess {
+            provider,
+            alt_taken,
+            predicted,
+            ..
+        } = access;
+
+        match provider {
+            Some(i) => {
+                let e = *self.entry(i, addr);
+                if e.taken() != alt_taken {
+                    if e.fresh() {
+                        self.use_alt_on_na = if alt_taken == was_taken {
+                            (self.use_alt_on_na + 1).min(TAGE_ALT_MAX)
+                        } else {
+                            (self.use_alt_on_na - 1).max(TAGE_ALT_MIN)
+                        };
+                    }
+                    let e = self.entry_mut(i, addr);
+                    e.u = if e.taken() == was_taken {
+                        (e.u + 1).min(TAGE_U_MAX)
+                    } else {
+                        e.u.saturating_sub(1)
+                    };
+                }
+                let e = self.entry_mut(i, addr);
+                e.ctr = if was_taken {
+                    (e.ctr + 1).min(TAGE_CTR_MAX)
+                } else {
+                    (e.ctr - 1).max(TAGE_CTR_MIN)
+                };
+            }
+            None => self.base.update(addr >> 1, was_taken),
+        }
+
+        let longer = provider.map_or(0, |i| i + 1);
+        if predicted != was_taken && longer < self.tables.len() {
+            self.allocate(longer, addr, was_taken);
+        }
+
+        self.updates += 1;
+        if self.updates.is_multiple_of(TAGE_U_PERIOD) {
+            for e in self.tables.iter_mut().flat_map(|t| t.entries.iter_mut()) {
+                e.u >>= 1;
+            }
+        }
+        self.folds.update(was_taken);
+    }
+}
+
+impl Predictor for TageBp {
+    fn predict_with_confidence(&mut self, addr: usize, was_taken: bool) -> (bool, bool) {
+        let access = self.access(addr);
+        let prediction = (access.predicted, access.confident);
+        self.learn(addr, access, was_taken);
+
+        prediction
+    }
+
+    fn update(&mut self, addr: usize, _predicted: bool, was_taken: bool) {
+        let access = self.access(addr);
+        self.learn(addr, access, was_taken);
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        let access = self.access(addr);
+        (access.predicted, access.confident)
+    }
+
+    fn history_snapshot(&self) -> HistorySnapshot {
+        self.folds.snapshot()
+    }
+
+    fn pin_history(&mut self, snapshot: Option<&HistorySnapshot>) {
+        self.folds.pin(snapshot);
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let tables = self.tables.len();
+        let name = if self.lengths == geometric_lengths(tables, TAGE_MIN_HISTORY, TAGE_MAX_HISTORY)
+        {
+            "TAGE".to_string()
+        } else {
+            let lengths: Vec<String> = self.lengths.iter().map(|l| l.to_string()).collect();
+            format!("TAGE[history={}]", lengths.join("/"))
+        };
+        // With the valid bit
+        let entry_bits = 1 + TAGE_CTR_BITS + self.tag_bits + TAGE_U_BITS;
+        (
+            name,
+            vec![self.base.bits(), tables, self.tag_bits],
+            self.base.size_bits() + tables * (entry_bits << self.table_bits) + TAGE_ALT_BITS,
+        )
+    }
+
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("base_bits", self.base.bits()),
+            ("tables", self.tables.len()),
+            ("tag_bits", self.tag_bits),
+        ]
+    }
+
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.base.heap_bytes()
+            + self
+                .tables
+                .iter()
+                .map(|t| table_bytes(&t.entries))
+                .sum::<usize>()
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.base.states())
+    }
+}
+
+/// Which components of a ChainBp learn from each event
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ChainTraining {
+    #[default]
+    All,
+    /// Only those consulted, up to and including the one chosen, as
+    /// asking a component for its prediction also trains it
+    Consulted,
+}
+
+impl std::fmt::Display for ChainTraining {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChainTraining::All => write!(f, "all"),
+            ChainTraining::Consulted => write!(f, "consulted"),
+        }
+    }
+}
+
+impl std::str::FromStr for ChainTraining {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(ChainTraining::All),
+            "consulted" => Ok(ChainTraining::Consulted),
+            _ => Err(format!("bad chain training {}", s)),
+        }
+    }
+}
+
+/*
+ * TournamentBp is the combining predictor of McFarling ("Combining
+ * Branch Predictors", 1993) and the Alpha 21264: a PC indexed table of
+ * 2-bit counters chooses, per branch, which of two predictors to
+ * believe, learning toward whichever was right when they disagree.
+ * Both always learn.
+ */
+pub struct TournamentBp {
+    first: Box<dyn Predictor>,
+    second: Box<dyn Predictor>,
+    // Taken believes the second
+    choice: CounterTable<TwoBitCounter>,
+}
+
+impl TournamentBp {
+    pub fn new(
+        first: Box<dyn Predictor>,
+        second: Box<dyn Predictor>,
+        choice_bits: usize,
+    ) -> TournamentBp {
+        TournamentBp::try_new(first, second, choice_bits)
+            .unwrap_or_else(|e| panic!("Tournament {}", e))
+    }
+
+    pub fn try_new(
+        first: Box<dyn Predictor>,
+        second: Box<dyn Predictor>,
+        choice_bits: usize,
+    ) -> Result<TournamentBp, AllocError> {
+        Ok(TournamentBp {
+            first,
+            second,
+            choice: CounterTable::try_new(choice_bits)?,
+        })
+    }
+
+    fn chosen(&self, addr: usize) -> &dyn Predictor {
+        if self.choice.predict(addr >> 1) {
+            self.second.as_ref()
+        } else {
+            self.first.as_ref()
+        }
+    }
+}
+
+impl Predictor for TournamentBp {
+    fn update(&mut self, addr: usize, _predicted: bool, was_taken: bool) {
+        let (first, second) = (self.first.predict(addr), self.second.predict(addr));
+        if first != second {
+            self.choice.update(addr >> 1, second == was_taken);
+        }
+        self.first.update(addr, first, was_taken);
+        self.second.update(addr, second, was_taken);
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        self.chosen(addr).query(addr)
+    }
+
+    fn predict_prob(&self, addr: usize) -> f64 {
+        self.chosen(addr).predict_prob(addr)
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let (first, first_config, first_bits) = self.first.report();
+        let (second, second_config, second_bits) = self.second.report();
+        (
+            format!(
+                "Tournament({}{:?} | {}{:?})",
+                first, first_config, second, second_config
+            ),
+            vec![self.choice.bits()],
+            first_bits + second_bits + self.choice.size_bits(),
+        )
+    }
 
-pub trait Predictor {
-    // XXX Make predict_and_update process a batch of branch events
-    fn predict_and_update(&mut self, addr: usize, was_taken: bool);
+    fn params(&self) -> Vec<(&'static str, usize)> {
+        vec![("choice_bits", self.choice.bits())]
+    }
 
-    fn report(&self) -> (String, Vec<usize>, usize, usize);
-}
+    fn footprint_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.choice.heap_bytes()
+            + self.first.footprint_bytes()
+            + self.second.footprint_bytes()
+    }
 
-pub struct NoneTakenBp {
-    misses: usize,
-}
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(
+            self.choice
+                .states()
+                .chain(self.first.table_states())
+                .chain(self.second.table_states()),
+        )
+    }
 
-impl NoneTakenBp {
-    pub fn new() -> NoneTakenBp {
-        NoneTakenBp { misses: 0 }
+    fn set_history_policy(&mut self, policy: HistoryPolicy) {
+        self.first.set_history_policy(policy);
+        self.second.set_history_policy(policy);
     }
-}
 
-impl Predictor for NoneTakenBp {
-    fn predict_and_update(&mut self, _addr: usize, was_taken: bool) {
-        self.misses += was_taken as usize;
+    fn init_from_bias(&mut self, profile: &BiasProfile) {
+        self.first.init_from_bias(profile);
+        self.second.init_from_bias(profile);
     }
-    fn report(&self) -> (String, Vec<usize>, usize, usize) {
-        ("NoneTaken".to_string(), vec![], 0, self.misses)
+
+    fn set_fetch_width(&mut self, width: usize) {
+        self.first.set_fetch_width(width);
+        self.second.set_fetch_width(width);
     }
-}
 
-pub struct LocalBp {
-    addr_bits: usize,
-    pht: Vec<TwoBitCounter>,
-    addr_mask: usize,
-    misses: usize,
-}
+    fn set_thread(&mut self, thread: usize) {
+        self.first.set_thread(thread);
+        self.second.set_thread(thread);
+    }
 
-impl LocalBp {
-    pub fn new(addr_bits: usize) -> LocalBp {
-        let pht = vec![TwoBitCounter::new(true); 1 << addr_bits];
-        LocalBp {
-            addr_bits,
-            pht,
-            addr_mask: (1 << addr_bits) - 1,
-            misses: 0,
-        }
+    fn set_history_reset_on_miss(&mut self, bits: usize) {
+        self.first.set_history_reset_on_miss(bits);
+        self.second.set_history_reset_on_miss(bits);
     }
-}
 
-impl Predictor for LocalBp {
-    fn predict_and_update(&mut self, addr: usize, was_taken: bool) {
-        let index = (addr >> 1) & self.addr_mask;
-        let predicted: bool = self.pht[index].value();
-        self.pht[index].update(was_taken);
-        self.misses += (predicted != was_taken) as usize;
+    fn history_snapshot(&self) -> HistorySnapshot {
+        HistorySnapshot::Components(vec![
+            self.first.history_snapshot(),
+            self.second.history_snapshot(),
+        ])
     }
 
-    fn report(&self) -> (String, Vec<usize>, usize, usize) {
-        (
-            "Two-level".to_string(),
-            vec![self.addr_bits],
-            (1 << self.addr_bits) * 2,
-            self.misses,
-        )
+    fn pin_history(&mut self, snapshot: Option<&HistorySnapshot>) {
+        let (first, second) = match snapshot {
+            Some(HistorySnapshot::Components(c)) => (c.first(), c.get(1)),
+            _ => (None, None),
+        };
+        self.first.pin_history(first);
+        self.second.pin_history(second);
     }
 }
 
-pub struct GshareBp {
-    addr_bits: usize,
-    history: usize,
-    pht: Vec<TwoBitCounter>,
-    addr_mask: usize,
-    misses: usize,
+/*
+ * ChainBp is an override chain: the components are asked left to
+ * right and the first one sure enough of its prediction, giving it
+ * odds of at least the component's threshold, makes it.  If none
+ * qualifies the last one does.
+ */
+pub struct ChainBp {
+    // Each with the odds it must give its prediction
+    components: Vec<(Box<dyn Predictor>, f64)>,
+    training: ChainTraining,
 }
 
-impl GshareBp {
-    pub fn new(addr_bits: usize) -> GshareBp {
-        GshareBp {
-            addr_bits,
-            history: 0,
-            pht: vec![TwoBitCounter::new(true); 1 << addr_bits],
-            addr_mask: (1 << addr_bits) - 1,
-            misses: 0,
-        }
-    }
+/// The odds a chained component must give its prediction unless told
+/// otherwise, which a strong counter's do and a weak one's don't
+pub const CHAIN_CONFIDENCE: f64 = 0.75;
+
+/// Whether `p` gives its prediction for `addr` odds of `min_odds` or
+/// better, as predict_prob has them
+fn sure_enough(p: &dyn Predictor, addr: usize, min_odds: f64) -> bool {
+    let prob = p.predict_prob(addr);
+    prob.max(1.0 - prob) >= min_odds
 }
 
-impl Predictor for GshareBp {
-    fn predict_and_update(&mut self, addr: usize, was_taken: bool) {
-        let index = ((addr >> 1) ^ self.history) & self.addr_mask;
-        let predicted: bool = self.pht[index].value();
-        self.pht[index].update(was_taken);
-        self.misses += (predicted != was_taken) as usize;
-        self.history = self.history << 1 | was_taken as usize;
+impl ChainBp {
+    /// Each component with the odds, from 0.5 to 1, it must give its
+    /// prediction to be chosen; the last one's don't matter
+    pub fn new(components: Vec<(Box<dyn Predictor>, f64)>) -> ChainBp {
+        assert!(!components.is_empty());
+        ChainBp {
+            components,
+            training: ChainTraining::All,
+        }
     }
 
-    fn report(&self) -> (String, Vec<usize>, usize, usize) {
-        (
-            "Gshare".to_string(),
-            vec![self.addr_bits],
-            (1 << self.addr_bits) * 2,
-            self.misses,
-        )
+    pub fn with_training(mut self, training: ChainTraining) -> ChainBp {
+        self.training = training;
+        self
     }
-}
 
-pub struct BimodalBp {
-    addr_bits: usize,
-    history: usize,
-    choice_pht: Vec<TwoBitCounter>,
-    direction_pht_nt: Vec<TwoBitCounter>,
-    direction_pht_t: Vec<TwoBitCounter>,
-    addr_mask: usize,
-    misses: usize,
+    fn chosen(&self, addr: usize) -> &dyn Predictor {
+        let (p, _) = self
+            .components
+            .iter()
+            .find(|(p, min_odds)| sure_enough(p.as_ref(), addr, *min_odds))
+            .unwrap_or_else(|| self.components.last().unwrap());
+        p.as_ref()
+    }
 }
 
-impl BimodalBp {
-    pub fn new(addr_bits: usize) -> BimodalBp {
-        let choice_pht = vec![TwoBitCounter::new(true); 1 << addr_bits];
-        let direction_pht_nt = vec![TwoBitCounter::new(true); 1 << addr_bits];
-        let direction_pht_t = vec![TwoBitCounter::new(true); 1 << addr_bits];
-        BimodalBp {
-            addr_bits,
-            history: 0,
-            choice_pht,
-            direction_pht_nt,
-            direction_pht_t,
-            addr_mask: (1 << addr_bits) - 1,
-            misses: 0,
+impl Predictor for ChainBp {
+    fn update(&mut self, addr: usize, _predicted: bool, was_taken: bool) {
+        for (p, min_odds) in self.components.iter_mut() {
+            let chosen = sure_enough(p.as_ref(), addr, *min_odds);
+            p.predict_and_update(addr, was_taken);
+            if chosen && self.training == ChainTraining::Consulted {
+                break;
+            }
         }
     }
-}
-
-impl Predictor for BimodalBp {
-    fn predict_and_update(&mut self, addr: usize, was_taken: bool) {
-        let choice_index = (addr >> 1) & self.addr_mask;
-        let direction_index = ((addr >> 1) ^ self.history) & self.addr_mask;
 
-        let choice = self.choice_pht[choice_index].value();
+    fn query(&self, addr: usize) -> (bool, bool) {
+        self.chosen(addr).query(addr)
+    }
 
-        let predicted;
+    fn predict_prob(&self, addr: usize) -> f64 {
+        self.chosen(addr).predict_prob(addr)
+    }
 
-        if choice {
-            predicted = self.direction_pht_t[direction_index].value();
-            self.direction_pht_t[direction_index].update(was_taken);
-        } else {
-            predicted = self.direction_pht_nt[direction_index].value();
-            self.direction_pht_nt[direction_index].update(was_taken);
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let mut names = vec![];
+        let mut size = 0;
+        let last = self.components.len() - 1;
+        for (i, (p, min_odds)) in self.components.iter().enumerate() {
+            let (alg, config, bits) = p.report();
+            if i < last && *min_odds != CHAIN_CONFIDENCE {
+                names.push(format!("{}{:?}@{}", alg, config, min_odds));
+            } else {
+                names.push(format!("{}{:?}", alg, config));
+            }
+            size += bits;
+        }
+        let training = match self.training {
+            ChainTraining::All => String::new(),
+            training => format!("[train={}]", training),
         };
+        (
+            format!("Chain{}({})", training, names.join(" > ")),
+            vec![],
+            size,
+        )
+    }
 
-        /* "The choice PHT is normally updated too, but not if it
-         * gives a prediction contradicting the branch outcome and the
-         * direction PHT chosen gives the correct prediction."
-         *
-         * That is, it's updated if we mispredicted or it disagreed
-         * with the actual direction */
+    fn footprint_bytes(&self) -> usize {
+        let components: usize = self
+            .components
+            .iter()
+            .map(|(p, _)| p.footprint_bytes())
+            .sum();
+        std::mem::size_of_val(self) + table_bytes(&self.components) + components
+    }
+
+    fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+        Box::new(self.components.iter().flat_map(|(p, _)| p.table_states()))
+    }
+
+    fn set_history_policy(&mut self, policy: HistoryPolicy) {
+        for (p, _) in self.components.iter_mut() {
+            p.set_history_policy(policy);
+        }
+    }
 
-        if predicted != was_taken || choice != was_taken {
-            self.choice_pht[choice_index].update(was_taken);
+    fn init_from_bias(&mut self, profile: &BiasProfile) {
+        for (p, _) in self.components.iter_mut() {
+            p.init_from_bias(profile);
         }
+    }
 
-        if predicted != was_taken {
-            self.misses += 1;
+    fn set_fetch_width(&mut self, width: usize) {
+        for (p, _) in self.components.iter_mut() {
+            p.set_fetch_width(width);
         }
+    }
 
-        self.history = self.history << 1 | was_taken as usize;
+    fn set_thread(&mut self, thread: usize) {
+        for (p, _) in self.components.iter_mut() {
+            p.set_thread(thread);
+        }
     }
 
-    fn report(&self) -> (String, Vec<usize>, usize, usize) {
-        (
-            "Bimodal".to_string(),
-            vec![self.addr_bits],
-            (self.choice_pht.capacity()
-                + self.direction_pht_t.capacity()
-                + self.direction_pht_nt.capacity())
-                * 2,
-            self.misses,
+    fn set_history_reset_on_miss(&mut self, bits: usize) {
+        for (p, _) in self.components.iter_mut() {
+            p.set_history_reset_on_miss(bits);
+        }
+    }
+
+    fn history_snapshot(&self) -> HistorySnapshot {
+        HistorySnapshot::Components(
+            self.components
+                .iter()
+                .map(|(p, _)| p.history_snapshot())
+                .collect(),
         )
     }
-}
 
-// YAGS1 = YAGS with a single direction table
-pub struct Yags1Bp {
-    addr_bits: usize,
-    dir_bits: usize,
-    tag_bits: usize,
-    history: usize,
-    choice_pht: Vec<TwoBitCounter>,
-    direction_pht: Vec<TwoBitCounter>,
-    direction_tag: Vec<usize>,
-    addr_mask: usize,
-    dir_mask: usize,
-    tag_mask: usize,
-    misses: usize,
+    fn pin_history(&mut self, snapshot: Option<&HistorySnapshot>) {
+        let snapshots = match snapshot {
+            Some(HistorySnapshot::Components(c)) => &c[..],
+            _ => &[],
+        };
+        for (i, (p, _)) in self.components.iter_mut().enumerate() {
+            p.pin_history(snapshots.get(i));
+        }
+    }
 }
 
-impl Yags1Bp {
-    pub fn new(addr_bits: usize, dir_bits: usize, tag_bits: usize) -> Yags1Bp {
-        let choice_pht = vec![TwoBitCounter::new(true); 1 << addr_bits];
-        let direction_pht = vec![TwoBitCounter::new(true); 1 << dir_bits];
-        let direction_tag = vec![0; 1 << dir_bits];
-        let tag_mask = (1 << tag_bits) - 1;
-        Yags1Bp {
-            addr_bits,
-            dir_bits,
-            tag_bits,
-            history: 0,
-            choice_pht,
-            direction_pht,
-            direction_tag,
-            addr_mask: (1 << addr_bits) - 1,
-            dir_mask: (1 << dir_bits) - 1,
-            tag_mask,
-            misses: 0,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sram::BitReader;
+    use crate::verify::synthetic_stream;
+
+    fn misses(mut p: impl Predictor, stream: &[(usize, bool)]) -> usize {
+        stream
+            .iter()
+            .filter(|&&(addr, was_taken)| p.predict_and_update(addr, was_taken) != was_taken)
+            .count()
+    }
+
+    // Config, then miss counts and sizes of the original, separate, YAGS1-4 implementations
+    type Golden = ((usize, usize, usize), [usize; 4], [usize; 4]);
+    const YAGS_GOLDEN: [Golden; 4] = [
+        (
+            (13, 13, 6),
+            [17184, 17189, 17169, 17144],
+            [81920, 81920, 163840, 163840],
+        ),
+        (
+            (13, 10, 6),
+            [17272, 17396, 17275, 17452],
+            [24576, 24576, 34816, 34816],
+        ),
+        (
+            (10, 12, 8),
+            [17432, 17448, 17358, 17410],
+            [43008, 43008, 92160, 92160],
+        ),
+        (
+            (12, 9, 4),
+            [17755, 17672, 17709, 17727],
+            [11264, 11264, 15360, 15360],
+        ),
+    ];
+
+    #[test]
+    fn yags_matches_originals() {
+        let stream = synthetic_stream(50_000, 1);
+        for ((a, d, t), golden_misses, golden_sizes) in YAGS_GOLDEN {
+            let variants = [
+                YagsBp::yags1(a, d, t).with_valid_bits(false),
+                YagsBp::yags2(a, d, t).with_valid_bits(false),
+                YagsBp::yags3(a, d, t).with_valid_bits(false),
+                YagsBp::yags4(a, d, t).with_valid_bits(false),
+            ];
+            let sizes: Vec<usize> = variants.iter().map(|p| p.report().2).collect();
+            let misses: Vec<usize> = variants.into_iter().map(|p| misses(p, &stream)).collect();
+            assert_eq!(misses, golden_misses, "config {:?}", (a, d, t));
+            assert_eq!(sizes, golden_sizes, "config {:?}", (a, d, t));
         }
     }
-}
 
-impl Predictor for Yags1Bp {
-    fn predict_and_update(&mut self, mut addr: usize, was_taken: bool) {
-        // First drop the constant zero LSB
-        addr >>= 1;
+    #[test]
+    fn empty_entries_miss_tag_zero() {
+        // 0x1000 hashes to tag 0 and direction index 0 on an empty history
+        for valid_bits in [true, false] {
+            let mut p = YagsBp::yags1(8, 7, 5).with_valid_bits(valid_bits);
+            assert_eq!(p.tag(0x1000 >> 1), 0);
+            p.predict_and_update(0x1000, false);
+            // Only a miss updates the choice, weakly taken to not taken
+            let choice = p.choice_pht.get(0x1000 >> 2).state();
+            assert_eq!(choice, if valid_bits { 1 } else { 2 });
+            assert_eq!(
+                p.direction_pht[0][0].state(),
+                if valid_bits { 2 } else { 1 }
+            );
+        }
+        let sizes = |p: YagsBp| p.report().2;
+        assert_eq!(
+            sizes(YagsBp::yags3(8, 7, 5)) - sizes(YagsBp::yags3(8, 7, 5).with_valid_bits(false)),
+            2 * 128
+        );
+    }
 
-        // Split the address into index and tag
-        let addr_index = (addr >> 1) & self.addr_mask;
-        let hash_index = (addr ^ self.history) & self.dir_mask;
-        let hash_tag = addr & self.tag_mask;
+    #[test]
+    fn alloc_policies() {
+        let stream = synthetic_stream(50_000, 1);
+        let always = misses(YagsBp::yags3(10, 8, 6), &stream);
+        let never = YagsBp::yags3(10, 8, 6).with_alloc_policy(AllocPolicy::Percent(0));
+        assert_ne!(misses(never, &stream), always);
+        assert_eq!(
+            misses(
+                YagsBp::yags3(10, 8, 6).with_alloc_policy(AllocPolicy::Percent(100)),
+                &stream
+            ),
+            always
+        );
+
+        let half = YagsBp::yags3(10, 8, 6).with_alloc_policy("50%".parse().unwrap());
+        assert_eq!(half.report().0, "YAGS3[alloc=50%]");
+    }
 
-        // Access
-        let predicted = if self.direction_tag[hash_index] == hash_tag {
-            self.direction_pht[hash_index].value()
-        } else {
-            self.choice_pht[addr_index].value()
-        };
+    #[test]
+    fn gag_ignores_address() {
+        // A repeating global pattern is learnt wherever it's fetched from
+        let stream: Vec<(usize, bool)> = (0..1000).map(|i| (i * 4, i % 3 != 0)).collect();
+        assert!(misses(GAgBp::try_new(4).unwrap(), &stream) < 10);
+        assert!(misses(GshareBp::new(4), &stream) > 10);
+    }
 
-        // Update
-        if self.direction_tag[hash_index] == hash_tag {
-            self.direction_pht[hash_index].update(was_taken);
-        } else {
-            // The choice is updated on misses
-            self.choice_pht[addr_index].update(was_taken);
-            if self.choice_pht[addr_index].value() != was_taken {
-                self.direction_tag[hash_index] = hash_tag;
-                self.direction_pht[hash_index] = TwoBitCounter::new(was_taken);
-            }
+    #[test]
+    fn sram_round_trip() {
+        let stream = synthetic_stream(20_000, 3);
+        let mut gshare = GshareBp::new(7);
+        let mut bimodal = BimodalBp::new(6);
+        let mut yags1 = YagsBp::yags1(8, 7, 5);
+        let mut yags3 = YagsBp::yags3(8, 7, 5);
+        for &(addr, was_taken) in &stream {
+            gshare.predict_and_update(addr, was_taken);
+            bimodal.predict_and_update(addr, was_taken);
+            yags1.predict_and_update(addr, was_taken);
+            yags3.predict_and_update(addr, was_taken);
         }
 
-        if predicted != was_taken {
-            self.misses += 1;
+        let states = |table: &[TwoBitCounter]| -> Vec<usize> {
+            table.iter().map(|c| c.state() as usize).collect()
+        };
+        let read_counters = |r: &mut BitReader, n: usize| -> Vec<usize> {
+            let counters = (0..n).map(|_| r.read(2)).collect();
+            r.align();
+            counters
+        };
+
+        let mut image = vec![];
+        gshare.dump_sram(&mut image).unwrap();
+        assert_eq!(image.len(), 128 * 2 / 8);
+        let mut r = BitReader::new(&image);
+        assert_eq!(read_counters(&mut r, 128), states(gshare.pht.counters()));
+        assert!(r.is_empty());
+
+        let mut image = vec![];
+        bimodal.dump_sram(&mut image).unwrap();
+        let mut r = BitReader::new(&image);
+        assert_eq!(
+            read_counters(&mut r, 64),
+            states(bimodal.choice_pht.counters())
+        );
+        assert_eq!(
+            read_counters(&mut r, 64),
+            states(bimodal.direction_pht_nt.counters())
+        );
+        assert_eq!(
+            read_counters(&mut r, 64),
+            states(bimodal.direction_pht_t.counters())
+        );
+        assert!(r.is_empty());
+
+        for yags in [yags1, yags3] {
+            let mut image = vec![];
+            yags.dump_sram(&mut image).unwrap();
+            let mut r = BitReader::new(&image);
+            assert_eq!(
+                read_counters(&mut r, 256),
+                states(yags.choice_pht.counters())
+            );
+            for n in 0..yags.ways() {
+                for i in 0..128 {
+                    assert_eq!(r.read(2), yags.direction_pht[n][i].state() as usize);
+                    if yags.ways() > 1 {
+                        assert_eq!(r.read(1) == 1, yags.direction_u[n][i]);
+                    }
+                    assert_eq!(r.read(1) == 1, yags.direction_valid[n][i]);
+                    assert_eq!(r.read(5), yags.direction_tag[n][i]);
+                }
+                r.align();
+            }
+            assert!(r.is_empty());
+            // Matches the reported size, as no table needs padding here
+            assert_eq!(image.len() * 8, yags.report().2);
         }
+    }
 
-        self.history = self.history << 1 | was_taken as usize;
+    #[test]
+    fn confidence_grades_counters() {
+        let mut p = LocalBp::new(4);
+        // Weakly taken, then strongly not taken after two updates
+        assert_eq!(p.predict_with_confidence(8, false), (true, false));
+        assert_eq!(p.predict_with_confidence(8, false), (false, false));
+        assert_eq!(p.predict_with_confidence(8, false), (false, true));
+        assert_eq!(
+            NoneTakenBp::new().predict_with_confidence(8, true),
+            (false, true)
+        );
     }
 
-    fn report(&self) -> (String, Vec<usize>, usize, usize) {
-        (
-            "YAGS1".to_string(),
-            vec![self.addr_bits, self.dir_bits, self.tag_bits],
-            self.choice_pht.capacity() * 2 + self.direction_pht.capacity() * (2 + self.tag_bits),
-            self.misses,
-        )
+    #[test]
+    fn counter_tables_mask_indices() {
+        let mut t = CounterTable::<TwoBitCounter>::try_new(4).unwrap();
+        t.update(0x13, false);
+        assert!(!t.predict(3));
+        assert_eq!(t.get(0x23).state(), 1);
+        assert_eq!(t.size_bits(), 32);
+        assert_eq!(t.states().filter(|&s| s == 2).count(), 15);
+        t.resize(5, true);
+        assert_eq!((t.bits(), t.get(3).state(), t.get(19).state()), (5, 1, 1));
+        assert_eq!(
+            CounterTable::<ProbablyBool>::try_new(4)
+                .unwrap()
+                .size_bits(),
+            48
+        );
     }
-}
 
-/* YAGS2 = YAGS1 + history hashed index  */
-pub struct Yags2Bp {
-    addr_bits: usize,
-    dir_bits: usize,
-    tag_bits: usize,
-    history: usize,
-    choice_pht: Vec<TwoBitCounter>,
-    direction_pht: Vec<TwoBitCounter>,
-    direction_tag: Vec<usize>,
-    addr_mask: usize,
-    dir_mask: usize,
-    tag_mask: usize,
-    misses: usize,
-}
+    #[test]
+    fn sizes_count_entry_bits() {
+        let two = TwoBitCounter::BITS;
+        let sizes: Vec<(Box<dyn Predictor>, usize)> = vec![
+            (Box::new(NoneTakenBp::new()), 0),
+            (Box::new(LocalBp::new(10)), 1024 * two),
+            (
+                Box::new(ProbabilisticLocalBp::try_new(10, 0).unwrap()),
+                1024 * ProbablyBool::BITS,
+            ),
+            (Box::new(GshareBp::new(10)), 1024 * two),
+            (Box::new(GAgBp::try_new(10).unwrap()), 1024 * two),
+            (
+                Box::new(PrevPcGshareBp::try_new(10, 2).unwrap()),
+                1024 * two + 2 * 10,
+            ),
+            (Box::new(BimodalBp::new(10)), 3 * 1024 * two),
+            // Counter, tag and valid bit
+            (Box::new(TaggedGshareBp::new(10, 4)), 1024 * (two + 4 + 1)),
+            (
+                Box::new(
+                    TaggedGshareBp::new(10, 4)
+                        .try_with_fallback(TagFallback::Bimodal)
+                        .unwrap(),
+                ),
+                1024 * (two + 4 + 1) + 1024 * two,
+            ),
+            // Counter, tag and valid bit
+            (
+                Box::new(YagsBp::yags1(10, 8, 6)),
+                1024 * two + 256 * (two + 6 + 1),
+            ),
+            (
+                Box::new(YagsBp::yags2(10, 8, 6).with_valid_bits(false)),
+                1024 * two + 256 * (two + 6),
+            ),
+            // Two ways, each entry also with a u-bit
+            (
+                Box::new(YagsBp::yags3(10, 8, 6)),
+                1024 * two + 2 * 256 * (two + 6 + 1 + 1),
+            ),
+        ];
+        for (p, bits) in &sizes {
+            assert_eq!(p.report().2, *bits, "{}", p.report().0);
+        }
+        let chain = ChainBp::new(vec![
+            (Box::new(LocalBp::new(10)), CHAIN_CONFIDENCE),
+            (Box::new(GshareBp::new(10)), 0.5),
+        ]);
+        assert_eq!(chain.report().2, 2 * 1024 * two);
+    }
 
-impl Yags2Bp {
-    pub fn new(addr_bits: usize, dir_bits: usize, tag_bits: usize) -> Yags2Bp {
-        let choice_pht = vec![TwoBitCounter::new(true); 1 << addr_bits];
-        let direction_pht = vec![TwoBitCounter::new(true); 1 << dir_bits];
-        let direction_tag = vec![0; 1 << dir_bits];
-        let tag_mask = (1 << tag_bits) - 1;
-        Yags2Bp {
-            addr_bits,
-            dir_bits,
-            tag_bits,
-            history: 0,
-            choice_pht,
-            direction_pht,
-            direction_tag,
-            addr_mask: (1 << addr_bits) - 1,
-            dir_mask: (1 << dir_bits) - 1,
-            tag_mask,
-            misses: 0,
+    // A floor well below what even slow CI machines do, to only catch
+    // cliffs like a bounds check or allocation in the hot path.  Run
+    // with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn gshare_throughput() {
+        let floor = if cfg!(debug_assertions) { 2e6 } else { 20e6 };
+        let stream = synthetic_stream(1 << 20, 1);
+        let mut gshare = GshareBp::new(14);
+        let rounds = 20;
+        let start = std::time::Instant::now();
+        let mut misses = 0;
+        for _ in 0..rounds {
+            for &(addr, was_taken) in &stream {
+                misses += (gshare.predict_and_update(addr, was_taken) != was_taken) as usize;
+            }
         }
+        let rate = (rounds * stream.len()) as f64 / start.elapsed().as_secs_f64();
+        println!(
+            "Gshare: {:.1} Mpredictions/s ({} misses)",
+            rate / 1e6,
+            misses
+        );
+        assert!(rate > floor, "{:.1} Mpredictions/s", rate / 1e6);
     }
-}
 
-impl Predictor for Yags2Bp {
-    fn predict_and_update(&mut self, mut addr: usize, was_taken: bool) {
-        // First drop the constant zero LSB
-        addr >>= 1;
+    #[test]
+    fn resize_preserves_aliases() {
+        let stream = synthetic_stream(20_000, 5);
+        let mut gshare = GshareBp::new(8);
+        for &(addr, was_taken) in &stream {
+            gshare.predict_and_update(addr, was_taken);
+        }
+        let old: Vec<u8> = gshare.table_states().collect();
 
-        /*
-         * For the history bits in the tag, use a different fold than
-         * what you use in the index something like
-         *
-         *   {address_bits[1:4], address_bits[5:8] ^ history_bits}
-         *
-         * for the index having folding is good if you decide you want
-         * larger history than your index bit Us
-         *
-         *   ((addr >> 1) & 0xf) << 4) | (((addr >> 4) ^ hist) & 0xf)
-         *
-         * when you add history to the tag, keep some bits from being
-         * xored probably easier to say:
-         *
-         *   addr ^ ((hist << 4) & 0xff)
-         *
-         */
+        gshare.resize(10, true).unwrap();
+        assert_eq!(gshare.report().2, 1024 * 2);
+        let grown: Vec<u8> = gshare.table_states().collect();
+        assert!((0..1024).all(|i| grown[i] == old[i % 256]));
 
-        let addr_index = (addr >> 1) & self.addr_mask;
+        gshare.resize(6, true).unwrap();
+        assert_eq!(gshare.table_states().collect::<Vec<u8>>(), old[..64]);
 
-        // This was very poor
-        //        let hash_index = (((addr >> 1) & 15) * 16 + ((addr >> 5) ^ history) & 15) & self.addr_mask;
-        //        let hash_tag   = (addr ^ (history << 4)) & self.tag_mask;
+        gshare.resize(6, false).unwrap();
+        assert!(gshare.table_states().all(|s| s == 2));
+        assert!(YagsBp::yags1(8, 8, 6).resize(10, true).is_err());
+    }
 
-        //      let hash_index = (((addr >> 1) & 15) * 16 + ((addr >> 5) ^ self.history) & 15) & self.addr_mask;
+    #[test]
+    fn hash_seeds() {
+        let stream = synthetic_stream(50_000, 1);
+        let ((a, d, t), golden, _) = YAGS_GOLDEN[1];
+        let unseeded = YagsBp::yags3(a, d, t)
+            .with_valid_bits(false)
+            .with_hash_seeds(0, 0);
+        assert_eq!(misses(unseeded, &stream), golden[2]);
+
+        let seeded = YagsBp::yags3(a, d, t)
+            .with_valid_bits(false)
+            .with_hash_seeds(1, 2);
+        assert_eq!(
+            seeded.report().0,
+            "YAGS3[index_seed=1,tag_seed=2,valid=false]"
+        );
+        assert_ne!(misses(seeded, &stream), golden[2]);
+    }
 
-        let hash_index = (addr ^ self.history) & self.dir_mask;
+    #[test]
+    fn probabilistic_is_reproducible() {
+        let stream = synthetic_stream(20_000, 1);
+        let run = |seed| misses(ProbabilisticLocalBp::try_new(10, seed).unwrap(), &stream);
+        assert_eq!(run(0), run(0));
+        assert_eq!(
+            ProbabilisticLocalBp::try_new(10, 3).unwrap().report().2,
+            3 * 1024
+        );
+    }
 
-        // {address_bits[1:4], address_bits[5:8] ^ history_bits}
-        let hash_tag = ((addr & 30) << 4 | (addr >> 5 ^ self.history) & 15) & self.tag_mask;
+    #[test]
+    fn choice_update_policies() {
+        let stream = synthetic_stream(50_000, 1);
+        // The paper's rule, as originally hardcoded
+        const PAPER_MISSES: usize = 19485;
+        assert_eq!(misses(BimodalBp::new(10), &stream), PAPER_MISSES);
+        let paper = BimodalBp::new(10).with_choice_update(ChoiceUpdate::Paper);
+        assert_eq!(misses(paper, &stream), PAPER_MISSES);
+
+        for policy in ["always", "mispredict", "agreement"] {
+            let p = BimodalBp::new(10).with_choice_update(policy.parse().unwrap());
+            assert_eq!(p.report().0, format!("Bimodal[choice={}]", policy));
+            assert_ne!(misses(p, &stream), PAPER_MISSES, "{}", policy);
+        }
+    }
 
-        // Access
-        let predicted = if self.direction_tag[hash_index] == hash_tag {
-            self.direction_pht[hash_index].value()
-        } else {
-            self.choice_pht[addr_index].value()
-        };
+    #[test]
+    fn bimodal_init_biases_warm_up() {
+        // Each branch twice, never taken
+        let not_taken: Vec<(usize, bool)> = (0..2000).map(|i| (i % 1000 * 2, false)).collect();
+        assert_eq!(misses(BimodalBp::new(10), &not_taken), 2000);
+        for init in ["weak-not-taken", "strong-not-taken"] {
+            let p = BimodalBp::new(10).with_init(init.parse().unwrap());
+            assert_eq!(p.report().0, format!("Bimodal[init={}]", init));
+            assert_eq!(misses(p, &not_taken), 0, "{}", init);
+        }
+        let p = BimodalBp::new(4).with_init(CounterInit::StronglyNotTaken);
+        assert_eq!(p.query(6), (false, true));
+        let p = BimodalBp::new(4)
+            .with_choice_update(ChoiceUpdate::Always)
+            .with_init(CounterInit::StronglyTaken);
+        assert_eq!(p.report().0, "Bimodal[choice=always,init=strong-taken]");
+        assert_eq!(p.query(6), (true, true));
+    }
 
-        // Update
-        if self.direction_tag[hash_index] == hash_tag {
-            self.direction_pht[hash_index].update(was_taken);
-        } else {
-            // The choice is updated on misses
-            self.choice_pht[addr_index].update(was_taken);
-            if self.choice_pht[addr_index].value() != was_taken {
-                self.direction_tag[hash_index] = hash_tag;
-                self.direction_pht[hash_index] = TwoBitCounter::new(was_taken);
-            }
+    #[test]
+    fn partial_tags_dealias_gshare() {
+        // 0x2 has tag 0, which its cold entry matches no more than any
+        // other, so takes it over.  With the history then 1 both 0x0
+        // and 0x10 index it, but only 0x0 has the tag of
+        let mut p = TaggedGshareBp::new(3, 2);
+        assert_eq!(p.query(0x2), (false, false));
+        p.predict_and_update(0x2, true);
+        assert_eq!(p.query(0x0), (true, false));
+        assert_eq!(p.query(0x10), (false, false));
+
+        let stream = synthetic_stream(50_000, 1);
+        let gshare = misses(GshareBp::new(8), &stream);
+        let tagged = TaggedGshareBp::new(8, 4)
+            .try_with_fallback(TagFallback::Bimodal)
+            .unwrap();
+        assert_eq!(tagged.report().0, "TaggedGshare[fallback=bimodal]");
+        assert!(misses(tagged, &stream) < gshare);
+    }
+
+    #[test]
+    fn port_accounting() {
+        let stream = synthetic_stream(20_000, 1);
+        let mut yags3 = YagsBp::yags3(10, 8, 6);
+        let mut yags3_wide = YagsBp::yags3(10, 8, 6).with_write_ports(3);
+        for &(addr, was_taken) in &stream {
+            yags3.predict_and_update(addr, was_taken);
+            yags3_wide.predict_and_update(addr, was_taken);
         }
 
-        if predicted != was_taken {
-            self.misses += 1;
+        let ports = yags3.port_stats().unwrap();
+        assert_eq!(ports.events, stream.len());
+        assert_eq!(ports.reads, 3 * stream.len());
+        // Every event writes something, and allocations need a second port
+        assert!(ports.writes > stream.len());
+        assert!(ports.stalls > 0);
+        assert_eq!(yags3_wide.port_stats().unwrap().stalls, 0);
+        assert_eq!(GshareBp::new(10).port_stats(), None);
+    }
+
+    #[test]
+    fn prev_pc_separates_paths() {
+        // B follows A1 or A2 at random, both always taken, and goes the
+        // way its predecessor says; only the path tells them apart
+        let mut rng = StdRng::seed_from_u64(1);
+        let (a1, a2, b) = (0x1000, 0x2340, 0x5678);
+        let mut stream = vec![];
+        for _ in 0..10_000 {
+            let first = rng.gen_bool(0.5);
+            stream.push((if first { a1 } else { a2 }, true));
+            stream.push((b, first));
         }
 
-        self.history = self.history << 1 | was_taken as usize;
+        let gshare = misses(GshareBp::new(10), &stream);
+        assert_eq!(
+            misses(PrevPcGshareBp::try_new(10, 0).unwrap(), &stream),
+            gshare
+        );
+        let prev_pc = misses(PrevPcGshareBp::try_new(10, 1).unwrap(), &stream);
+        assert!(prev_pc * 10 < gshare, "{} vs {}", prev_pc, gshare);
+        assert_eq!(
+            PrevPcGshareBp::try_new(10, 2).unwrap().report(),
+            ("PrevPcGshare".to_string(), vec![10, 2], 2048 + 20)
+        );
     }
 
-    fn report(&self) -> (String, Vec<usize>, usize, usize) {
-        (
-            "YAGS2".to_string(),
-            vec![self.addr_bits, self.dir_bits, self.tag_bits],
-            self.choice_pht.capacity() * 2 + self.direction_pht.capacity() * (2 + self.tag_bits),
-            self.misses,
-        )
+    #[test]
+    fn batches_match_single_steps() {
+        let stream = synthetic_stream(20_000, 3);
+        let configs = [
+            (HistoryPolicy::Retire, 1, 0),
+            (HistoryPolicy::Speculative, 1, 0),
+            (HistoryPolicy::Retire, 2, 0),
+            (HistoryPolicy::Speculative, 3, 0),
+            (HistoryPolicy::Retire, 1, 2),
+            (HistoryPolicy::Retire, 2, 2),
+        ];
+        for (policy, width, reset) in configs {
+            // Small tables collide often, large ones rarely
+            for bits in [4, 16] {
+                let mut single = GshareBp::new(bits);
+                let mut batched = GshareBp::new(bits);
+                for p in [&mut single, &mut batched] {
+                    p.set_history_policy(policy);
+                    p.set_fetch_width(width);
+                    p.set_history_reset_on_miss(reset);
+                }
+                let expected: Vec<bool> = stream
+                    .iter()
+                    .map(|&(addr, taken)| single.predict_and_update(addr, taken))
+                    .collect();
+                // Batches that aren't a multiple of the lanes
+                let mut predictions = vec![];
+                for batch in stream.chunks(1000) {
+                    predictions.extend(batched.predict_and_update_batch(batch));
+                }
+                let config = (policy, width, reset, bits);
+                assert!(predictions == expected, "{:?}", config);
+                assert!(single.table_states().eq(batched.table_states()));
+            }
+        }
     }
-}
 
-/* YAGS3 = YAGS1 + u-bits + 2-way associative directions */
-pub struct Yags3Bp {
-    addr_bits: usize,
-    dir_bits: usize,
-    tag_bits: usize,
-    history: usize,
-    choice_pht: Vec<TwoBitCounter>,
-    direction_pht: [Vec<TwoBitCounter>; 2],
-    direction_tag: [Vec<usize>; 2],
-    direction_u: [Vec<bool>; 2],
-    addr_mask: usize,
-    dir_mask: usize,
-    tag_mask: usize,
-    misses: usize,
-}
+    #[test]
+    fn local_batches_match_single_steps() {
+        let stream = synthetic_stream(20_000, 4);
+        for bits in [4, 16] {
+            let mut single = LocalBp::new(bits);
+            let mut batched = LocalBp::new(bits);
+            let expected: Vec<bool> = stream
+                .iter()
+                .map(|&(addr, taken)| single.predict_and_update(addr, taken))
+                .collect();
+            let mut predictions = vec![];
+            for batch in stream.chunks(1000) {
+                predictions.extend(batched.predict_and_update_batch(batch));
+            }
+            assert!(predictions == expected, "{}", bits);
+            assert!(single.table_states().eq(batched.table_states()));
+        }
+    }
 
-impl Yags3Bp {
-    pub fn new(addr_bits: usize, dir_bits: usize, tag_bits: usize) -> Yags3Bp {
-        let choice_pht = vec![TwoBitCounter::new(true); 1 << addr_bits];
-        let direction_pht = [
-            vec![TwoBitCounter::new(true); 1 << dir_bits],
-            vec![TwoBitCounter::new(true); 1 << dir_bits],
-        ];
-        let direction_tag = [vec![0; 1 << dir_bits], vec![0; 1 << dir_bits]];
-        let direction_u = [vec![false; 1 << dir_bits], vec![false; 1 << dir_bits]];
-        let tag_mask = (1 << tag_bits) - 1;
-        Yags3Bp {
-            addr_bits,
-            dir_bits,
-            tag_bits,
-            history: 0,
-            choice_pht,
-            direction_pht,
-            direction_tag,
-            direction_u,
-            addr_mask: (1 << addr_bits) - 1,
-            dir_mask: (1 << dir_bits) - 1,
-            tag_mask,
-            misses: 0,
+    #[test]
+    fn logs_arbitration() {
+        let mut bimodal = BimodalBp::new(4);
+        assert!(bimodal.log_arbitration(2));
+        for _ in 0..3 {
+            bimodal.predict_and_update(0x10, false);
         }
+        let log = bimodal.arbitration_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].provider, Provider::DirectionTaken);
+        assert_eq!((log[0].choice, log[0].state), (2, 2));
+        // The choice flipped to not taken on the miss
+        assert_eq!(log[1].provider, Provider::DirectionNotTaken);
+
+        let mut yags = YagsBp::yags1(4, 4, 6);
+        assert!(yags.log_arbitration(30));
+        for i in 0..30 {
+            yags.predict_and_update(0x10, i % 3 != 2);
+        }
+        let log = yags.arbitration_log();
+        assert_eq!((log[0].provider, log[0].tag_hit), (Provider::Choice, None));
+        // The not taken exceptions to the taken choice got allocated
+        // and, once the history repeats, are predicted from
+        let last = log[29];
+        assert_eq!((last.provider, last.tag_hit), (Provider::Way(0), Some(0)));
+        assert_eq!((last.choice, last.state), (3, 0));
+
+        assert!(!GshareBp::new(4).log_arbitration(10));
     }
-}
 
-impl Predictor for Yags3Bp {
-    fn predict_and_update(&mut self, mut addr: usize, was_taken: bool) {
-        // First drop the constant zero LSB
-        addr >>= 1;
-
-        let addr_index = (addr >> 1) & self.addr_mask;
-        let hash_index = (addr ^ self.history) & self.dir_mask;
-        let hash_tag = addr & self.tag_mask;
-
-        // Access
-        let used;
-        let predicted = if self.direction_tag[0][hash_index] == hash_tag {
-            used = Some(0);
-            self.direction_pht[0][hash_index].value()
-        } else if self.direction_tag[1][hash_index] == hash_tag {
-            used = Some(1);
-            self.direction_pht[1][hash_index].value()
-        } else {
-            used = None;
-            self.choice_pht[addr_index].value()
+    #[test]
+    fn tag_match_policies() {
+        // Without valid bits both ways of an empty table hit 0x1000
+        let yags = |tag_match| {
+            let mut p = YagsBp::yags3(8, 7, 5)
+                .with_valid_bits(false)
+                .with_tag_match(tag_match);
+            p.direction_pht[0][0] = TwoBitCounter::new(false);
+            p.direction_pht[1][0] = TwoBitCounter::strong(true);
+            p
         };
+        assert_eq!(yags(TagMatch::First).query(0x1000), (false, false));
+        let confident = yags(TagMatch::MostConfident);
+        assert_eq!(confident.query(0x1000), (true, true));
+        assert_eq!(confident.report().0, "YAGS3[match=confident,valid=false]");
+        // The weakly taken choice sides with way 1, then against it
+        let mut vote = yags(TagMatch::Vote);
+        assert_eq!(vote.query(0x1000), (true, true));
+        vote.choice_pht.set(0x1000 >> 2, TwoBitCounter::new(false));
+        assert_eq!(vote.query(0x1000), (false, false));
+    }
 
-        // Update
-        match used {
-            Some(n) => {
-                self.direction_pht[n][hash_index].update(was_taken);
-                self.direction_u[n][hash_index] =
-                    self.direction_pht[n][hash_index].value() == was_taken;
-            }
-            None => {
-                // The choice is updated on misses
-                self.choice_pht[addr_index].update(was_taken);
-
-                // NB: this is key no not waste an entry needlessly
-                if self.choice_pht[addr_index].value() != was_taken {
-                    if !self.direction_u[0][hash_index] {
-                        self.direction_tag[0][hash_index] = hash_tag;
-                        self.direction_pht[0][hash_index] = TwoBitCounter::new(was_taken);
-                    } else if !self.direction_u[1][hash_index] {
-                        self.direction_tag[1][hash_index] = hash_tag;
-                        self.direction_pht[1][hash_index] = TwoBitCounter::new(was_taken);
-                    } else {
-                        self.direction_u[0][hash_index] = false;
-                        self.direction_u[1][hash_index] = false;
-                    }
+    #[test]
+    fn split_matches_combined() {
+        // Misses of the predict_and_update implementations before the
+        // split, paired fetches widening the gap between the policies
+        let golden = [
+            (
+                HistoryPolicy::Retire,
+                [15740, 18447, 17949, 16464, 16505, 16441, 16778],
+            ),
+            (
+                HistoryPolicy::Speculative,
+                [15740, 18304, 17814, 16370, 16536, 16462, 16514],
+            ),
+        ];
+        type Step = fn(&mut dyn Predictor, usize, bool) -> bool;
+        let stream = synthetic_stream(50_000, 3);
+        let predictors = || -> Vec<Box<dyn Predictor>> {
+            vec![
+                Box::new(LocalBp::new(10)),
+                Box::new(GshareBp::new(10)),
+                Box::new(BimodalBp::new(10)),
+                Box::new(YagsBp::yags1(10, 8, 6)),
+                Box::new(YagsBp::yags2(10, 8, 6)),
+                Box::new(YagsBp::yags3(10, 8, 6)),
+                Box::new(YagsBp::yags4(10, 8, 6)),
+            ]
+        };
+        for (policy, expected) in golden {
+            let ways: [Step; 3] = [
+                |p, addr, was_taken| p.predict_and_update(addr, was_taken),
+                |p, addr, was_taken| p.predict_with_confidence(addr, was_taken).0,
+                |p, addr, was_taken| {
+                    let predicted = p.predict(addr);
+                    p.update(addr, predicted, was_taken);
+                    predicted
+                },
+            ];
+            for way in ways {
+                for (mut p, &expected) in predictors().into_iter().zip(&expected) {
+                    p.set_history_policy(policy);
+                    p.set_fetch_width(2);
+                    let misses = stream
+                        .iter()
+                        .filter(|&&(addr, was_taken)| way(p.as_mut(), addr, was_taken) != was_taken)
+                        .count();
+                    assert_eq!(misses, expected, "{:?} {:?}", p.report(), policy);
                 }
             }
         }
+    }
 
-        if predicted != was_taken {
-            self.misses += 1;
+    #[test]
+    fn tournament_chooses_per_branch() {
+        // Gshare learns the alternating branch, where NoneTaken misses
+        // half; on the rarely taken one NoneTaken only misses the
+        // rare taken, where gshare's history is noise
+        let mut rng = StdRng::seed_from_u64(1);
+        let stream: Vec<(usize, bool)> = (0..40_000)
+            .map(|i| match i % 3 {
+                0 => (0x100, i % 2 == 0),
+                _ => (0x200 + 4 * (i % 3), rng.gen_bool(0.1)),
+            })
+            .collect();
+        let tournament =
+            || TournamentBp::new(Box::new(NoneTakenBp::new()), Box::new(GshareBp::new(10)), 8);
+        let combined = misses(tournament(), &stream);
+        assert!(combined < misses(NoneTakenBp::new(), &stream));
+        assert!(combined < misses(GshareBp::new(10), &stream));
+
+        // The default predict_and_update makes the same predictions
+        let mut stepped = tournament();
+        let mut queried = tournament();
+        for &(addr, was_taken) in &stream[..1000] {
+            let predicted = queried.predict(addr);
+            queried.update(addr, predicted, was_taken);
+            assert_eq!(stepped.predict_and_update(addr, was_taken), predicted);
         }
 
-        self.history = self.history << 1 | was_taken as usize;
+        let (name, config, size) = tournament().report();
+        assert_eq!(name, "Tournament(NoneTaken[] | Gshare[10])");
+        assert_eq!((config, size), (vec![8], (2 << 10) + (2 << 8)));
     }
 
-    fn report(&self) -> (String, Vec<usize>, usize, usize) {
-        (
-            "YAGS3".to_string(),
-            vec![self.addr_bits, self.dir_bits, self.tag_bits],
-            self.choice_pht.capacity() * 2
-                + self.direction_pht[0].capacity() * 2 * (3 + self.tag_bits),
-            self.misses,
-        )
+    #[test]
+    fn tage_sees_past_global_history() {
+        // The second branch repeats the first of 40 before, further
+        // back than the 32 outcomes of GlobalHistory
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut stream = vec![];
+        for _ in 0..20_000 {
+            let first = rng.gen_bool(0.5);
+            stream.push((0x100, first));
+            stream.extend((0..38).map(|i| (0x200 + 4 * (i % 4), true)));
+            stream.push((0x300, first));
+        }
+        let second_misses = |mut p: Box<dyn Predictor>| {
+            let late = stream.len() / 2;
+            stream
+                .iter()
+                .enumerate()
+                .filter(|&(i, &(addr, was_taken))| {
+                    p.predict_and_update(addr, was_taken) != was_taken && addr == 0x300 && i >= late
+                })
+                .count()
+        };
+        let tage = TageBp::new(13, &geometric_lengths(5, 4, 64), 8);
+        assert!(second_misses(Box::new(tage)) < 100);
+        assert!(second_misses(Box::new(GshareBp::new(16))) > 3_000);
+
+        let tage = TageBp::new(13, &[4, 8, 16, 32, 64], 8);
+        let (name, config, size) = tage.report();
+        assert_eq!((name.as_str(), config), ("TAGE", vec![13, 5, 8]));
+        assert_eq!(size, (2 << 13) + 5 * (1 + 3 + 8 + 2) * (1 << 10) + 4);
+
+        // Address 0 has tag 0 while the history is clear, which the
+        // cold entries hold, yet only the base knows it
+        let tage = TageBp::new(13, &[4, 8, 16, 32, 64], 8);
+        assert_eq!(tage.tag(4, 0), 0);
+        assert_eq!(tage.access(0).provider, None);
+        assert_eq!(geometric_lengths(4, 5, 40), [5, 10, 20, 40]);
+        assert_eq!(geometric_lengths(1, 5, 40), [5]);
     }
-}
 
-/* YAGS4 = YAGS2 + YAGS3 */
-pub struct Yags4Bp {
-    addr_bits: usize,
-    dir_bits: usize,
-    tag_bits: usize,
-    history: usize,
-    choice_pht: Vec<TwoBitCounter>,
-    direction_pht: [Vec<TwoBitCounter>; 2],
-    direction_tag: [Vec<usize>; 2],
-    direction_u: [Vec<bool>; 2],
-    addr_mask: usize,
-    dir_mask: usize,
-    tag_mask: usize,
-    misses: usize,
-}
+    #[test]
+    fn trust_threshold() {
+        let stream = synthetic_stream(50_000, 1);
+        let trusting = misses(YagsBp::yags3(10, 8, 6).with_trust_threshold(1), &stream);
+        assert_eq!(trusting, misses(YagsBp::yags3(10, 8, 6), &stream));
+        let wary = YagsBp::yags3(10, 8, 6).with_trust_threshold(2);
+        assert_eq!(wary.report().0, "YAGS3[trust=2]");
+        assert_ne!(misses(wary, &stream), trusting);
+        // Nothing is strong enough, so it's down to the choice PHT
+        let never = YagsBp::yags3(10, 8, 6).with_trust_threshold(3);
+        assert_ne!(misses(never, &stream), trusting);
+    }
 
-impl Yags4Bp {
-    pub fn new(addr_bits: usize, dir_bits: usize, tag_bits: usize) -> Yags4Bp {
-        let dir_entries = 1 << dir_bits;
-        let choice_pht = vec![TwoBitCounter::new(true); 1 << addr_bits];
-        let direction_pht = [
-            vec![TwoBitCounter::new(true); dir_entries],
-            vec![TwoBitCounter::new(true); dir_entries],
+    #[test]
+    fn oversized_tables_fail() {
+        assert!(GshareBp::try_new(10).is_ok());
+        let e = GshareBp::try_new(50).err().unwrap();
+        assert_eq!(e.to_string(), "needs a 1048576.0 GiB table");
+        assert!(GshareBp::try_new(64).is_err());
+        assert!(YagsBp::try_variant(1, 22, 40, 6).is_err());
+    }
+
+    #[test]
+    fn queries_match_predictions() {
+        let stream = synthetic_stream(20_000, 2);
+        let predictors: Vec<Box<dyn Predictor>> = vec![
+            Box::new(NoneTakenBp::new()),
+            Box::new(LocalBp::new(8)),
+            Box::new(ProbabilisticLocalBp::try_new(8, 1).unwrap()),
+            Box::new(GshareBp::new(8)),
+            Box::new(GAgBp::try_new(8).unwrap()),
+            Box::new(PrevPcGshareBp::try_new(8, 2).unwrap()),
+            Box::new(BimodalBp::new(8)),
+            Box::new(YagsBp::yags1(8, 7, 5)),
+            Box::new(YagsBp::yags4(8, 7, 5).with_trust_threshold(2)),
+            Box::new(ChainBp::new(vec![
+                (Box::new(LocalBp::new(8)), CHAIN_CONFIDENCE),
+                (Box::new(GshareBp::new(8)), 0.5),
+            ])),
         ];
-        let direction_tag = [vec![0; dir_entries], vec![0; dir_entries]];
-        let direction_u = [vec![false; dir_entries], vec![false; dir_entries]];
-        let tag_mask = (1 << tag_bits) - 1;
-        Yags4Bp {
-            addr_bits,
-            dir_bits,
-            tag_bits,
-            history: 0,
-            choice_pht,
-            direction_pht,
-            direction_tag,
-            direction_u,
-            addr_mask: (1 << addr_bits) - 1,
-            dir_mask: (dir_entries) - 1,
-            tag_mask,
-            misses: 0,
+        for mut p in predictors {
+            for &(addr, was_taken) in &stream {
+                let queried = p.query(addr);
+                assert_eq!(p.predict(addr), queried.0);
+                assert_eq!(p.predict_with_confidence(addr, was_taken), queried);
+            }
         }
     }
-}
 
-impl Predictor for Yags4Bp {
-    fn predict_and_update(&mut self, mut addr: usize, was_taken: bool) {
-        // First drop the constant zero LSB
-        addr >>= 1;
-
-        let addr_index = (addr >> 1) & self.addr_mask;
-        let hash_index = ((addr >> 1) ^ self.history) & self.dir_mask;
-        let hash_tag = ((addr & 30) << 4 | (addr >> 5 ^ self.history) & 15) & self.tag_mask;
-
-        // Access
-        let used;
-        let predicted = if self.direction_tag[0][hash_index] == hash_tag {
-            used = Some(0);
-            self.direction_pht[0][hash_index].value()
-        } else if self.direction_tag[1][hash_index] == hash_tag {
-            used = Some(1);
-            self.direction_pht[1][hash_index].value()
-        } else {
-            used = None;
-            self.choice_pht[addr_index].value()
+    #[test]
+    fn chain_overrides() {
+        let stream = synthetic_stream(20_000, 1);
+        let gshare = misses(GshareBp::new(10), &stream);
+        let alone = ChainBp::new(vec![(Box::new(GshareBp::new(10)), CHAIN_CONFIDENCE)]);
+        assert_eq!(misses(alone, &stream), gshare);
+
+        // NoneTaken is always confident so nothing gets past it
+        let chain = |training| {
+            ChainBp::new(vec![
+                (Box::new(NoneTakenBp::new()), CHAIN_CONFIDENCE),
+                (Box::new(GshareBp::new(10)), 0.5),
+            ])
+            .with_training(training)
         };
+        let none_taken = stream.iter().filter(|&&(_, t)| t).count();
+        assert_eq!(misses(chain(ChainTraining::All), &stream), none_taken);
+        let consulted = chain(ChainTraining::Consulted);
+        assert_eq!(
+            consulted.report().0,
+            "Chain[train=consulted](NoneTaken[] > Gshare[10])"
+        );
+        assert_eq!(consulted.report().2, 2048);
+
+        // Only the confident local predictions are taken, the rest left
+        // to gshare, and none at all when it asks for more than a
+        // counter ever gives
+        let local_first = |min_odds| {
+            ChainBp::new(vec![
+                (Box::new(LocalBp::new(10)), min_odds),
+                (Box::new(GshareBp::new(10)), 0.5),
+            ])
+        };
+        assert_ne!(misses(local_first(CHAIN_CONFIDENCE), &stream), gshare);
+        assert_eq!(misses(local_first(0.95), &stream), gshare);
+        assert_eq!(
+            local_first(0.95).report().0,
+            "Chain(Two-level[10]@0.95 > Gshare[10])"
+        );
+    }
 
-        // Update
-        match used {
-            Some(n) => {
-                self.direction_pht[n][hash_index].update(was_taken);
-                self.direction_u[n][hash_index] =
-                    self.direction_pht[n][hash_index].value() == was_taken;
-            }
-            None => {
-                // The choice is updated on misses
-                self.choice_pht[addr_index].update(was_taken);
-
-                // NB: this is key no not waste an entry needlessly
-                if self.choice_pht[addr_index].value() != was_taken {
-                    if !self.direction_u[0][hash_index] {
-                        self.direction_tag[0][hash_index] = hash_tag;
-                        self.direction_pht[0][hash_index] = TwoBitCounter::new(was_taken);
-                    } else if !self.direction_u[1][hash_index] {
-                        self.direction_tag[1][hash_index] = hash_tag;
-                        self.direction_pht[1][hash_index] = TwoBitCounter::new(was_taken);
-                    } else {
-                        self.direction_u[0][hash_index] = false;
-                        self.direction_u[1][hash_index] = false;
-                    }
-                }
-            }
-        }
-
-        if predicted != was_taken {
-            self.misses += 1;
+    #[test]
+    fn history_width_is_fixed() {
+        // On a 64-bit host the history would otherwise keep 64 outcomes
+        // where a 32-bit one keeps 32, so the widest indices differ
+        let stream = synthetic_stream(10_000, 1);
+        let mut gshare = GshareBp::new(12);
+        let mut yags = YagsBp::yags2(12, 10, 8);
+        for &(addr, was_taken) in &stream {
+            gshare.predict_and_update(addr, was_taken);
+            yags.predict_and_update(addr, was_taken);
+            assert!(gshare.history.value() >> HISTORY_BITS == 0);
+            assert!(yags.history.value() >> HISTORY_BITS == 0);
         }
 
-        self.history = self.history << 1 | was_taken as usize;
-    }
-
-    fn report(&self) -> (String, Vec<usize>, usize, usize) {
-        (
-            "YAGS4".to_string(),
-            vec![self.addr_bits, self.dir_bits, self.tag_bits],
-            self.choice_pht.capacity() * 2
-                + self.direction_pht[0].capacity() * 2 * (3 + self.tag_bits),
-            self.misses,
-        )
+        // The hash agrees in the low 32 bits, all a 32-bit host keeps
+        assert_eq!(seeded_hash(0x1234, 0), 0x1234);
+        assert_eq!(seeded_hash(0x1234, 7) as u32, 0xf30c_78c8);
     }
 }
@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Write;
+
+/*
+ * Profile tracks, for a single predictor, the events and misses of
+ * every branch so the worst offenders can be reported.  For up to
+ * TRACKED of the worst branches it also keeps the recent miss pattern
+ * (bounded to HISTORY_LEN occurrences) so we can look for periodic
+ * mispredictions that a loop predictor could capture.
+ *
+ * Optionally it also aggregates by PC region, `addr >> bucket_bits`,
+ * to show whether the misses cluster in parts of the code.
+ */
+
+const TRACKED: usize = 64;
+const HISTORY_LEN: usize = 1024;
+const MIN_CORRELATION: f64 = 0.5;
+
+#[derive(Default)]
+struct BranchStats {
+    events: usize,
+    misses: usize,
+}
+
+pub struct Profile {
+    branches: HashMap<usize, BranchStats>,
+    histories: HashMap<usize, VecDeque<bool>>,
+    bucket_bits: Option<usize>,
+    buckets: BTreeMap<usize, BranchStats>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            branches: HashMap::new(),
+            histories: HashMap::new(),
+            bucket_bits: None,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_buckets(mut self, bucket_bits: usize) -> Profile {
+        self.bucket_bits = Some(bucket_bits);
+        self
+    }
+
+    pub fn record(&mut self, addr: usize, missed: bool, weight: usize) {
+        let stats = self.branches.entry(addr).or_default();
+        stats.events += weight;
+        stats.misses += missed as usize * weight;
+        let misses = stats.misses;
+
+        if let Some(bits) = self.bucket_bits {
+            let bucket = self.buckets.entry(addr >> bits).or_default();
+            bucket.events += weight;
+            bucket.misses += missed as usize * weight;
+        }
+
+        if !self.histories.contains_key(&addr) {
+            if !missed {
+                return;
+            }
+
+            // Space-saving style: replace the tracked branch with the fewest misses
+            if self.histories.len() == TRACKED {
+                let (&victim, _) = self
+                    .histories
+                    .iter()
+                    .min_by_key(|(&a, _)| (self.branches[&a].misses, a))
+                    .unwrap();
+                if self.branches[&victim].misses >= misses {
+                    return;
+                }
+                self.histories.remove(&victim);
+            }
+            self.histories
+                .insert(addr, VecDeque::with_capacity(HISTORY_LEN));
+        }
+
+        let history = self.histories.get_mut(&addr).unwrap();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(missed);
+    }
+
+    pub fn print(&self, top: usize) {
+        let mut worst: Vec<(&usize, &BranchStats)> = self.branches.iter().collect();
+        worst.sort_by(|a, b| b.1.misses.cmp(&a.1.misses).then(a.0.cmp(b.0)));
+
+        println!(
+            "{:>16} {:>12} {:>10} {:>6}  miss pattern",
+            "pc", "events", "misses", "rate"
+        );
+        for (addr, stats) in worst.into_iter().take(top) {
+            let pattern = match self.histories.get(addr).and_then(miss_period) {
+                Some((period, r)) => format!("periodic, every {} (r={:.2})", period, r),
+                None => "-".to_string(),
+            };
+            println!(
+                "{:>16x} {:>12} {:>10} {:5.1}%  {}",
+                addr,
+                stats.events,
+                stats.misses,
+                100.0 * stats.misses as f64 / stats.events as f64,
+                pattern
+            );
+        }
+    }
+}
+
+impl Profile {
+    /// Writes the per region miss histogram as `base\tevents\tmisses\trate`
+    pub fn write_buckets(&self, file_name: &str, label: Option<&str>) -> std::io::Result<()> {
+        let bits = match self.bucket_bits {
+            Some(bits) => bits,
+            None => return Ok(()),
+        };
+
+        let mut data = std::fs::File::create(file_name)?;
+        if let Some(label) = label {
+            writeln!(&mut data, "# label: {}", label)?;
+        }
+        writeln!(&mut data, "# {} byte regions", 1usize << bits)?;
+        for (bucket, stats) in &self.buckets {
+            writeln!(
+                &mut data,
+                "{:#x}\t{}\t{}\t{}",
+                bucket << bits,
+                stats.events,
+                stats.misses,
+                stats.misses as f64 / stats.events as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the lag with the strongest autocorrelation in a miss sequence,
+/// returning it when it's at least MIN_CORRELATION.
+fn miss_period(history: &VecDeque<bool>) -> Option<(usize, f64)> {
+    let n = history.len();
+    let mean = history.iter().filter(|&&m| m).count() as f64 / n as f64;
+    let x: Vec<f64> = history.iter().map(|&m| m as usize as f64 - mean).collect();
+    let variance: f64 = x.iter().map(|v| v * v).sum();
+    if variance == 0.0 {
+        return None;
+    }
+
+    (2..=n / 2)
+        .map(|lag| {
+            let r: f64 = (0..n - lag).map(|i| x[i] * x[i + lag]).sum();
+            (lag, r / variance)
+        })
+        .filter(|&(_, r)| r >= MIN_CORRELATION)
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periodic_misses() {
+        let history: VecDeque<bool> = (0..500).map(|i| i % 7 == 6).collect();
+        assert_eq!(miss_period(&history).map(|(p, _)| p), Some(7));
+    }
+
+    #[test]
+    fn aperiodic_misses() {
+        let always: VecDeque<bool> = (0..500).map(|_| true).collect();
+        assert_eq!(miss_period(&always), None);
+
+        // A fixed pseudo random sequence has no dominant period
+        let mut x: u32 = 1;
+        let noise: VecDeque<bool> = (0..500)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x & 1 == 1
+            })
+            .collect();
+        assert_eq!(miss_period(&noise), None);
+    }
+
+    #[test]
+    fn tracking_is_bounded() {
+        let mut profile = Profile::new();
+        for addr in 0..10 * TRACKED {
+            for _ in 0..addr % 5 + 1 {
+                profile.record(addr, true, 1);
+            }
+        }
+        assert_eq!(profile.histories.len(), TRACKED);
+        assert!(profile.histories.keys().all(|a| a % 5 == 4));
+    }
+
+    #[test]
+    fn buckets_by_region() {
+        let mut profile = Profile::new().with_buckets(12);
+        profile.record(0x1000, true, 1);
+        profile.record(0x1ffe, false, 2);
+        profile.record(0x3000, true, 3);
+        let buckets: Vec<(usize, usize, usize)> = profile
+            .buckets
+            .iter()
+            .map(|(&b, s)| (b, s.events, s.misses))
+            .collect();
+        assert_eq!(buckets, [(1, 3, 1), (3, 3, 3)]);
+    }
+}
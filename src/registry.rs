@@ -0,0 +1,452 @@
+use crate::predictor::*;
+use std::sync::Mutex;
+
+/*
+ * The registry maps the predictor names accepted by `-p` to their
+ * constructors.  A spec is `name` or `name:param,param,..` with the
+ * parameters in the order of the constructor, eg. `yags3:13,11,6`,
+ * optionally followed by named options, eg. `yags3:13,11,6,alloc=50%`.
+ *
+ * Predictors from other crates join the built-in ones with register,
+ * called before any spec is parsed, see lib.rs.
+ */
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub name: &'static str,
+    pub params: &'static [&'static str],
+    pub options: &'static [&'static str],
+    /// Gets as many params as are named, in that order
+    pub build: fn(&Args) -> Result<Box<dyn Predictor>, SpecError>,
+}
+
+#[derive(Debug)]
+pub enum SpecError {
+    /// The spec doesn't name a predictor and parameters it takes
+    Invalid(String),
+    /// It does, but the tables can't be allocated
+    Alloc(AllocError),
+}
+
+impl std::fmt::Display for SpecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SpecError::Invalid(e) => write!(f, "{}", e),
+            SpecError::Alloc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<String> for SpecError {
+    fn from(e: String) -> SpecError {
+        SpecError::Invalid(e)
+    }
+}
+
+impl From<AllocError> for SpecError {
+    fn from(e: AllocError) -> SpecError {
+        SpecError::Alloc(e)
+    }
+}
+
+pub struct Args {
+    params: Vec<usize>,
+    options: Vec<(String, String)>,
+}
+
+impl std::ops::Index<usize> for Args {
+    type Output = usize;
+
+    fn index(&self, i: usize) -> &usize {
+        &self.params[i]
+    }
+}
+
+impl Args {
+    /// The named option `key`, if given
+    pub fn option<T: std::str::FromStr>(&self, key: &str) -> Result<Option<T>, String> {
+        match self.options.iter().find(|(k, _)| k == key) {
+            Some((_, v)) => v
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("bad value {} for {}", v, key)),
+            None => Ok(None),
+        }
+    }
+}
+
+const YAGS_OPTIONS: &[&str] = &[
+    "alloc",
+    "trust",
+    "match",
+    "index_seed",
+    "tag_seed",
+    "write_ports",
+    "valid",
+];
+
+fn yags(variant: usize, a: &Args) -> Result<Box<dyn Predictor>, SpecError> {
+    let mut p = YagsBp::try_variant(variant, a[0], a[1], a[2])?;
+    if let Some(alloc) = a.option::<AllocPolicy>("alloc")? {
+        p = p.with_alloc_policy(alloc);
+    }
+    match a.option("trust")? {
+        Some(trust @ 1..=2) => p = p.with_trust_threshold(trust),
+        Some(trust) => return Err(format!("trust {} isn't a strength 1 or 2", trust).into()),
+        None => {}
+    }
+    if let Some(tag_match) = a.option::<TagMatch>("match")? {
+        p = p.with_tag_match(tag_match);
+    }
+    let index_seed = a.option("index_seed")?.unwrap_or(0);
+    let tag_seed = a.option("tag_seed")?.unwrap_or(0);
+    p = p.with_hash_seeds(index_seed, tag_seed);
+    if let Some(ports) = a.option("write_ports")? {
+        p = p.with_write_ports(ports);
+    }
+    if let Some(valid) = a.option("valid")? {
+        p = p.with_valid_bits(valid);
+    }
+    Ok(Box::new(p))
+}
+
+fn tage(a: &Args) -> Result<Box<dyn Predictor>, SpecError> {
+    let (base_bits, tables, tag_bits) = (a[0], a[1], a[2]);
+    let min = a.option("min_history")?.unwrap_or(TAGE_MIN_HISTORY);
+    let max = a.option("max_history")?.unwrap_or(TAGE_MAX_HISTORY);
+    if base_bits < 4 || tables == 0 || tag_bits < 2 {
+        return Err(
+            "TAGE needs base_bits of 4 or more, a table and tag_bits of 2 or more"
+                .to_string()
+                .into(),
+        );
+    }
+    if min == 0 {
+        return Err("TAGE needs a min_history of 1 or more".to_string().into());
+    }
+    if min > max {
+        return Err(format!("min_history {} is longer than max_history {}", min, max).into());
+    }
+    let lengths = geometric_lengths(tables, min, max);
+    Ok(Box::new(TageBp::try_new(base_bits, &lengths, tag_bits)?))
+}
+
+pub const REGISTRY: &[Entry] = &[
+    Entry {
+        name: "nonetaken",
+        params: &[],
+        options: &[],
+        build: |_| Ok(Box::new(NoneTakenBp::new())),
+    },
+    Entry {
+        name: "local",
+        params: &["addr_bits"],
+        options: &[],
+        build: |a| Ok(Box::new(LocalBp::try_new(a[0])?)),
+    },
+    Entry {
+        name: "probabilistic",
+        params: &["addr_bits"],
+        options: &["seed"],
+        build: |a| {
+            let seed = a.option("seed")?.unwrap_or(0);
+            Ok(Box::new(ProbabilisticLocalBp::try_new(a[0], seed)?))
+        },
+    },
+    Entry {
+        name: "gshare",
+        params: &["addr_bits"],
+        options: &[],
+        build: |a| Ok(Box::new(GshareBp::try_new(a[0])?)),
+    },
+    Entry {
+        name: "taggedgshare",
+        params: &["index_bits", "tag_bits"],
+        options: &["fallback"],
+        build: |a| {
+            let fallback = a.option("fallback")?.unwrap_or_default();
+            Ok(Box::new(
+                TaggedGshareBp::try_new(a[0], a[1])?.try_with_fallback(fallback)?,
+            ))
+        },
+    },
+    Entry {
+        name: "prevpcgshare",
+        params: &["addr_bits", "prev_pcs"],
+        options: &[],
+        build: |a| Ok(Box::new(PrevPcGshareBp::try_new(a[0], a[1])?)),
+    },
+    Entry {
+        name: "gag",
+        params: &["history_bits"],
+        options: &[],
+        build: |a| Ok(Box::new(GAgBp::try_new(a[0])?)),
+    },
+    Entry {
+        name: "bimodal",
+        params: &["addr_bits"],
+        options: &["choice", "init"],
+        build: |a| {
+            let choice = a.option("choice")?.unwrap_or_default();
+            let init = a.option("init")?.unwrap_or_default();
+            Ok(Box::new(
+                BimodalBp::try_new(a[0])?
+                    .with_choice_update(choice)
+                    .with_init(init),
+            ))
+        },
+    },
+    Entry {
+        name: "yags1",
+        params: &["addr_bits", "dir_bits", "tag_bits"],
+        options: YAGS_OPTIONS,
+        build: |a| yags(1, a),
+    },
+    Entry {
+        name: "yags2",
+        params: &["addr_bits", "dir_bits", "tag_bits"],
+        options: YAGS_OPTIONS,
+        build: |a| yags(2, a),
+    },
+    Entry {
+        name: "yags3",
+        params: &["addr_bits", "dir_bits", "tag_bits"],
+        options: YAGS_OPTIONS,
+        build: |a| yags(3, a),
+    },
+    Entry {
+        name: "yags4",
+        params: &["addr_bits", "dir_bits", "tag_bits"],
+        options: YAGS_OPTIONS,
+        build: |a| yags(4, a),
+    },
+    Entry {
+        name: "tage",
+        params: &["base_bits", "tables", "tag_bits"],
+        options: &["min_history", "max_history"],
+        build: tage,
+    },
+];
+
+static REGISTERED: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// Adds an external predictor, failing if the name is taken
+pub fn register(entry: Entry) -> Result<(), String> {
+    let mut registered = REGISTERED.lock().unwrap();
+    if entries_of(&registered).any(|e| e.name.eq_ignore_ascii_case(entry.name)) {
+        return Err(format!("predictor {} is already registered", entry.name));
+    }
+    registered.push(entry);
+    Ok(())
+}
+
+fn entries_of(registered: &[Entry]) -> impl Iterator<Item = &Entry> {
+    REGISTRY.iter().chain(registered)
+}
+
+/// The built-in predictors followed by the registered ones
+pub fn entries() -> Vec<Entry> {
+    entries_of(&REGISTERED.lock().unwrap()).copied().collect()
+}
+
+impl Entry {
+    pub fn usage(&self) -> String {
+        let options = self.options.iter().map(|o| format!("[{}=..]", o));
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|p| p.to_string())
+            .chain(options)
+            .collect();
+        if params.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{}:{}", self.name, params.join(","))
+        }
+    }
+}
+
+pub fn parse_spec(spec: &str) -> Result<Box<dyn Predictor>, SpecError> {
+    let (name, params) = match spec.split_once(':') {
+        Some((name, params)) => (name, params),
+        None => (spec, ""),
+    };
+
+    let entry = entries()
+        .into_iter()
+        .find(|e| e.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("unknown predictor {} in {}", name, spec))?;
+
+    let mut args = Args {
+        params: vec![],
+        options: vec![],
+    };
+    for p in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match p.split_once('=') {
+            Some((k, v)) if entry.options.contains(&k) => {
+                args.options.push((k.to_string(), v.to_string()))
+            }
+            Some(_) => return Err(format!("{} expects {}", spec, entry.usage()).into()),
+            None => args.params.push(
+                p.parse()
+                    .map_err(|_| format!("bad parameter {} in {}", p, spec))?,
+            ),
+        }
+    }
+
+    if args.params.len() != entry.params.len() {
+        return Err(format!("{} expects {}", spec, entry.usage()).into());
+    }
+
+    (entry.build)(&args)
+}
+
+/// A spec, or several joined by `>` into an override chain whose
+/// components, all but the last, are chosen only when they give their
+/// prediction odds of CHAIN_CONFIDENCE or those after an `@`, eg.
+/// `local:12@0.9 > gshare:14`
+pub fn parse_chain(spec: &str, training: ChainTraining) -> Result<Box<dyn Predictor>, SpecError> {
+    let specs: Vec<&str> = spec.split('>').map(str::trim).collect();
+    if specs.len() == 1 {
+        return parse_spec(spec);
+    }
+    let mut components = vec![];
+    for (i, spec) in specs.iter().enumerate() {
+        let (spec, min_odds) = match spec.split_once('@') {
+            None if i + 1 < specs.len() => (*spec, CHAIN_CONFIDENCE),
+            None => (*spec, 0.5),
+            Some(_) if i + 1 == specs.len() => {
+                return Err(format!("{}: the last of a chain is chosen however sure", spec).into())
+            }
+            Some((spec, odds)) => match odds.trim().parse::<f64>() {
+                Ok(odds) if (0.5..=1.0).contains(&odds) => (spec.trim(), odds),
+                _ => return Err(format!("{}: expects odds from 0.5 to 1 after @", spec).into()),
+            },
+        };
+        components.push((parse_spec(spec)?, min_odds));
+    }
+    Ok(Box::new(ChainBp::new(components).with_training(training)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_specs() {
+        let p = parse_spec("yags3:13,11,6").unwrap();
+        assert_eq!(p.report().0, "YAGS3");
+        assert_eq!(p.report().1, vec![13, 11, 6]);
+        assert_eq!(parse_spec("NoneTaken").unwrap().report().0, "NoneTaken");
+        let p = parse_spec("yags1:13,11,6,alloc=50%").unwrap();
+        assert_eq!(p.report().0, "YAGS1[alloc=50%]");
+        let p = parse_spec("yags2:13,11,6,tag_seed=7").unwrap();
+        assert_eq!(p.report().0, "YAGS2[index_seed=0,tag_seed=7]");
+        let p = parse_spec("yags3:13,11,6,valid=false").unwrap();
+        assert_eq!(p.report().0, "YAGS3[valid=false]");
+        let p = parse_spec("yags4:13,11,6,match=vote").unwrap();
+        assert_eq!(p.report().0, "YAGS4[match=vote]");
+        let p = parse_spec("tage:13,5,8").unwrap();
+        assert_eq!(p.report().0, "TAGE");
+        assert_eq!(p.report().1, vec![13, 5, 8]);
+        let p = parse_spec("tage:13,3,8,max_history=100").unwrap();
+        assert_eq!(p.report().0, "TAGE[history=4/20/100]");
+        let p = parse_spec("bimodal:10,init=strong-not-taken").unwrap();
+        assert_eq!(p.report().0, "Bimodal[init=strong-not-taken]");
+    }
+
+    #[test]
+    fn parses_chains() {
+        let p = parse_chain("local:12 > gshare:14", ChainTraining::All).unwrap();
+        assert_eq!(p.report().0, "Chain(Two-level[12] > Gshare[14])");
+        let p = parse_chain("gshare:14", ChainTraining::Consulted).unwrap();
+        assert_eq!(p.report().0, "Gshare");
+        assert!(parse_chain("local:12 > gshare", ChainTraining::All).is_err());
+        let p = parse_chain("local:12@0.9 > gshare:14", ChainTraining::All).unwrap();
+        assert_eq!(p.report().0, "Chain(Two-level[12]@0.9 > Gshare[14])");
+        assert!(parse_chain("local:12@2 > gshare:14", ChainTraining::All).is_err());
+        assert!(parse_chain("local:12 > gshare:14@0.9", ChainTraining::All).is_err());
+    }
+
+    #[test]
+    fn ids_tell_configs_apart() {
+        let specs = [
+            "nonetaken",
+            "gshare:14",
+            "gshare:4",
+            "local:14",
+            "prevpcgshare:14,1",
+            "prevpcgshare:1,41",
+            "yags1:13,11,6",
+            "yags1:13,11,6,alloc=50%",
+            "yags3:13,11,6",
+        ];
+        let ids: Vec<String> = specs.iter().map(|s| parse_spec(s).unwrap().id()).collect();
+        assert_eq!(ids[1], "gshare/14");
+        assert_eq!(ids[7], "yags1[alloc=50%]/13,11,6");
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(*id, parse_spec(specs[i]).unwrap().id());
+            assert!(!ids[..i].contains(id), "{} twice", id);
+        }
+        let chain = parse_chain("local:12 > gshare:14", ChainTraining::All).unwrap();
+        assert_eq!(chain.id(), "chain(two-level[12] > gshare[14])");
+    }
+
+    #[test]
+    fn params_name_the_config() {
+        let specs = [
+            "nonetaken",
+            "local:10",
+            "probabilistic:10",
+            "gshare:10",
+            "taggedgshare:10,4",
+            "prevpcgshare:10,2",
+            "gag:10",
+            "bimodal:10",
+            "yags1:10,8,6",
+            "yags4:10,8,6",
+            "tage:10,4,8",
+        ];
+        // All but yags2 and yags3, which are built like yags1 and yags4
+        assert_eq!(specs.len(), REGISTRY.len() - 2);
+        for spec in specs {
+            let p = parse_spec(spec).unwrap();
+            let name = spec.split(':').next().unwrap();
+            let entry = REGISTRY.iter().find(|e| e.name == name).unwrap();
+            let (names, values): (Vec<&str>, Vec<usize>) = p.params().into_iter().unzip();
+            assert_eq!(names, entry.params, "{}", spec);
+            assert_eq!(values, p.report().1, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn registers_external_predictors() {
+        register(Entry {
+            name: "external",
+            params: &["addr_bits"],
+            options: &[],
+            build: |a| Ok(Box::new(GshareBp::try_new(a[0])?)),
+        })
+        .unwrap();
+        assert_eq!(parse_spec("external:10").unwrap().report().1, vec![10]);
+        assert!(parse_spec("external").is_err());
+        assert!(register(entries()[0]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        assert!(parse_spec("gshare").is_err());
+        assert!(parse_spec("gshare:14,2").is_err());
+        assert!(parse_spec("gshare:x").is_err());
+        assert!(parse_spec("gshars:14").is_err());
+        assert!(parse_spec("gshare:14,alloc=50%").is_err());
+        assert!(parse_spec("yags1:13,11,6,alloc=sometimes").is_err());
+        assert!(parse_spec("yags1:13,11,6,alloc=strong").is_err());
+        assert!(parse_spec("yags3:13,11,6,trust=3").is_err());
+        assert!(parse_spec("tage:13,5,8,min_history=0").is_err());
+        assert!(matches!(
+            parse_spec("yags1:22,40,6"),
+            Err(SpecError::Alloc(_))
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::Write;
+
+/*
+ * SRAM images of predictor tables for loading into an RTL testbench.
+ * Each table is written as its entries in index order, packed back to
+ * back with no padding, LSB first within each byte.  A table ends on a
+ * byte boundary (zero padded) so the next one starts on a fresh byte.
+ *
+ * The fields of an entry are packed low to high in the order given by
+ * each predictor's dump_sram.  Counters are stored as their 2-bit
+ * state, 0 = strongly not taken .. 3 = strongly taken.
+ */
+
+pub struct BitWriter<'a, W: Write + ?Sized> {
+    out: &'a mut W,
+    byte: u8,
+    bits: usize,
+}
+
+impl<'a, W: Write + ?Sized> BitWriter<'a, W> {
+    pub fn new(out: &'a mut W) -> BitWriter<'a, W> {
+        BitWriter {
+            out,
+            byte: 0,
+            bits: 0,
+        }
+    }
+
+    /// Appends the low `width` bits of `value`
+    pub fn write(&mut self, value: usize, width: usize) -> std::io::Result<()> {
+        for i in 0..width {
+            self.byte |= ((value >> i & 1) as u8) << self.bits;
+            self.bits += 1;
+            if self.bits == 8 {
+                self.out.write_all(&[self.byte])?;
+                self.byte = 0;
+                self.bits = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pads to the next byte boundary, ending the table
+    pub fn align(&mut self) -> std::io::Result<()> {
+        if self.bits != 0 {
+            self.out.write_all(&[self.byte])?;
+            self.byte = 0;
+            self.bits = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Reads back what BitWriter wrote
+#[cfg(test)]
+pub struct BitReader<'a> {
+    image: &'a [u8],
+    pos: usize,
+}
+
+#[cfg(test)]
+impl<'a> BitReader<'a> {
+    pub fn new(image: &'a [u8]) -> BitReader<'a> {
+        BitReader { image, pos: 0 }
+    }
+
+    pub fn read(&mut self, width: usize) -> usize {
+        let mut value = 0;
+        for i in 0..width {
+            let bit = self.image[self.pos / 8] >> (self.pos % 8) & 1;
+            value |= (bit as usize) << i;
+            self.pos += 1;
+        }
+        value
+    }
+
+    pub fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.image.len() * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_lsb_first() {
+        let mut image = vec![];
+        {
+            let mut w = BitWriter::new(&mut image);
+            w.write(0b101, 3).unwrap();
+            w.write(0x3f, 6).unwrap();
+            w.align().unwrap();
+            w.write(2, 2).unwrap();
+            w.align().unwrap();
+        }
+        assert_eq!(image, [0b1111_1101, 0b1, 0b10]);
+
+        let mut r = BitReader::new(&image);
+        assert_eq!(r.read(3), 0b101);
+        assert_eq!(r.read(6), 0x3f);
+        r.align();
+        assert_eq!(r.read(2), 2);
+        r.align();
+        assert!(r.is_empty());
+    }
+}
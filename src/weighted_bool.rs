@@ -1,5 +1,8 @@
 use rand::Rng;
 
+// TODO:
+// - separate prediction and update, enabling modelling delayed updates
+
 /**
  * Boolish houses traits what can be interpreted as boolean, but
  * internally may take on more values.  The classic example is two-bit
@@ -7,20 +10,37 @@ use rand::Rng;
  * variations. Key is the convertion to and fro boolean as well as an
  * `update` that nudges the value in a particular direction.
  */
-
-// TODO:
-// - separate prediction and update, enabling modelling delayed updates
-
 pub trait Boolish {
+    /// The bits the hardware stores it in
+    const BITS: usize;
+
     fn update(&mut self, taken: bool) -> &mut Self;
     fn value(self) -> bool;
     fn new(b: bool) -> Self;
+
+    /// The discrete state in 0..=3, from strongly not taken to strongly taken
+    fn state(self) -> u8;
+
+    fn confident(self) -> bool
+    where
+        Self: Sized,
+    {
+        matches!(self.state(), 0 | 3)
+    }
+
+    /// How far from flipping: 1 for the weak states, 2 for the strong
+    fn strength(self) -> u8
+    where
+        Self: Sized,
+    {
+        1 + self.confident() as u8
+    }
 }
 
 const _STRONGLY_NOT_TAKEN: i8 = 0;
 const WEAKLY_NOT_TAKEN: i8 = 1;
 const WEAKLY_TAKEN: i8 = 2;
-const _STRONGLY_TAKEN: i8 = 3;
+const STRONGLY_TAKEN: i8 = 3;
 const SCALE: usize = 5;
 
 // NB. Not using enums in order to use a bit encoding trick
@@ -29,6 +49,8 @@ pub struct TwoBitCounter {
     counter: i8,
 }
 impl Boolish for TwoBitCounter {
+    const BITS: usize = 2;
+
     fn update(&mut self, taken: bool) -> &mut Self {
         /* Conceptually
 
@@ -52,7 +74,14 @@ impl Boolish for TwoBitCounter {
             .counter
             .wrapping_add((taken as i8) * (2 << SCALE) - (1 << SCALE));
         let overflow_mask = (new << (5 - SCALE)) >> 7;
+        debug_assert!(overflow_mask == 0 || overflow_mask == -1);
         self.counter = self.counter & overflow_mask | new & !overflow_mask;
+        debug_assert!(
+            (0..=STRONGLY_TAKEN << SCALE).contains(&self.counter)
+                && self.counter & ((1 << SCALE) - 1) == 0,
+            "counter {} out of range",
+            self.counter
+        );
 
         self
     }
@@ -61,6 +90,10 @@ impl Boolish for TwoBitCounter {
         WEAKLY_TAKEN << SCALE <= self.counter
     }
 
+    fn state(self) -> u8 {
+        (self.counter >> SCALE) as u8
+    }
+
     fn new(b: bool) -> Self {
         TwoBitCounter {
             counter: if b {
@@ -72,9 +105,191 @@ impl Boolish for TwoBitCounter {
     }
 }
 
+impl TwoBitCounter {
+    /// Strongly taken or strongly not taken, which `new` can't make
+    pub fn strong(b: bool) -> Self {
+        TwoBitCounter {
+            counter: if b {
+                STRONGLY_TAKEN << SCALE
+            } else {
+                _STRONGLY_NOT_TAKEN << SCALE
+            },
+        }
+    }
+
+    /// The counter in `state`, as returned by Boolish::state, eg. when
+    /// loading saved tables
+    pub fn with_state(state: u8) -> Self {
+        assert!(state as i8 <= STRONGLY_TAKEN, "no two bit state {}", state);
+        TwoBitCounter {
+            counter: (state as i8) << SCALE,
+        }
+    }
+}
+
+/*
+ * LANES independent counters predicted and updated at once, for the
+ * batched predictors.  With the `simd` feature on x86-64 the update is
+ * done by SSE2 on the prescaled counters as unsigned bytes: a
+ * saturating add capped at strongly taken, or a subtract saturating at
+ * strongly not taken, picked per lane by the outcome.  Otherwise it's
+ * the same lane by lane, which the compiler may vectorize itself.
+ */
+pub const LANES: usize = 16;
+
+/// Returns the predictions of `counters` and updates them with `taken`
+pub fn update_lanes(counters: &mut [TwoBitCounter; LANES], taken: &[bool; LANES]) -> [bool; LANES] {
+    let mut lanes = counters.map(|c| c.counter as u8);
+    let predictions = lanes.map(|c| WEAKLY_TAKEN << SCALE <= c as i8);
+    update_bytes(&mut lanes, taken);
+    for (c, lane) in counters.iter_mut().zip(lanes) {
+        c.counter = lane as i8;
+    }
+    predictions
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn update_bytes(lanes: &mut [u8; LANES], taken: &[bool; LANES]) {
+    use std::arch::x86_64::*;
+
+    // SSE2 is part of x86-64, so these are always available
+    unsafe {
+        let counters = _mm_loadu_si128(lanes.as_ptr() as *const __m128i);
+        let mask = _mm_sub_epi8(
+            _mm_setzero_si128(),
+            _mm_loadu_si128(taken.as_ptr() as *const __m128i),
+        );
+        let step = _mm_set1_epi8(1 << SCALE);
+        let up = _mm_min_epu8(
+            _mm_adds_epu8(counters, step),
+            _mm_set1_epi8(STRONGLY_TAKEN << SCALE),
+        );
+        let down = _mm_subs_epu8(counters, step);
+        let updated = _mm_or_si128(_mm_and_si128(mask, up), _mm_andnot_si128(mask, down));
+        _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, updated);
+    }
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+fn update_bytes(lanes: &mut [u8; LANES], taken: &[bool; LANES]) {
+    for (c, &taken) in lanes.iter_mut().zip(taken) {
+        *c = if taken {
+            (*c + (1 << SCALE)).min((STRONGLY_TAKEN << SCALE) as u8)
+        } else {
+            c.saturating_sub(1 << SCALE)
+        };
+    }
+}
+
+/*
+ * EnumTwoBitCounter is the plain, obviously correct version of
+ * TwoBitCounter.  It's not used by the predictors but serves as the
+ * reference the bit trick above is checked against.
+ */
+#[allow(dead_code, clippy::enum_variant_names)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EnumTwoBitCounter {
+    StronglyNotTaken,
+    WeaklyNotTaken,
+    WeaklyTaken,
+    StronglyTaken,
+}
+
+impl Boolish for EnumTwoBitCounter {
+    const BITS: usize = 2;
+
+    fn update(&mut self, taken: bool) -> &mut Self {
+        use EnumTwoBitCounter::*;
+        *self = match (*self, taken) {
+            (StronglyNotTaken, false) | (WeaklyNotTaken, false) => StronglyNotTaken,
+            (StronglyNotTaken, true) | (WeaklyTaken, false) => WeaklyNotTaken,
+            (WeaklyNotTaken, true) | (StronglyTaken, false) => WeaklyTaken,
+            (WeaklyTaken, true) | (StronglyTaken, true) => StronglyTaken,
+        };
+
+        self
+    }
+
+    fn value(self) -> bool {
+        matches!(
+            self,
+            EnumTwoBitCounter::WeaklyTaken | EnumTwoBitCounter::StronglyTaken
+        )
+    }
+
+    fn state(self) -> u8 {
+        self as u8
+    }
+
+    fn new(b: bool) -> Self {
+        if b {
+            EnumTwoBitCounter::WeaklyTaken
+        } else {
+            EnumTwoBitCounter::WeaklyNotTaken
+        }
+    }
+}
+
+/*
+ * ProbCounter is a two-bit counter that only takes each step with odds
+ * 1 in ODDS, so on average it takes ODDS times as many outcomes to
+ * move.  That gives roughly the hysteresis of a 2 + log2(ODDS) bit
+ * counter at the storage cost of two bits plus a random source.
+ * ODDS = 1 is TwoBitCounter.
+ */
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProbCounter<const ODDS: u32> {
+    state: u8,
+}
+
+impl<const ODDS: u32> Boolish for ProbCounter<ODDS> {
+    const BITS: usize = 2;
+
+    fn update(&mut self, taken: bool) -> &mut Self {
+        self.update_with(taken, &mut rand::thread_rng())
+    }
+
+    fn value(self) -> bool {
+        self.state >= WEAKLY_TAKEN as u8
+    }
+
+    fn new(b: bool) -> Self {
+        let state = if b { WEAKLY_TAKEN } else { WEAKLY_NOT_TAKEN };
+        ProbCounter { state: state as u8 }
+    }
+
+    fn state(self) -> u8 {
+        self.state
+    }
+}
+
+#[allow(dead_code)]
+impl<const ODDS: u32> ProbCounter<ODDS> {
+    /// update drawing the die rolls from `rng`, for reproducible runs
+    pub fn update_with(&mut self, taken: bool, rng: &mut impl Rng) -> &mut Self {
+        let saturated = if taken {
+            self.state == STRONGLY_TAKEN as u8
+        } else {
+            self.state == 0
+        };
+        if !saturated && (ODDS <= 1 || rng.gen_range(0..ODDS) == 0) {
+            if taken {
+                self.state += 1;
+            } else {
+                self.state -= 1;
+            }
+        }
+
+        self
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn idempodence() {
@@ -115,6 +330,150 @@ mod tests {
             .update(false)
             .value());
     }
+
+    #[test]
+    fn state() {
+        assert_eq!(TwoBitCounter::new(false).state(), 1);
+        assert_eq!(TwoBitCounter::new(true).state(), 2);
+        assert_eq!(TwoBitCounter::new(false).update(false).state(), 0);
+        assert_eq!(TwoBitCounter::new(true).update(true).state(), 3);
+        assert_eq!(
+            TwoBitCounter::new(true).update(true).update(true).state(),
+            3
+        );
+        assert_eq!(TwoBitCounter::new(false).strength(), 1);
+        assert_eq!(TwoBitCounter::new(false).update(false).strength(), 2);
+    }
+
+    #[test]
+    fn strong_states() {
+        for b in [false, true] {
+            let mut c = TwoBitCounter::strong(b);
+            assert_eq!((c.value(), c.confident()), (b, true));
+            assert_eq!(c.update(b).state(), TwoBitCounter::strong(b).state());
+            // One step toward the other way only weakens it
+            assert_eq!((c.update(!b).value(), c.confident()), (b, false));
+            assert_eq!(c.update(!b).value(), !b);
+        }
+        for state in 0..=3 {
+            assert_eq!(TwoBitCounter::with_state(state).state(), state);
+        }
+        assert_eq!(
+            TwoBitCounter::with_state(3).state(),
+            TwoBitCounter::strong(true).state()
+        );
+    }
+
+    #[test]
+    fn all_transitions() {
+        // Every state and input, relying on update's debug assertions
+        for state in 0..=3 {
+            for taken in [false, true] {
+                let mut c = TwoBitCounter {
+                    counter: state << SCALE,
+                };
+                c.update(taken);
+                let expected = if taken { state + 1 } else { state - 1 };
+                assert_eq!(c.state() as i8, expected.clamp(0, 3));
+            }
+        }
+    }
+
+    #[test]
+    fn lanes_match_scalar_updates() {
+        // Every state with both outcomes, four times over
+        let mut counters: [TwoBitCounter; LANES] = std::array::from_fn(|i| TwoBitCounter {
+            counter: (i as i8 / 2 % 4) << SCALE,
+        });
+        let taken = std::array::from_fn(|i| i % 2 == 1);
+        let mut expected = counters;
+        let expected_predictions = expected.map(|c| c.value());
+        for (c, &taken) in expected.iter_mut().zip(&taken) {
+            c.update(taken);
+        }
+        assert_eq!(update_lanes(&mut counters, &taken), expected_predictions);
+        assert_eq!(counters.map(|c| c.state()), expected.map(|c| c.state()));
+    }
+
+    #[test]
+    fn matches_enum_reference() {
+        // Reach all four states from both weak starting points
+        let paths: &[&[bool]] = &[&[false], &[], &[true], &[true, true]];
+        for start in [false, true] {
+            for path in paths {
+                for taken in [false, true] {
+                    let mut fast = TwoBitCounter::new(start);
+                    let mut reference = EnumTwoBitCounter::new(start);
+                    for &t in path.iter() {
+                        fast.update(t);
+                        reference.update(t);
+                    }
+                    assert_eq!(fast.state(), reference.state());
+                    assert_eq!(fast.value(), reference.value());
+
+                    fast.update(taken);
+                    reference.update(taken);
+                    assert_eq!(fast.state(), reference.state());
+                    assert_eq!(fast.value(), reference.value());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prob_counter_of_even_odds_is_two_bit() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for start in [false, true] {
+            let mut fast = TwoBitCounter::new(start);
+            let mut prob = ProbCounter::<1>::new(start);
+            for i in 0..64 {
+                let taken = i % 3 == 0 || i % 7 < 3;
+                fast.update(taken);
+                prob.update_with(taken, &mut rng);
+                assert_eq!(prob.state(), fast.state());
+                assert_eq!(prob.value(), fast.value());
+            }
+        }
+    }
+
+    #[test]
+    fn prob_counter_steps_at_its_odds() {
+        // Always push toward the other weak state, so every update is a
+        // chance to step and the fraction taken estimates the odds
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut c = ProbCounter::<4>::new(false);
+        let updates = 40_000;
+        let mut steps = 0;
+        for _ in 0..updates {
+            let before = c.state();
+            c.update_with(before == 1, &mut rng);
+            assert!(matches!(c.state(), 1 | 2));
+            steps += (c.state() != before) as usize;
+        }
+        let rate = steps as f64 / updates as f64;
+        assert!((rate - 0.25).abs() < 0.01, "stepped at {}", rate);
+    }
+
+    #[test]
+    fn prob_counter_saturates() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut c = ProbCounter::<8>::new(true);
+        assert!(c.value());
+        // Odds of not reaching strongly taken in 1000 rolls are ~1e-58
+        for _ in 0..1000 {
+            c.update_with(true, &mut rng);
+        }
+        assert_eq!(c.state(), 3);
+        assert!(c.confident());
+
+        // A single contrary outcome rarely moves it off the rail
+        let mut moved = 0;
+        for _ in 0..10_000 {
+            let mut d = c;
+            moved += (d.update_with(false, &mut rng).state() != 3) as usize;
+        }
+        assert!((1_000..1_500).contains(&moved), "moved {} times", moved);
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -125,20 +484,52 @@ pub enum Confidence {
     Conviction,
 }
 
+#[derive(Copy, Clone)]
 pub struct ProbablyBool {
     value: bool,
     confidence: Confidence,
 }
 
 impl Boolish for ProbablyBool {
+    // A value bit and two bits of confidence
+    const BITS: usize = 3;
+
     fn update(&mut self, new_value: bool) -> &mut Self {
+        self.update_with(new_value, &mut rand::thread_rng())
+    }
+
+    fn value(self) -> bool {
+        self.value
+    }
+
+    fn new(value: bool) -> Self {
+        Self {
+            value,
+            confidence: Confidence::Weak,
+        }
+    }
+
+    /* Anything but Weak counts as strong in the four state view */
+    fn state(self) -> u8 {
+        match (self.value, self.confidence) {
+            (false, Confidence::Weak) => 1,
+            (false, _) => 0,
+            (true, Confidence::Weak) => 2,
+            (true, _) => 3,
+        }
+    }
+}
+
+impl ProbablyBool {
+    /// update drawing the die rolls from `rng`, for reproducible runs
+    pub fn update_with(&mut self, new_value: bool, rng: &mut impl Rng) -> &mut Self {
         use Confidence::*;
         self.confidence = if self.value == new_value {
             /* Strengthen */
             match self.confidence {
                 Weak => Fair,
-                Fair if lucky_die_roll() => Strong,
-                Strong if lucky_die_roll() => Conviction,
+                Fair if lucky_die_roll(rng) => Strong,
+                Strong if lucky_die_roll(rng) => Conviction,
                 _ => self.confidence,
             }
         } else {
@@ -159,19 +550,21 @@ impl Boolish for ProbablyBool {
         self
     }
 
-    fn value(self) -> bool {
-        self.value
-    }
-
-    fn new(value: bool) -> Self {
-        Self {
-            value,
-            confidence: Confidence::Weak,
+    /// The odds it gives of taken, more lopsided the more confident
+    pub fn probability(self) -> f64 {
+        let p = match self.confidence {
+            Confidence::Weak => 0.6,
+            Confidence::Fair => 0.75,
+            Confidence::Strong => 0.9,
+            Confidence::Conviction => 0.99,
+        };
+        if self.value {
+            p
+        } else {
+            1.0 - p
         }
     }
-}
 
-impl ProbablyBool {
     #[allow(dead_code)]
     fn confident(self) -> bool {
         !matches!(self.confidence, Confidence::Weak)
@@ -183,6 +576,6 @@ impl ProbablyBool {
     }
 }
 
-fn lucky_die_roll() -> bool {
-    rand::thread_rng().gen_range(1..101) == 42
+fn lucky_die_roll(rng: &mut impl Rng) -> bool {
+    rng.gen_range(1..101) == 42
 }
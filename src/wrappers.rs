@@ -0,0 +1,618 @@
+use crate::bias::BiasProfile;
+use crate::history::{HistoryPolicy, HistorySnapshot};
+use crate::predictor::{graded_probability, Arbitration, PortStats, Predictor};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::rc::Rc;
+
+/*
+ * Wrappers are predictors that delegate to another predictor while
+ * observing or altering what goes in and out of it.
+ */
+
+/// Forwards to `self.inner` the Predictor methods a wrapper leaves as
+/// they are, naming those it writes itself, eg.
+/// `delegate_to_inner!(report, stability)`.  Only query, predict_prob,
+/// report and stability can be named; the rest are always forwarded.
+macro_rules! delegate_to_inner {
+    ($($own:ident),* $(,)?) => {
+        delegate_to_inner!(@unless query [$($own)*]);
+        delegate_to_inner!(@unless predict_prob [$($own)*]);
+        delegate_to_inner!(@unless report [$($own)*]);
+        delegate_to_inner!(@unless stability [$($own)*]);
+
+        fn params(&self) -> Vec<(&'static str, usize)> {
+            self.inner.params()
+        }
+
+        fn footprint_bytes(&self) -> usize {
+            std::mem::size_of_val(self) + self.inner.footprint_bytes()
+        }
+
+        fn table_states(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+            self.inner.table_states()
+        }
+
+        fn set_history_policy(&mut self, policy: HistoryPolicy) {
+            self.inner.set_history_policy(policy);
+        }
+
+        fn init_from_bias(&mut self, profile: &BiasProfile) {
+            self.inner.init_from_bias(profile);
+        }
+
+        fn set_fetch_width(&mut self, width: usize) {
+            self.inner.set_fetch_width(width);
+        }
+
+        fn set_thread(&mut self, thread: usize) {
+            self.inner.set_thread(thread);
+        }
+
+        fn set_history_reset_on_miss(&mut self, bits: usize) {
+            self.inner.set_history_reset_on_miss(bits);
+        }
+
+        fn history_snapshot(&self) -> HistorySnapshot {
+            self.inner.history_snapshot()
+        }
+
+        fn pin_history(&mut self, snapshot: Option<&HistorySnapshot>) {
+            self.inner.pin_history(snapshot);
+        }
+
+        fn port_stats(&self) -> Option<PortStats> {
+            self.inner.port_stats()
+        }
+
+        fn log_arbitration(&mut self, events: usize) -> bool {
+            self.inner.log_arbitration(events)
+        }
+
+        fn arbitration_log(&self) -> &[Arbitration] {
+            self.inner.arbitration_log()
+        }
+
+        fn resize(&mut self, new_bits: usize, preserve: bool) -> Result<(), String> {
+            self.inner.resize(new_bits, preserve)
+        }
+
+        fn dump_sram(&self, w: &mut dyn Write) -> std::io::Result<()> {
+            self.inner.dump_sram(w)
+        }
+    };
+    (@unless query [query $($rest:ident)*]) => {};
+    (@unless predict_prob [predict_prob $($rest:ident)*]) => {};
+    (@unless report [report $($rest:ident)*]) => {};
+    (@unless stability [stability $($rest:ident)*]) => {};
+    (@unless $method:ident [$other:ident $($rest:ident)*]) => {
+        delegate_to_inner!(@unless $method [$($rest)*]);
+    };
+    (@unless $method:ident []) => {
+        delegate_to_inner!(@forward $method);
+    };
+    (@forward query) => {
+        fn query(&self, addr: usize) -> (bool, bool) {
+            self.inner.query(addr)
+        }
+    };
+    (@forward predict_prob) => {
+        fn predict_prob(&self, addr: usize) -> f64 {
+            self.inner.predict_prob(addr)
+        }
+    };
+    (@forward report) => {
+        fn report(&self) -> (String, Vec<usize>, usize) {
+            self.inner.report()
+        }
+    };
+    (@forward stability) => {
+        fn stability(&self) -> Option<(usize, usize)> {
+            self.inner.stability()
+        }
+    };
+}
+
+/// Where a wrapper that writes as it goes keeps the first error it
+/// met, after which it writes no more, for the run to report once done
+pub type WriteError = Rc<RefCell<Option<std::io::Error>>>;
+
+/// Writes every prediction of the wrapped predictor as a packed bit
+/// array, one bit per event, LSB first within each byte.
+pub struct DumpPredictions<W: Write> {
+    inner: Box<dyn Predictor>,
+    out: W,
+    error: WriteError,
+    byte: u8,
+    bits: usize,
+}
+
+impl<W: Write> DumpPredictions<W> {
+    pub fn new(inner: Box<dyn Predictor>, out: W, error: WriteError) -> DumpPredictions<W> {
+        DumpPredictions {
+            inner,
+            out,
+            error,
+            byte: 0,
+            bits: 0,
+        }
+    }
+
+    fn record(&mut self, predicted: bool) {
+        self.byte |= (predicted as u8) << (self.bits % 8);
+        self.bits += 1;
+        if self.bits.is_multiple_of(8) && self.error.borrow().is_none() {
+            if let Err(e) = self.out.write_all(&[self.byte]) {
+                *self.error.borrow_mut() = Some(e);
+            }
+            self.byte = 0;
+        }
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        if self.error.borrow().is_some() {
+            return Ok(());
+        }
+        if !self.bits.is_multiple_of(8) {
+            self.out.write_all(&[self.byte])?;
+            self.bits += 8 - self.bits % 8;
+        }
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Drop for DumpPredictions<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("failed to write predictions: {}", e);
+        }
+    }
+}
+
+impl<W: Write> Predictor for DumpPredictions<W> {
+    fn predict_with_confidence(&mut self, addr: usize, was_taken: bool) -> (bool, bool) {
+        let (predicted, confident) = self.inner.predict_with_confidence(addr, was_taken);
+        self.record(predicted);
+
+        (predicted, confident)
+    }
+
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        self.inner.update(addr, predicted, was_taken);
+        self.record(predicted);
+    }
+
+    delegate_to_inner!();
+}
+
+/// Counts how often the wrapped predictor's prediction for a branch
+/// differs from its prediction the previous time the branch was seen,
+/// its churn, however accurate it is.
+pub struct Stability {
+    inner: Box<dyn Predictor>,
+    last: HashMap<usize, bool>,
+    flips: usize,
+    repeats: usize,
+}
+
+impl Stability {
+    pub fn new(inner: Box<dyn Predictor>) -> Stability {
+        Stability {
+            inner,
+            last: HashMap::new(),
+            flips: 0,
+            repeats: 0,
+        }
+    }
+
+    fn record(&mut self, addr: usize, predicted: bool) {
+        if let Some(last) = self.last.insert(addr, predicted) {
+            self.repeats += 1;
+            self.flips += (last != predicted) as usize;
+        }
+    }
+}
+
+impl Predictor for Stability {
+    fn predict_with_confidence(&mut self, addr: usize, was_taken: bool) -> (bool, bool) {
+        let (predicted, confident) = self.inner.predict_with_confidence(addr, was_taken);
+        self.record(addr, predicted);
+        (predicted, confident)
+    }
+
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        self.inner.update(addr, predicted, was_taken);
+        self.record(addr, predicted);
+    }
+
+    fn stability(&self) -> Option<(usize, usize)> {
+        Some((self.flips, self.repeats))
+    }
+
+    delegate_to_inner!(stability);
+}
+
+/// Writes a CSV time series of the wrapped predictor's predictions for
+/// a single branch, one row per occurrence:
+///
+///   occurrence,event,predicted,strength,taken
+///
+/// with `event` counting all the events the predictor has seen and
+/// `strength` as Boolish has it, 1 weak and 2 confident.
+pub struct TrackPc<W: Write> {
+    inner: Box<dyn Predictor>,
+    addr: usize,
+    out: W,
+    error: WriteError,
+    events: usize,
+    occurrences: usize,
+}
+
+impl<W: Write> TrackPc<W> {
+    /// Writes the header row straight away
+    pub fn new(
+        inner: Box<dyn Predictor>,
+        addr: usize,
+        mut out: W,
+        error: WriteError,
+    ) -> std::io::Result<TrackPc<W>> {
+        writeln!(out, "occurrence,event,predicted,strength,taken")?;
+        Ok(TrackPc {
+            inner,
+            addr,
+            out,
+            error,
+            events: 0,
+            occurrences: 0,
+        })
+    }
+
+    fn record(&mut self, addr: usize, (predicted, confident): (bool, bool), was_taken: bool) {
+        if addr == self.addr && self.error.borrow().is_none() {
+            if let Err(e) = writeln!(
+                self.out,
+                "{},{},{},{},{}",
+                self.occurrences,
+                self.events,
+                predicted as u8,
+                1 + confident as u8,
+                was_taken as u8
+            ) {
+                *self.error.borrow_mut() = Some(e);
+            }
+            self.occurrences += 1;
+        }
+        self.events += 1;
+    }
+}
+
+impl<W: Write> Drop for TrackPc<W> {
+    fn drop(&mut self) {
+        if self.error.borrow().is_some() {
+            return;
+        }
+        if let Err(e) = self.out.flush() {
+            eprintln!("failed to write the tracked branch: {}", e);
+        }
+    }
+}
+
+impl<W: Write> Predictor for TrackPc<W> {
+    fn predict_with_confidence(&mut self, addr: usize, was_taken: bool) -> (bool, bool) {
+        let prediction = self.inner.predict_with_confidence(addr, was_taken);
+        self.record(addr, prediction, was_taken);
+
+        prediction
+    }
+
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        let confident = self.inner.query(addr).1;
+        self.inner.update(addr, predicted, was_taken);
+        self.record(addr, (predicted, confident), was_taken);
+    }
+
+    delegate_to_inner!();
+}
+
+/// Answers not taken, without consulting the wrapped predictor, for
+/// branches that have been not taken `streak` times in a row.  The
+/// streaks are an idealized, unbounded table and aren't counted in the
+/// size.
+pub struct NotTakenFilter {
+    inner: Box<dyn Predictor>,
+    streak: usize,
+    streaks: HashMap<usize, usize>,
+}
+
+impl NotTakenFilter {
+    pub fn new(inner: Box<dyn Predictor>, streak: usize) -> NotTakenFilter {
+        NotTakenFilter {
+            inner,
+            streak,
+            streaks: HashMap::new(),
+        }
+    }
+
+    /// Whether `addr` is filtered this time, counting `was_taken` in
+    /// its streak
+    fn filtered(&mut self, addr: usize, was_taken: bool) -> bool {
+        let streak = self.streaks.entry(addr).or_insert(0);
+        let filtered = *streak >= self.streak;
+        *streak = if was_taken { 0 } else { *streak + 1 };
+        filtered
+    }
+}
+
+impl Predictor for NotTakenFilter {
+    fn predict_with_confidence(&mut self, addr: usize, was_taken: bool) -> (bool, bool) {
+        // Filtered branches don't touch the inner tables until they break the streak
+        if self.filtered(addr, was_taken) {
+            if was_taken {
+                self.inner.predict_and_update(addr, was_taken);
+            }
+            (false, true)
+        } else {
+            self.inner.predict_with_confidence(addr, was_taken)
+        }
+    }
+
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        // What was predicted was the filter's, the inner one predicts afresh
+        if self.filtered(addr, was_taken) {
+            if was_taken {
+                self.inner.predict_and_update(addr, was_taken);
+            }
+        } else {
+            self.inner.update(addr, predicted, was_taken);
+        }
+    }
+
+    fn query(&self, addr: usize) -> (bool, bool) {
+        if self.streaks.get(&addr).copied().unwrap_or(0) >= self.streak {
+            (false, true)
+        } else {
+            self.inner.query(addr)
+        }
+    }
+
+    fn predict_prob(&self, addr: usize) -> f64 {
+        if self.streaks.get(&addr).copied().unwrap_or(0) >= self.streak {
+            graded_probability((false, true))
+        } else {
+            self.inner.predict_prob(addr)
+        }
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let (alg, config, size) = self.inner.report();
+        (format!("{}[nt-filter={}]", alg, self.streak), config, size)
+    }
+
+    delegate_to_inner!(query, predict_prob, report);
+}
+
+/// Holds back each outcome for `delay` events before the wrapped
+/// predictor learns it, as a pipelined update does, so its predictions
+/// are made against tables, and history, that are `delay` outcomes
+/// stale, blind to those of a branch still in flight.  The update is
+/// applied with the history its prediction was made with, as a
+/// pipeline carries a branch's index and tags along with it, so it
+/// trains the counters that predicted.
+pub struct DelayedBp {
+    inner: Box<dyn Predictor>,
+    delay: usize,
+    // (addr, predicted, was_taken, history), oldest first
+    in_flight: VecDeque<(usize, bool, bool, HistorySnapshot)>,
+}
+
+impl DelayedBp {
+    pub fn new(inner: Box<dyn Predictor>, delay: usize) -> DelayedBp {
+        DelayedBp {
+            inner,
+            delay,
+            in_flight: VecDeque::with_capacity(delay + 1),
+        }
+    }
+}
+
+impl Predictor for DelayedBp {
+    fn update(&mut self, addr: usize, predicted: bool, was_taken: bool) {
+        let history = self.inner.history_snapshot();
+        self.in_flight
+            .push_back((addr, predicted, was_taken, history));
+        if self.in_flight.len() > self.delay {
+            let (addr, predicted, was_taken, history) = self.in_flight.pop_front().unwrap();
+            self.inner.pin_history(Some(&history));
+            self.inner.update(addr, predicted, was_taken);
+            self.inner.pin_history(None);
+        }
+    }
+
+    fn report(&self) -> (String, Vec<usize>, usize) {
+        let (alg, config, size) = self.inner.report();
+        (format!("{}[delay={}]", alg, self.delay), config, size)
+    }
+
+    delegate_to_inner!(report);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predictor::{GshareBp, LocalBp, YagsBp};
+
+    /// The misses of `p` behind a delay of `delay` over the second half
+    /// of `stream`, once it has had time to learn
+    fn late_misses(p: Box<dyn Predictor>, delay: usize, stream: &[(usize, bool)]) -> usize {
+        let mut delayed = DelayedBp::new(p, delay);
+        let predictions: Vec<bool> = stream
+            .iter()
+            .map(|&(addr, was_taken)| delayed.predict_and_update(addr, was_taken))
+            .collect();
+        let half = stream.len() / 2;
+        (half..stream.len())
+            .filter(|&i| predictions[i] != stream[i].1)
+            .count()
+    }
+
+    #[test]
+    fn delayed_updates_train_what_predicted() {
+        let alternating: Vec<(usize, bool)> = (0..2000).map(|i| (0x40, i % 2 == 0)).collect();
+        let loop3: Vec<(usize, bool)> = (0..3000).map(|i| (0x40, i % 3 != 2)).collect();
+        // Applied with the history of the moment they're applied
+        // instead, updates train other counters than predicted, and
+        // these are all missed
+        for delay in 0..=2 {
+            for stream in [&alternating, &loop3] {
+                assert_eq!(late_misses(Box::new(GshareBp::new(10)), delay, stream), 0);
+            }
+            let yags = YagsBp::yags3(10, 8, 6);
+            assert_eq!(late_misses(Box::new(yags), delay, &loop3), 0);
+        }
+        // Two stale, YAGS's choice flips in step with the alternation,
+        // so it's never still wrong after its update to allocate
+        for delay in 0..=1 {
+            let yags = YagsBp::yags3(10, 8, 6);
+            assert_eq!(late_misses(Box::new(yags), delay, &alternating), 0);
+        }
+    }
+
+    #[test]
+    fn forwards_what_it_leaves_alone() {
+        let stable = Stability::new(Box::new(LocalBp::new(4)));
+        let mut nested = DelayedBp::new(Box::new(NotTakenFilter::new(Box::new(stable), 3)), 1);
+        for i in 0..20 {
+            nested.predict_and_update(i * 2 % 12, i % 3 == 0);
+        }
+        let local = LocalBp::new(4);
+        assert_eq!(nested.params(), local.params());
+        assert_eq!(nested.report().0, "Two-level[nt-filter=3][delay=1]");
+        assert_eq!(nested.table_states().count(), 16);
+        assert!(nested.stability().is_some());
+        assert!(nested.footprint_bytes() > local.footprint_bytes());
+    }
+
+    #[test]
+    fn dumps_packed_predictions() {
+        let mut reference = LocalBp::new(4);
+        let mut expected = vec![];
+        let mut dumped = vec![];
+        {
+            let error = WriteError::default();
+            let mut dump = DumpPredictions::new(Box::new(LocalBp::new(4)), &mut dumped, error);
+            for i in 0..21 {
+                let (addr, was_taken) = (i * 2 % 10, i % 3 == 0);
+                expected.push(reference.predict_and_update(addr, was_taken));
+                assert_eq!(dump.predict_and_update(addr, was_taken), expected[i]);
+            }
+        }
+
+        assert_eq!(dumped.len(), 3);
+        for (i, &predicted) in expected.iter().enumerate() {
+            assert_eq!(dumped[i / 8] >> (i % 8) & 1 == 1, predicted);
+        }
+
+        // Room for a byte only, so the second fails and ends the dump
+        let mut full = [0; 1];
+        let error = WriteError::default();
+        {
+            let out = &mut full[..];
+            let mut dump = DumpPredictions::new(Box::new(LocalBp::new(4)), out, error.clone());
+            for i in 0..21 {
+                dump.predict_and_update(i * 2 % 10, i % 3 == 0);
+            }
+        }
+        let error = error.take().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn counts_prediction_flips() {
+        let mut stable = Stability::new(Box::new(LocalBp::new(4)));
+        assert_eq!(stable.stability(), Some((0, 0)));
+        // Predicted taken, not taken three times, then taken twice
+        for was_taken in [false, false, true, true, true, true] {
+            stable.predict_and_update(8, was_taken);
+        }
+        stable.predict_and_update(4, false);
+        assert_eq!(stable.stability(), Some((2, 5)));
+        assert_eq!(stable.report().0, "Two-level");
+        assert_eq!(LocalBp::new(4).stability(), None);
+    }
+
+    #[test]
+    fn tracks_one_branch() {
+        let mut csv = vec![];
+        {
+            let error = WriteError::default();
+            let mut track = TrackPc::new(Box::new(LocalBp::new(4)), 8, &mut csv, error).unwrap();
+            for (addr, was_taken) in [(8, false), (4, true), (8, false), (8, true)] {
+                track.predict_and_update(addr, was_taken);
+            }
+        }
+        // Weakly taken, then weakly and strongly not taken
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "occurrence,event,predicted,strength,taken\n\
+             0,0,1,1,0\n\
+             1,2,0,1,0\n\
+             2,3,0,2,1\n"
+        );
+
+        // Room for the header and a row, so the second row fails
+        let mut full = [0; 52];
+        let error = WriteError::default();
+        {
+            let out = &mut full[..];
+            let mut track = TrackPc::new(Box::new(LocalBp::new(4)), 8, out, error.clone()).unwrap();
+            for (addr, was_taken) in [(8, false), (4, true), (8, false), (8, true)] {
+                track.predict_and_update(addr, was_taken);
+            }
+        }
+        assert!(full.ends_with(b"0,0,1,1,0\n"));
+        let error = error.take().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn delays_updates() {
+        let mut delayed = DelayedBp::new(Box::new(LocalBp::new(4)), 2);
+        // The in flight not takens stay unseen until two events later
+        let predicted: Vec<bool> = (0..5)
+            .map(|_| delayed.predict_and_update(8, false))
+            .collect();
+        assert_eq!(predicted, [true, true, true, false, false]);
+        assert_eq!(delayed.report().0, "Two-level[delay=2]");
+
+        let stream: Vec<(usize, bool)> = (0..1000).map(|i| (i * 6 % 34, i % 5 < 3)).collect();
+        let mut undelayed = DelayedBp::new(Box::new(LocalBp::new(4)), 0);
+        let mut reference = LocalBp::new(4);
+        for &(addr, was_taken) in &stream {
+            assert_eq!(
+                undelayed.predict_with_confidence(addr, was_taken),
+                reference.predict_with_confidence(addr, was_taken)
+            );
+        }
+    }
+
+    #[test]
+    fn filters_not_taken_streaks() {
+        let mut filter = NotTakenFilter::new(Box::new(LocalBp::new(4)), 3);
+        // The inner counter starts weakly taken, so the first two miss
+        let outcomes = [false, false, false, false, false, true];
+        let predicted: Vec<bool> = outcomes
+            .iter()
+            .map(|&t| {
+                let queried = filter.predict(8);
+                assert_eq!(filter.predict_and_update(8, t), queried);
+                queried
+            })
+            .collect();
+        assert_eq!(predicted, [true, false, false, false, false, false]);
+        assert_eq!(filter.report().0, "Two-level[nt-filter=3]");
+
+        // The inner counter only saw the first three and the taken one
+        assert_eq!(filter.table_states().nth(4), Some(1));
+    }
+}